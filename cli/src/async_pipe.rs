@@ -5,6 +5,7 @@
 
 use crate::{constants::APPLICATION_NAME, util::errors::CodeError};
 use async_trait::async_trait;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::task::{Context, Poll};
@@ -34,6 +35,12 @@ cfg_if::cfg_if! {
 
 		pub struct AsyncPipeListener(tokio::net::UnixListener);
 
+		impl From<tokio::net::UnixListener> for AsyncPipeListener {
+			fn from(listener: tokio::net::UnixListener) -> Self {
+				AsyncPipeListener(listener)
+			}
+		}
+
 		impl AsyncPipeListener {
 			pub async fn accept(&mut self) -> Result<AsyncPipe, CodeError> {
 				self.0.accept().await.map_err(CodeError::AsyncPipeListenerFailed).map(|(s, _)| s)
@@ -238,9 +245,12 @@ pub fn get_socket_name() -> PathBuf {
 	}
 }
 
+/// Read and write halves of an accepted connection, and the address of the
+/// peer if it connected over the network.
 pub type AcceptedRW = (
 	Box<dyn AsyncRead + Send + Unpin>,
 	Box<dyn AsyncWrite + Send + Unpin>,
+	Option<SocketAddr>,
 );
 
 #[async_trait]
@@ -253,18 +263,18 @@ impl AsyncRWAccepter for AsyncPipeListener {
 	async fn accept_rw(&mut self) -> Result<AcceptedRW, CodeError> {
 		let pipe = self.accept().await?;
 		let (read, write) = socket_stream_split(pipe);
-		Ok((Box::new(read), Box::new(write)))
+		Ok((Box::new(read), Box::new(write), None))
 	}
 }
 
 #[async_trait]
 impl AsyncRWAccepter for TcpListener {
 	async fn accept_rw(&mut self) -> Result<AcceptedRW, CodeError> {
-		let (stream, _) = self
+		let (stream, addr) = self
 			.accept()
 			.await
 			.map_err(CodeError::AsyncPipeListenerFailed)?;
 		let (read, write) = tokio::io::split(stream);
-		Ok((Box::new(read), Box::new(write)))
+		Ok((Box::new(read), Box::new(write), Some(addr)))
 	}
 }
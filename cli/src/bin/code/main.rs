@@ -99,13 +99,19 @@ async fn main() -> Result<(), std::convert::Infallible> {
 				tunnels::command_shell(context!(), cs_args).await
 			}
 
-			Some(args::Commands::ServeWeb(sw_args)) => {
-				serve_web::serve_web(context!(), sw_args).await
-			}
+			Some(args::Commands::ServeWeb(sw_args)) => match sw_args.subcommand {
+				Some(args::ServeWebSubcommand::Service(service_args)) => {
+					serve_web::service(context!(), service_args).await
+				}
+				None => serve_web::serve_web(context!(), sw_args.serve_args).await,
+			},
 
 			Some(args::Commands::Tunnel(tunnel_args)) => match tunnel_args.subcommand {
 				Some(args::TunnelSubcommand::Prune) => tunnels::prune(context!()).await,
 				Some(args::TunnelSubcommand::Unregister) => tunnels::unregister(context!()).await,
+				Some(args::TunnelSubcommand::ImportServer(import_args)) => {
+					tunnels::import_server(context!(), import_args).await
+				}
 				Some(args::TunnelSubcommand::Kill) => tunnels::kill(context!()).await,
 				Some(args::TunnelSubcommand::Restart) => tunnels::restart(context!()).await,
 				Some(args::TunnelSubcommand::Status) => tunnels::status(context!()).await,
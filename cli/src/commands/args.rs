@@ -8,6 +8,7 @@ use std::{fmt, path::PathBuf};
 use crate::{constants, log, options, tunnels::code_server::CodeServerArgs};
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use const_format::concatcp;
+use serde::{Deserialize, Serialize};
 
 const CLI_NAME: &str = concatcp!(constants::PRODUCT_NAME_LONG, " CLI");
 const HELP_COMMANDS: &str = concatcp!(
@@ -180,7 +181,7 @@ pub enum Commands {
 
 	/// Runs a local web version of VS Code.
 	#[clap(about = concatcp!("Runs a local web version of ", constants::PRODUCT_NAME_LONG))]
-	ServeWeb(ServeWebArgs),
+	ServeWeb(Box<ServeWebCommandArgs>),
 
 	/// Runs the control server on process stdin/stdout
 	#[clap(hide = true)]
@@ -188,10 +189,47 @@ pub enum Commands {
 }
 
 #[derive(Args, Debug, Clone)]
+pub struct ServeWebCommandArgs {
+	#[clap(subcommand)]
+	pub subcommand: Option<ServeWebSubcommand>,
+
+	#[clap(flatten)]
+	pub serve_args: ServeWebArgs,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ServeWebSubcommand {
+	/// (Preview) Manages the web server as a service.
+	#[clap(subcommand)]
+	Service(ServeWebServiceSubCommands),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ServeWebServiceSubCommands {
+	/// Installs or re-installs the web server as a service with the given options.
+	Install(Box<ServeWebArgs>),
+
+	/// Uninstalls and stops the web server service.
+	Uninstall,
+
+	/// Shows logs for the running service.
+	Log,
+
+	/// Internal command for running the service
+	#[clap(hide = true)]
+	InternalRun,
+}
+
+// Options for serve-web. These are persisted when it's installed as a service,
+// so options missing from an older installation get their defaults.
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ServeWebArgs {
-	/// Host to listen on, defaults to 'localhost'
+	/// Host to listen on, defaults to 'localhost'. Can be given multiple times to
+	/// listen on several addresses, as `host`, `host:port`, or `unix:/path/to/socket`.
+	/// Sockets passed by systemd socket activation (`LISTEN_FDS`) are also used.
 	#[clap(long)]
-	pub host: Option<String>,
+	pub host: Vec<String>,
 	// The path to a socket file for the server to listen to.
 	#[clap(long)]
 	pub socket_path: Option<String>,
@@ -222,6 +260,134 @@ pub struct ServeWebArgs {
 	/// Set the root path for extensions.
 	#[clap(long)]
 	pub extensions_dir: Option<String>,
+	/// Path to a PEM-encoded certificate. If given along with `--cert-key-file`, the web UI is served over HTTPS.
+	#[clap(long, requires = "cert_key_file", conflicts_with = "socket_path")]
+	pub cert_file: Option<String>,
+	/// Path to the PEM-encoded private key for the certificate given in `--cert-file`. PKCS#1 and SEC1 keys are converted on Linux, elsewhere the key must be PKCS#8.
+	#[clap(long, requires = "cert_file")]
+	pub cert_key_file: Option<String>,
+	/// Serve the web UI over HTTPS using a self-signed certificate, which is created on first use and kept in the CLI data directory. Only supported on Linux.
+	#[clap(long, conflicts_with_all = ["cert_file", "socket_path"])]
+	pub self_signed_cert: bool,
+	/// Quality of the VS Code server to serve. Defaults to the quality of the CLI.
+	#[clap(long, value_enum)]
+	pub quality: Option<options::Quality>,
+	/// Always serve the server build with the given commit hash, instead of the latest release.
+	#[clap(long, conflicts_with = "server_version")]
+	pub commit_id: Option<String>,
+	/// Always serve the given release version of the server, such as `1.90.0`, instead of the latest release.
+	#[clap(long)]
+	pub server_version: Option<String>,
+	/// Never contact the update service. The most recently used server in the cache is served, unless a commit is given with `--commit-id`.
+	#[clap(long, conflicts_with = "server_version")]
+	pub offline: bool,
+	/// Path to a server archive (.tar.gz or .zip) to import into the cache before starting.
+	#[clap(long)]
+	pub server_archive: Option<String>,
+	/// Serve metrics in the Prometheus text format at `/metrics` under the base path. Requires the connection token.
+	#[clap(long)]
+	pub enable_metrics: bool,
+	/// Check for new releases at the given interval, in seconds, and download them before they're requested.
+	#[clap(long, value_name = "seconds", conflicts_with_all = ["commit_id", "server_version", "offline"])]
+	pub prefetch_interval: Option<u64>,
+	/// Also start releases once they're prefetched, so the first client to use them doesn't wait for startup.
+	/// Not supported with `--auth-config` or `--user-header`, since servers are then started per user.
+	#[clap(long, requires = "prefetch_interval", conflicts_with_all = ["auth_config", "user_header"])]
+	pub prefetch_warm: bool,
+	/// Shut down servers that have no clients after this many seconds. Defaults to one hour.
+	#[clap(long, value_name = "seconds")]
+	pub idle_timeout: Option<u64>,
+	/// Shut down servers that still have clients after this many seconds. Defaults to never.
+	#[clap(long, value_name = "seconds")]
+	pub active_timeout: Option<u64>,
+	/// When a newer release becomes the latest, shut down older servers once they have no clients, or after this many seconds otherwise, and then route clients to the latest release. Clients still connected then are disconnected and reload onto the latest release.
+	#[clap(long, value_name = "seconds")]
+	pub drain_grace_period: Option<u64>,
+	/// Write a log of HTTP requests to the given file, or to stdout if `-` is given.
+	#[clap(long, value_name = "path")]
+	pub access_log: Option<String>,
+	/// Format of entries in the access log.
+	#[clap(long, value_enum, default_value_t = AccessLogFormat::Combined)]
+	pub access_log_format: AccessLogFormat,
+	/// Rotate the access log file once it grows larger than this many megabytes.
+	#[clap(long, value_name = "MB", default_value_t = 100)]
+	pub access_log_max_size: u64,
+	/// Require users to sign in before using the web UI. Takes a JSON file listing users with
+	/// password hashes (`pbkdf2-sha256$<iterations>$<base64 salt>$<base64 hash>`) and/or an
+	/// OpenID Connect provider to sign in with.
+	#[clap(long, value_name = "path")]
+	pub auth_config: Option<String>,
+	/// Identify users by the given request header, which must be set by a trusted reverse proxy.
+	/// Only use this behind a proxy that strips the header from incoming requests, since anyone
+	/// who can reach the server directly can otherwise send it to act as any user. Refused when
+	/// listening on a non-loopback host without TLS or a connection token. Users signed in with
+	/// `--auth-config` are identified by their login instead. Each user gets their own servers,
+	/// user data, and extensions.
+	#[clap(long, value_name = "header")]
+	pub user_header: Option<String>,
+	/// Template for the user data directory of each identified user, where `{user}` is replaced
+	/// with the user's name, percent-encoded to be safe in paths. Defaults to a directory per user in
+	/// the CLI data directory.
+	#[clap(long, value_name = "path")]
+	pub user_data_dir_template: Option<String>,
+	/// Template for the extensions directory of each identified user, where `{user}` is replaced
+	/// with the user's name, percent-encoded to be safe in paths. Defaults to a directory per user in
+	/// the CLI data directory.
+	#[clap(long, value_name = "path")]
+	pub extensions_dir_template: Option<String>,
+	/// Pass an additional argument to every server that's started, such as `--server-arg=--log=trace`.
+	/// Can be given multiple times.
+	#[clap(long, value_name = "arg", allow_hyphen_values = true)]
+	pub server_arg: Vec<String>,
+	/// Set an environment variable, given as `KEY=VALUE`, for every server that's started. Can be given multiple times.
+	#[clap(long, value_name = "KEY=VALUE")]
+	pub server_env: Vec<String>,
+	/// Read additional server arguments, environment variables, and extensions to install from a JSON
+	/// file with `args`, `env`, and `installExtensions` properties. The file is read again whenever a
+	/// server is started.
+	#[clap(long, value_name = "path")]
+	pub server_config: Option<String>,
+	/// Install the given extension in every release before it's served. Can be given multiple times.
+	#[clap(long, value_name = "id")]
+	pub install_extension: Vec<String>,
+	/// Add or override a header, given as `Name: value`, on every response. Can be given multiple times.
+	#[clap(long, value_name = "header")]
+	pub response_header: Vec<String>,
+	/// Send a Strict-Transport-Security header with the given max-age in seconds. Only used with TLS.
+	#[clap(long, value_name = "seconds")]
+	pub hsts_max_age: Option<u64>,
+	/// Allow the web UI to be embedded by the given source, such as `https://portal.example.com`,
+	/// using the `frame-ancestors` content security policy. Can be given multiple times.
+	#[clap(long, value_name = "source")]
+	pub frame_ancestors: Vec<String>,
+	/// Allow cross-origin requests with credentials from the given origin. `*` allows any other
+	/// origin, but without credentials. Can be given multiple times.
+	#[clap(long, value_name = "origin")]
+	pub cors_allow_origin: Vec<String>,
+	/// Read additional response headers from a JSON file with `headers`, `hstsMaxAge`,
+	/// `frameAncestors`, and `corsAllowOrigins` properties.
+	#[clap(long, value_name = "path")]
+	pub headers_config: Option<String>,
+}
+
+impl Default for ServeWebArgs {
+	/// Gets the options used when no arguments are given, so options missing
+	/// from a persisted service config get the same defaults as on the CLI.
+	fn default() -> Self {
+		use clap::{Command, FromArgMatches};
+
+		let matches = Self::augment_args(Command::new("serve-web")).get_matches_from(["serve-web"]);
+		Self::from_arg_matches(&matches).expect("default arguments are valid")
+	}
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum AccessLogFormat {
+	/// The Apache/nginx "combined" log format, with additional fields appended.
+	#[default]
+	Combined,
+	/// One JSON object per line.
+	Json,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -673,6 +839,10 @@ pub struct TunnelServeArgs {
 	/// Set the root path for extensions.
 	#[clap(long)]
 	pub extensions_dir: Option<String>,
+
+	/// Serve metrics in the Prometheus text format on localhost at the given port, at `/metrics`.
+	#[clap(long)]
+	pub metrics_port: Option<u16>,
 }
 
 impl TunnelServeArgs {
@@ -719,6 +889,9 @@ pub enum TunnelSubcommand {
 	/// Remove this machine's association with the port forwarding service.
 	Unregister,
 
+	/// Imports a server archive from disk, for use on machines that cannot download servers.
+	ImportServer(TunnelImportServerArgs),
+
 	#[clap(subcommand)]
 	User(TunnelUserSubCommands),
 
@@ -764,6 +937,12 @@ pub struct TunnelRenameArgs {
 	pub name: String,
 }
 
+#[derive(Args, Debug, Clone)]
+pub struct TunnelImportServerArgs {
+	/// Path to the server archive (.tar.gz or .zip) to import.
+	pub archive: String,
+}
+
 #[derive(Args, Debug, Clone)]
 pub struct TunnelForwardArgs {
 	/// One or more ports to forward.
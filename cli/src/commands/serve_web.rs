@@ -3,36 +3,49 @@
  *  Licensed under the MIT License. See License.txt in the project root for license information.
  *--------------------------------------------------------------------------------------------*/
 
+mod access_log;
+mod auth;
+mod headers;
+mod listeners;
+mod pool;
+mod server_options;
+mod service;
+mod supervisor;
+mod tls;
+
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::fs;
 use std::io::{Read, Write};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+use hyper::body::HttpBody;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server};
+use serde::Serialize;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::pin;
 
-use crate::async_pipe::{
-	get_socket_name, get_socket_rw_stream, listen_socket_rw_stream, AsyncPipe,
-};
+use crate::async_pipe::{get_socket_name, get_socket_rw_stream, AsyncPipe};
 use crate::constants::VSCODE_CLI_QUALITY;
 use crate::download_cache::DownloadCache;
-use crate::log;
+use crate::log::{self, DownloadLogger};
 use crate::options::Quality;
 use crate::state::{LauncherPaths, PersistedState};
 use crate::tunnels::shutdown_signal::ShutdownRequest;
 use crate::update_service::{
-	unzip_downloaded_release, Platform, Release, TargetKind, UpdateService,
+	import_release_archive, unzip_downloaded_release, Platform, Release, ReleaseProduct,
+	TargetKind, UpdateService,
 };
+use crate::util::auth_limit::AuthLimiter;
 use crate::util::command::new_script_command;
 use crate::util::errors::AnyError;
 use crate::util::http::{self, ReqwestSimpleHttp};
-use crate::util::io::SilentCopyProgress;
+use crate::util::io::{ReportCopyProgress, SilentCopyProgress};
+use crate::util::metrics::{metrics_response, METRICS, METRICS_PATH};
 use crate::util::sync::{new_barrier, Barrier, BarrierOpener};
 use crate::{
 	tunnels::legal,
@@ -40,6 +53,14 @@ use crate::{
 };
 
 use super::{args::ServeWebArgs, CommandContext};
+use access_log::{strip_connection_token, AccessLog, AccessLogEntry};
+use auth::{AuthConfig, Authenticator};
+use headers::ResponseHeaders;
+use pool::ConnectionPool;
+use server_options::ServerOptions;
+use supervisor::{child_log_path, open_child_log, write_child_log, BadServer, CrashTracker};
+
+pub use service::service;
 
 /// Length of a commit hash, for validation
 const COMMIT_HASH_LEN: usize = 40;
@@ -51,15 +72,32 @@ const SERVER_IDLE_TIMEOUT_SECS: u64 = 60 * 60;
 const SERVER_ACTIVE_TIMEOUT_SECS: u64 = SERVER_IDLE_TIMEOUT_SECS * 24 * 30 * 12;
 /// How long to cache the "latest" version we get from the update service.
 const RELEASE_CACHE_SECS: u64 = 60 * 60;
+/// How long a failed download is reported to clients before it's retried.
+const FAILED_DOWNLOAD_TTL: Duration = Duration::from_secs(60);
 
 /// Number of bytes for the secret keys. See workbench.ts for their usage.
 const SECRET_KEY_BYTES: usize = 32;
 /// Path to mint the key combining server and client parts.
 const SECRET_KEY_MINT_PATH: &str = "_vscode-cli/mint-key";
+/// Path prefix of the event stream reporting progress of a server download.
+/// It's followed by `/<quality>-<commit>`.
+const DOWNLOAD_PROGRESS_PATH: &str = "_vscode-cli/download-progress";
+/// Path to get JSON status information about running servers.
+const STATUS_PATH: &str = "_vscode-cli/status";
+/// Cookie the VS Code server sets with the connection token.
+const CONNECTION_TOKEN_COOKIE_NAME: &str = "vscode-tkn";
+/// Query parameter used to pass the connection token.
+const CONNECTION_TOKEN_QUERY_NAME: &str = "tkn";
 /// Cookie set to the `SECRET_KEY_MINT_PATH`
 const PATH_COOKIE_NAME: &str = "vscode-secret-key-path";
 /// HTTP-only cookie where the client's secret half is stored.
 const SECRET_KEY_COOKIE_NAME: &str = "vscode-cli-secret-half";
+/// HTTP-only cookie with the ID of the user's session, when `--auth-config` is used.
+const SESSION_COOKIE_NAME: &str = "vscode-cli-session";
+/// HTTP-only cookie binding a pending OIDC login to the browser that started it.
+const OIDC_LOGIN_COOKIE_NAME: &str = "vscode-cli-oidc-login";
+/// Largest login form body that will be read.
+const MAX_LOGIN_FORM_BYTES: u64 = 16 * 1024;
 
 /// Implements the vscode "server of servers". Clients who go to the URI get
 /// served the latest version of the VS Code server whenever they load the
@@ -68,6 +106,15 @@ const SECRET_KEY_COOKIE_NAME: &str = "vscode-cli-secret-half";
 /// while new clients get new VS Code Server versions.
 pub async fn serve_web(ctx: CommandContext, mut args: ServeWebArgs) -> Result<i32, AnyError> {
 	legal::require_consent(&ctx.paths, args.accept_server_license_terms)?;
+	if let Some(commit) = &args.commit_id {
+		if !is_commit_hash(commit) {
+			return Err(CodeError::InvalidCommitHash(commit.clone()).into());
+		}
+	}
+	check_user_header(&args)?;
+
+	// checked up front, though it's read again for each server
+	ServerOptions::read(&args)?;
 
 	let platform: crate::update_service::Platform = PreReqChecker::new().verify().await?;
 	if !args.without_connection_token {
@@ -86,60 +133,94 @@ pub async fn serve_web(ctx: CommandContext, mut args: ServeWebArgs) -> Result<i3
 		}
 	}
 
+	if let Some(archive) = &args.server_archive {
+		let cache = DownloadCache::new(ctx.paths.web_server_storage());
+		let (product, dir) = import_release_archive(
+			Path::new(archive),
+			&cache,
+			None,
+			|p| p.commit.clone(),
+			ctx.log.get_download_logger("server inflate progress:"),
+		)
+		.await?;
+		info!(
+			ctx.log,
+			"Imported server {} into {}",
+			product.commit,
+			dir.display()
+		);
+	}
+
+	let tls = tls::get_tls_acceptor(&ctx.log, &ctx.paths, &args)?;
+	let response_headers = ResponseHeaders::read(&ctx.log, &args, tls.is_some())?.map(Arc::new);
 	let cm = ConnectionManager::new(&ctx, platform, args.clone());
+	if let Some(secs) = args.prefetch_interval {
+		tokio::spawn(
+			cm.clone()
+				.prefetch_releases(Duration::from_secs(secs), args.prefetch_warm),
+		);
+	}
 	let key = get_server_key_half(&ctx.paths);
-	let make_svc = move || {
+	let access_log = AccessLog::open(&args)?.map(Arc::new);
+	let auth = match &args.auth_config {
+		Some(path) => Some(Arc::new(Authenticator::new(
+			AuthConfig::read(path)?,
+			ctx.http.clone(),
+		))),
+		None => None,
+	};
+	let make_svc = move |remote_addr: Option<SocketAddr>| {
 		let ctx = HandleContext {
 			cm: cm.clone(),
 			log: cm.log.clone(),
 			server_secret_key: key.clone(),
+			access_log: access_log.clone(),
+			auth: auth.clone(),
+			response_headers: response_headers.clone(),
+			remote_addr,
 		};
 		let service = service_fn(move |req| handle(ctx.clone(), req));
 		async move { Ok::<_, Infallible>(service) }
 	};
 
-	let mut shutdown = ShutdownRequest::create_rx([ShutdownRequest::CtrlC]);
-	let r = if let Some(s) = args.socket_path {
-		let s = PathBuf::from(&s);
-		let socket = listen_socket_rw_stream(&s).await?;
-		ctx.log
-			.result(format!("Web UI available on {}", s.display()));
-		let r = Server::builder(socket.into_pollable())
-			.serve(make_service_fn(|_| make_svc()))
-			.with_graceful_shutdown(async {
-				let _ = shutdown.wait().await;
-			})
-			.await;
-		let _ = std::fs::remove_file(&s); // cleanup
-		r
-	} else {
-		let addr: SocketAddr = match &args.host {
-			Some(h) => {
-				SocketAddr::new(h.parse().map_err(CodeError::InvalidHostAddress)?, args.port)
-			}
-			None => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), args.port),
-		};
-		let builder = Server::try_bind(&addr).map_err(CodeError::CouldNotListenOnInterface)?;
-
-		let mut listening = format!("Web UI available at http://{}", addr);
-		if let Some(base) = args.server_base_path {
-			if !base.starts_with('/') {
-				listening.push('/');
-			}
-			listening.push_str(&base);
+	let listeners = listeners::bind_listeners(&ctx.log, &args).await?;
+	if let Err(e) = check_user_header_listeners(&args, &listeners) {
+		for file in listeners::socket_files(&listeners) {
+			let _ = fs::remove_file(file);
 		}
-		if let Some(ct) = args.connection_token {
-			listening.push_str(&format!("?tkn={}", ct));
+		return Err(e.into());
+	}
+	let mut path_and_query = String::new();
+	if let Some(base) = &args.server_base_path {
+		if !base.starts_with('/') {
+			path_and_query.push('/');
 		}
-		ctx.log.result(listening);
+		path_and_query.push_str(base);
+	}
+	if let Some(ct) = &args.connection_token {
+		path_and_query.push_str(&format!("?tkn={}", ct));
+	}
+	for listener in &listeners {
+		ctx.log.result(format!(
+			"Web UI available {}",
+			listener.describe(tls.is_some(), &path_and_query)
+		));
+	}
 
-		builder
-			.serve(make_service_fn(|_| make_svc()))
-			.with_graceful_shutdown(async {
-				let _ = shutdown.wait().await;
-			})
-			.await
-	};
+	let socket_files = listeners::socket_files(&listeners);
+	let mut shutdown = ShutdownRequest::create_rx([ShutdownRequest::CtrlC]);
+	let r = Server::builder(listeners::incoming(ctx.log.clone(), listeners, tls))
+		.serve(make_service_fn(|conn: &listeners::Connection| {
+			make_svc(conn.remote_addr())
+		}))
+		.with_graceful_shutdown(async {
+			let _ = shutdown.wait().await;
+		})
+		.await;
+
+	for path in socket_files {
+		let _ = std::fs::remove_file(path); // cleanup
+	}
 
 	r.map_err(CodeError::CouldNotListenOnInterface)?;
 
@@ -151,48 +232,232 @@ struct HandleContext {
 	cm: Arc<ConnectionManager>,
 	log: log::Logger,
 	server_secret_key: SecretKeyPart,
+	access_log: Option<Arc<AccessLog>>,
+	auth: Option<Arc<Authenticator>>,
+	response_headers: Option<Arc<ResponseHeaders>>,
+	remote_addr: Option<SocketAddr>,
 }
 
+/// Commit of the server release that handled a request, recorded as an
+/// extension on proxied responses for the access log.
+#[derive(Clone)]
+struct ServedCommit(String);
+
 /// Handler function for an inbound request
 async fn handle(ctx: HandleContext, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	let started_at = Instant::now();
+	let mut log_entry = ctx
+		.access_log
+		.as_ref()
+		.map(|_| new_access_log_entry(&ctx, &req));
 	let client_key_half = get_client_key_half(&req);
+	let origin = req.headers().get(hyper::header::ORIGIN).cloned();
 	let path = req.uri().path();
+	let cli_path = path.strip_prefix(ctx.cm.base_path.as_str());
+	let user = get_user(&ctx, &req);
+	if let Some(entry) = &mut log_entry {
+		entry.user = user.clone();
+	}
 
-	let mut res = if path.starts_with(&ctx.cm.base_path)
-		&& path.get(ctx.cm.base_path.len()..).unwrap_or_default() == SECRET_KEY_MINT_PATH
-	{
-		handle_secret_mint(&ctx, req)
-	} else {
-		handle_proxied(&ctx, req).await
+	let mut res = match cli_path {
+		_ if ctx
+			.response_headers
+			.as_ref()
+			.map(|h| h.is_preflight(&req))
+			.unwrap_or(false) =>
+		{
+			// answered before auth, since browsers don't send credentials with preflights
+			ctx.response_headers.as_ref().unwrap().preflight_response()
+		}
+		Some(p) if ctx.auth.is_some() && auth::is_auth_path(p) => handle_auth(&ctx, req).await,
+		_ if ctx.auth.is_some() && user.is_none() => {
+			if req.headers().contains_key(hyper::header::UPGRADE) {
+				response::login_required()
+			} else {
+				response::redirect_to_login(&ctx.cm.base_path, &req)
+			}
+		}
+		Some(SECRET_KEY_MINT_PATH) => handle_secret_mint(&ctx, req),
+		Some(STATUS_PATH) => handle_status(&ctx, req).await,
+		Some(p) if p.starts_with(DOWNLOAD_PROGRESS_PATH) => handle_download_progress(&ctx, req),
+		Some(p) if ctx.cm.args.enable_metrics && p == &METRICS_PATH[1..] => {
+			handle_metrics(&ctx, req)
+		}
+		_ => {
+			let res = handle_proxied(&ctx, req, user).await;
+			METRICS
+				.http_requests
+				.inc_by(&[("status", res.status().as_str())], 1);
+			METRICS.http_request_duration.observe(started_at.elapsed());
+			res
+		}
 	};
 
 	append_secret_headers(&ctx.cm.base_path, &mut res, &client_key_half);
+	if let Some(headers) = &ctx.response_headers {
+		headers.apply(origin.as_ref(), &mut res);
+	}
+
+	if let (Some(access_log), Some(entry)) = (&ctx.access_log, &mut log_entry) {
+		entry.status = res.status().as_u16();
+		entry.bytes = res.body().size_hint().exact();
+		entry.commit = res.extensions().get::<ServedCommit>().map(|c| c.0.clone());
+		entry.set_duration(started_at.elapsed());
+		access_log.write(entry);
+	}
 
 	Ok(res)
 }
 
-async fn handle_proxied(ctx: &HandleContext, req: Request<Body>) -> Response<Body> {
+/// Creates an access log entry for the request, filled in once it's handled.
+fn new_access_log_entry(ctx: &HandleContext, req: &Request<Body>) -> AccessLogEntry {
+	let header = |name: hyper::header::HeaderName| {
+		req.headers()
+			.get(name)
+			.and_then(|v| v.to_str().ok())
+			.map(|v| v.to_string())
+	};
+
+	AccessLogEntry {
+		time: chrono::Local::now(),
+		remote_addr: ctx.remote_addr,
+		user: None,
+		method: req.method().to_string(),
+		path: strip_connection_token(
+			req.uri()
+				.path_and_query()
+				.map(|p| p.as_str())
+				.unwrap_or("/"),
+			CONNECTION_TOKEN_QUERY_NAME,
+		),
+		version: format!("{:?}", req.version()),
+		status: 0,
+		bytes: None,
+		duration_ms: 0,
+		commit: None,
+		websocket: req.headers().contains_key(hyper::header::UPGRADE),
+		referer: header(hyper::header::REFERER),
+		user_agent: header(hyper::header::USER_AGENT),
+	}
+}
+
+/// Refuses `--user-header` if clients can reach the server directly without
+/// TLS or a connection token, since they could then set the header
+/// themselves rather than it being set by a trusted proxy. Only the `--host`
+/// values are checked, see `check_user_header_listeners` for the sockets
+/// that are actually listened on.
+fn check_user_header(args: &ServeWebArgs) -> Result<(), CodeError> {
+	if !user_header_needs_loopback(args) {
+		return Ok(());
+	}
+
+	match args.host.iter().find(|h| !listeners::is_loopback_host(h)) {
+		Some(host) => Err(CodeError::UnsafeUserHeader(host.clone())),
+		None => Ok(()),
+	}
+}
+
+/// Like `check_user_header`, but checks the addresses the listeners are bound
+/// to, including sockets passed by the service manager.
+fn check_user_header_listeners(
+	args: &ServeWebArgs,
+	listeners: &[listeners::Listener],
+) -> Result<(), CodeError> {
+	if !user_header_needs_loopback(args) {
+		return Ok(());
+	}
+
+	match listeners.iter().find(|l| !l.is_loopback()) {
+		Some(listeners::Listener::Tcp(l)) => Err(CodeError::UnsafeUserHeader(
+			l.local_addr()
+				.map(|a| a.to_string())
+				.unwrap_or_else(|_| "<unknown>".to_string()),
+		)),
+		_ => Ok(()),
+	}
+}
+
+/// Gets whether `--user-header` is only safe to use on loopback addresses,
+/// since nothing else keeps clients from setting the header themselves.
+fn user_header_needs_loopback(args: &ServeWebArgs) -> bool {
+	args.user_header.is_some()
+		&& args.auth_config.is_none()
+		&& args.without_connection_token
+		&& args.cert_file.is_none()
+		&& !args.self_signed_cert
+}
+
+/// Gets the user making the request, from their login session if
+/// `--auth-config` is used, or otherwise from the `--user-header`.
+fn get_user(ctx: &HandleContext, req: &Request<Body>) -> Option<String> {
+	if let Some(auth) = &ctx.auth {
+		return extract_cookie(req, SESSION_COOKIE_NAME).and_then(|s| auth.get_session_user(&s));
+	}
+
+	let header = ctx.cm.args.user_header.as_deref()?;
+	req.headers()
+		.get(header)
+		.and_then(|v| v.to_str().ok())
+		.map(|v| v.trim())
+		.filter(|v| !v.is_empty())
+		.map(|v| v.to_string())
+}
+
+async fn handle_proxied(
+	ctx: &HandleContext,
+	req: Request<Body>,
+	user: Option<String>,
+) -> Response<Body> {
+	if let Some(res) = check_proxied_token(ctx, &req) {
+		return res;
+	}
+
 	let release = if let Some((r, _)) = get_release_from_path(req.uri().path(), ctx.cm.platform) {
 		r
 	} else {
-		match ctx.cm.get_latest_release().await {
+		match ctx.cm.get_default_release(user.as_deref()).await {
 			Ok(r) => r,
 			Err(e) => {
-				error!(ctx.log, "error getting latest version: {}", e);
+				error!(ctx.log, "error getting release to serve: {}", e);
 				return response::code_err(e);
 			}
 		}
 	};
 
-	match ctx.cm.get_connection(release).await {
-		Ok(rw) => {
-			if req.headers().contains_key(hyper::header::UPGRADE) {
-				forward_ws_req_to_server(ctx.log.clone(), rw, req).await
-			} else {
-				forward_http_req_to_server(rw, req).await
-			}
+	let commit = ServedCommit(release.commit.clone());
+	let mut res = if ctx.cm.is_drained(&release) {
+		response::version_drained()
+	} else {
+		forward_to_release(ctx, release, req, user).await
+	};
+	res.extensions_mut().insert(commit);
+	res
+}
+
+async fn forward_to_release(
+	ctx: &HandleContext,
+	release: Release,
+	req: Request<Body>,
+	user: Option<String>,
+) -> Response<Body> {
+	let key = key_for_release(&release);
+	let result = if req.headers().contains_key(hyper::header::UPGRADE) {
+		match ctx.cm.get_connection(release, user).await {
+			Ok(rw) => Ok(forward_ws_req_to_server(ctx.log.clone(), rw, req).await),
+			Err(e) => Err(e),
+		}
+	} else {
+		match ctx.cm.get_server(release, user).await {
+			Ok(server) => Ok(forward_http_req_to_server(&ctx.cm.pool, server, req).await),
+			Err(e) => Err(e),
+		}
+	};
+
+	match result {
+		Ok(res) => res,
+		Err(CodeError::ServerNotYetDownloaded) => {
+			response::wait_for_download(&ctx.cm.base_path, &key)
 		}
-		Err(CodeError::ServerNotYetDownloaded) => response::wait_for_download(),
 		Err(e) => response::code_err(e),
 	}
 }
@@ -208,6 +473,310 @@ fn handle_secret_mint(ctx: &HandleContext, req: Request<Body>) -> Response<Body>
 	response::secret_key(hash)
 }
 
+async fn handle_status(ctx: &HandleContext, req: Request<Body>) -> Response<Body> {
+	if let Some(res) = check_connection_token(ctx, &req) {
+		return res;
+	}
+
+	response::json(&ctx.cm.get_status().await)
+}
+
+/// Handles the login page, the OIDC flow, and logging out.
+async fn handle_auth(ctx: &HandleContext, req: Request<Body>) -> Response<Body> {
+	let auth = match &ctx.auth {
+		Some(a) => a,
+		None => return response::not_found(),
+	};
+
+	let base_path = &ctx.cm.base_path;
+	let path = &req.uri().path()[base_path.len()..];
+	let query: HashMap<String, String> = req
+		.uri()
+		.query()
+		.map(|q| {
+			url::form_urlencoded::parse(q.as_bytes())
+				.into_owned()
+				.collect()
+		})
+		.unwrap_or_default();
+	let return_to = auth::safe_return_to(query.get("return_to").map(|s| s.as_str()), base_path);
+
+	match path {
+		auth::LOGIN_PATH if req.method() == hyper::Method::POST => {
+			let form = match read_form(req).await {
+				Some(f) => f,
+				None => return response::bad_request("Invalid login form"),
+			};
+			let addr = ctx.remote_addr.map(|a| a.ip());
+			if let Some(wait) = ctx.cm.auth_limiter.locked_out_for(addr) {
+				return response::too_many_attempts(wait);
+			}
+
+			let user = form.get("user").map(|s| s.as_str()).unwrap_or_default();
+			let return_to =
+				auth::safe_return_to(form.get("return_to").map(|s| s.as_str()), base_path);
+			if auth.has_password_login()
+				&& auth.check_password(
+					user,
+					form.get("password").map(|s| s.as_str()).unwrap_or_default(),
+				) {
+				info!(ctx.log, "User {} signed in with a password", user);
+				ctx.cm.auth_limiter.record_success(addr);
+				login_succeeded(ctx, auth, user.to_string(), &return_to)
+			} else {
+				record_auth_failure(ctx, &format!("password for user {}", user));
+				response::login_page(
+					base_path,
+					&return_to,
+					auth,
+					Some("The user name or password is incorrect."),
+				)
+			}
+		}
+		auth::LOGIN_PATH => response::login_page(base_path, &return_to, auth, None),
+		auth::OIDC_LOGIN_PATH => match auth.start_oidc_login(return_to).await {
+			Ok((url, binding)) => {
+				let mut res = response::redirect(&url);
+				append_cookie(
+					&mut res,
+					OIDC_LOGIN_COOKIE_NAME,
+					&binding,
+					auth::PENDING_LOGIN_TTL,
+				);
+				res
+			}
+			Err(e) => {
+				error!(ctx.log, "error starting OIDC sign-in: {}", e);
+				response::bad_gateway("Could not reach the sign-in provider")
+			}
+		},
+		auth::OIDC_CALLBACK_PATH => {
+			let result = match (query.get("code"), query.get("state")) {
+				(Some(code), Some(state)) if is_oidc_login_bound(&req, state) => {
+					auth.finish_oidc_login(code, state).await
+				}
+				(Some(_), Some(_)) => Err(CodeError::OidcLoginFailed(
+					"the callback is for a sign-in started elsewhere".to_string(),
+				)
+				.into()),
+				_ => Err(CodeError::OidcLoginFailed(
+					query
+						.get("error")
+						.cloned()
+						.unwrap_or_else(|| "missing code".to_string()),
+				)
+				.into()),
+			};
+
+			let mut res = match result {
+				Ok((user, return_to)) => {
+					info!(ctx.log, "User {} signed in with OIDC", user);
+					login_succeeded(ctx, auth, user, &return_to)
+				}
+				Err(e) => {
+					warning!(ctx.log, "Failed OIDC sign-in: {}", e);
+					response::login_page(
+						base_path,
+						base_path,
+						auth,
+						Some("Sign-in failed, please try again."),
+					)
+				}
+			};
+			append_cookie(&mut res, OIDC_LOGIN_COOKIE_NAME, "", Duration::ZERO);
+			res
+		}
+		auth::LOGOUT_PATH => {
+			if let Some(session) = extract_cookie(&req, SESSION_COOKIE_NAME) {
+				auth.end_session(&session);
+			}
+			let mut res = response::redirect(&format!("{}{}", base_path, auth::LOGIN_PATH));
+			append_session_cookie(&mut res, "", Duration::ZERO);
+			res
+		}
+		_ => response::not_found(),
+	}
+}
+
+/// Starts a session for a user who signed in, and redirects them onwards.
+fn login_succeeded(
+	ctx: &HandleContext,
+	auth: &Authenticator,
+	user: String,
+	return_to: &str,
+) -> Response<Body> {
+	let session = auth.create_session(user);
+	let mut res = response::redirect(return_to);
+	append_session_cookie(&mut res, &session, auth.session_ttl());
+
+	// The server checks the connection token on its own, so let signed-in
+	// users through without needing to have it in the URL.
+	if let Some(token) = &ctx.cm.args.connection_token {
+		res.headers_mut().append(
+			hyper::header::SET_COOKIE,
+			format!(
+				"{}={}; SameSite=Lax; Path=/",
+				CONNECTION_TOKEN_COOKIE_NAME, token
+			)
+			.parse()
+			.unwrap(),
+		);
+	}
+
+	res
+}
+
+/// Reads a URL-encoded form from the request body.
+async fn read_form(req: Request<Body>) -> Option<HashMap<String, String>> {
+	if req.body().size_hint().upper().unwrap_or(u64::MAX) > MAX_LOGIN_FORM_BYTES {
+		return None;
+	}
+
+	let body = hyper::body::to_bytes(req.into_body()).await.ok()?;
+	Some(url::form_urlencoded::parse(&body).into_owned().collect())
+}
+
+/// Streams the progress of a server download as Server-Sent Events, used by
+/// the page shown while waiting for a download.
+fn handle_download_progress(ctx: &HandleContext, req: Request<Body>) -> Response<Body> {
+	if let Some(res) = check_connection_token(ctx, &req) {
+		return res;
+	}
+
+	let path = req.uri().path();
+	let release_path = &path[ctx.cm.base_path.len() + DOWNLOAD_PROGRESS_PATH.len()..];
+	let progress = get_release_from_path(release_path, ctx.cm.platform)
+		.and_then(|(release, _)| ctx.cm.get_download_progress(&release));
+
+	match progress {
+		Some(rx) => response::progress_events(rx),
+		// no download in progress, so the page can reload to connect to the server
+		None => response::progress_events(tokio::sync::watch::channel(DownloadProgress::Done).1),
+	}
+}
+
+fn handle_metrics(ctx: &HandleContext, req: Request<Body>) -> Response<Body> {
+	if let Some(res) = check_connection_token(ctx, &req) {
+		return res;
+	}
+
+	metrics_response()
+}
+
+#[derive(PartialEq, Eq)]
+enum TokenStatus {
+	/// The token is valid, or none is required.
+	Valid,
+	/// A token was given, but it's not the right one.
+	Invalid,
+	/// No token was given.
+	Missing,
+}
+
+/// Checks whether the request includes the connection token, either in the
+/// query string, the cookie the server sets, or an `Authorization: Bearer`
+/// header.
+fn get_token_status(req: &Request<Body>, token: Option<&str>) -> TokenStatus {
+	let token = match token {
+		Some(t) => t,
+		None => return TokenStatus::Valid,
+	};
+
+	let from_query = req.uri().query().and_then(|q| {
+		url::form_urlencoded::parse(q.as_bytes())
+			.find(|(k, _)| k == CONNECTION_TOKEN_QUERY_NAME)
+			.map(|(_, v)| v.into_owned())
+	});
+	let from_header = req
+		.headers()
+		.get(hyper::header::AUTHORIZATION)
+		.and_then(|h| h.to_str().ok())
+		.and_then(|h| h.strip_prefix("Bearer "))
+		.map(|h| h.to_string());
+
+	let given: Vec<String> = [
+		from_query,
+		extract_cookie(req, CONNECTION_TOKEN_COOKIE_NAME),
+		from_header,
+	]
+	.into_iter()
+	.flatten()
+	.collect();
+
+	if given.is_empty() {
+		TokenStatus::Missing
+	} else if given
+		.iter()
+		.any(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+	{
+		TokenStatus::Valid
+	} else {
+		TokenStatus::Invalid
+	}
+}
+
+/// Checks the connection token of requests to the CLI's own endpoints, which
+/// always require it, returning the response to reject the request with if it
+/// fails. Failed attempts are limited per remote address, and requests are
+/// refused while the address is locked out, whatever their token, so the
+/// lockout doesn't keep telling a guesser which tokens are right.
+fn check_connection_token(ctx: &HandleContext, req: &Request<Body>) -> Option<Response<Body>> {
+	check_token_with_limit(ctx, req, true)
+}
+
+/// Checks the connection token given by requests that are proxied to a server.
+/// Requests without a token are left to the server to reject, but a wrong
+/// token counts as a failed attempt.
+fn check_proxied_token(ctx: &HandleContext, req: &Request<Body>) -> Option<Response<Body>> {
+	check_token_with_limit(ctx, req, false)
+}
+
+fn check_token_with_limit(
+	ctx: &HandleContext,
+	req: &Request<Body>,
+	required: bool,
+) -> Option<Response<Body>> {
+	let addr = ctx.remote_addr.map(|a| a.ip());
+	if let Some(wait) = ctx.cm.auth_limiter.locked_out_for(addr) {
+		return Some(response::too_many_attempts(wait));
+	}
+
+	match get_token_status(req, ctx.cm.args.connection_token.as_deref()) {
+		TokenStatus::Valid => None,
+		TokenStatus::Missing if !required => None,
+		_ => match record_auth_failure(ctx, "connection token") {
+			Some(wait) => Some(response::too_many_attempts(wait)),
+			None => Some(response::unauthorized()),
+		},
+	}
+}
+
+/// Records and logs a failed authentication attempt from the request's peer,
+/// returning the time the peer is now locked out for, if any.
+fn record_auth_failure(ctx: &HandleContext, what: &str) -> Option<Duration> {
+	METRICS.auth_failures.inc_by(&[("kind", "serve-web")], 1);
+	let lockout = ctx
+		.cm
+		.auth_limiter
+		.record_failure(ctx.remote_addr.map(|a| a.ip()));
+	warning!(
+		ctx.log,
+		"Rejected invalid {} from {}{}",
+		what,
+		ctx.remote_addr
+			.map(|a| a.to_string())
+			.unwrap_or_else(|| "local socket".to_string()),
+		lockout
+			.map(|d| format!(", locked out for {}s", d.as_secs()))
+			.unwrap_or_default()
+	);
+	lockout
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 /// Appends headers to response to maintain the secret storage of the workbench:
 /// sets the `PATH_COOKIE_VALUE` so workbench.ts knows about the 'mint' endpoint,
 /// and maintains the http-only cookie the client will use for cookies.
@@ -238,6 +807,36 @@ fn append_secret_headers(
 	);
 }
 
+/// Sets or, given an empty session and duration, clears the session cookie.
+fn append_session_cookie(res: &mut Response<Body>, session: &str, max_age: Duration) {
+	append_cookie(res, SESSION_COOKIE_NAME, session, max_age);
+}
+
+/// Sets an HTTP-only cookie, or clears it with a zero `max_age`. Being `Lax`,
+/// it's still sent on top-level navigations from other sites, such as the
+/// redirect back from an OIDC provider.
+fn append_cookie(res: &mut Response<Body>, name: &str, value: &str, max_age: Duration) {
+	res.headers_mut().append(
+		hyper::header::SET_COOKIE,
+		format!(
+			"{}={}; SameSite=Lax; HttpOnly; Max-Age={}; Path=/",
+			name,
+			value,
+			max_age.as_secs()
+		)
+		.parse()
+		.unwrap(),
+	);
+}
+
+/// Gets whether an OIDC callback comes from the browser that started the
+/// login with the `state`.
+fn is_oidc_login_bound(req: &Request<Body>, state: &str) -> bool {
+	extract_cookie(req, OIDC_LOGIN_COOKIE_NAME)
+		.map(|b| constant_time_eq(b.as_bytes(), auth::oidc_state_binding(state).as_bytes()))
+		.unwrap_or(false)
+}
+
 /// Gets the release info from the VS Code path prefix, which is in the
 /// format `/<quality>-<commit>/...`
 fn get_release_from_path(path: &str, platform: Platform) -> Option<(Release, String)> {
@@ -269,23 +868,32 @@ fn get_release_from_path(path: &str, platform: Platform) -> Option<(Release, Str
 	))
 }
 
-/// Proxies the standard HTTP request to the async pipe, returning the piped response
+/// Proxies the HTTP request to the server, reusing a pooled connection to it
+/// if there's one that's ready. If the pooled connection fails before there's
+/// a response, such as when the server closed it, requests that are safe to
+/// send again are retried once on a new connection.
 async fn forward_http_req_to_server(
-	(rw, handle): (AsyncPipe, ConnectionHandle),
+	pool: &ConnectionPool,
+	(socket_path, handle): (PathBuf, ConnectionHandle),
 	req: Request<Body>,
 ) -> Response<Body> {
-	let (mut request_sender, connection) =
-		match hyper::client::conn::Builder::new().handshake(rw).await {
-			Ok(r) => r,
-			Err(e) => return response::connection_err(e),
-		};
-
-	tokio::spawn(connection);
-
-	let res = request_sender
-		.send_request(req)
-		.await
-		.unwrap_or_else(response::connection_err);
+	let res = match pool.take(&socket_path) {
+		Some(sender) => {
+			let retry = copy_for_retry(&req);
+			match send_request(pool, &socket_path, sender, req).await {
+				Ok(res) => res,
+				Err(e) => match retry {
+					Some(req) => {
+						// other pooled connections were likely closed too
+						pool.evict(&socket_path);
+						send_on_new_connection(pool, &socket_path, req).await
+					}
+					None => response::connection_err(e),
+				},
+			}
+		}
+		None => send_on_new_connection(pool, &socket_path, req).await,
+	};
 
 	// technically, we should buffer the body into memory since it may not be
 	// read at this point, but because the keepalive time is very large
@@ -296,6 +904,61 @@ async fn forward_http_req_to_server(
 	res
 }
 
+/// Sends the request on a new connection to the server.
+async fn send_on_new_connection(
+	pool: &ConnectionPool,
+	socket_path: &Path,
+	req: Request<Body>,
+) -> Response<Body> {
+	let rw = match get_socket_rw_stream(socket_path).await {
+		Ok(rw) => rw,
+		Err(e) => return response::code_err(e),
+	};
+	let (request_sender, connection) = match hyper::client::conn::Builder::new().handshake(rw).await
+	{
+		Ok(r) => r,
+		Err(e) => return response::connection_err(e),
+	};
+
+	tokio::spawn(connection);
+	send_request(pool, socket_path, request_sender, req)
+		.await
+		.unwrap_or_else(response::connection_err)
+}
+
+/// Sends the request, returning the connection to the pool once it's sent.
+async fn send_request(
+	pool: &ConnectionPool,
+	socket_path: &Path,
+	mut request_sender: hyper::client::conn::SendRequest<Body>,
+	req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+	let res = request_sender.send_request(req).await?;
+	pool.put(socket_path, request_sender);
+	Ok(res)
+}
+
+/// Copies a request so it can be sent again, if it has an idempotent method
+/// and no body.
+fn copy_for_retry(req: &Request<Body>) -> Option<Request<Body>> {
+	use hyper::Method;
+
+	let idempotent = matches!(
+		*req.method(),
+		Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE
+	);
+	if !idempotent || req.body().size_hint().exact() != Some(0) {
+		return None;
+	}
+
+	let mut copy = Request::new(Body::empty());
+	*copy.method_mut() = req.method().clone();
+	*copy.uri_mut() = req.uri().clone();
+	*copy.version_mut() = req.version();
+	*copy.headers_mut() = req.headers().clone();
+	Some(copy)
+}
+
 /// Proxies the websocket request to the async pipe
 async fn forward_ws_req_to_server(
 	log: log::Logger,
@@ -342,7 +1005,9 @@ async fn forward_ws_req_to_server(
 				(_, Err(e2)) => debug!(log, "server ({}) websocket upgrade failed", e2),
 				(Ok(mut s_req), Ok(mut s_res)) => {
 					trace!(log, "websocket upgrade succeeded");
+					METRICS.websockets_active.inc();
 					let r = tokio::io::copy_bidirectional(&mut s_req, &mut s_res).await;
+					METRICS.websockets_active.dec();
 					trace!(log, "websocket closed (error: {:?})", r.err());
 				}
 			}
@@ -354,6 +1019,22 @@ async fn forward_ws_req_to_server(
 	proxied_res
 }
 
+/// Gets the quality to serve, which is the one requested in the arguments or
+/// otherwise the quality of the CLI.
+fn resolve_quality(
+	requested: Option<Quality>,
+	cli_quality: Option<&str>,
+) -> Result<Quality, CodeError> {
+	match requested {
+		Some(q) => Ok(q),
+		None => cli_quality
+			.ok_or(CodeError::UpdatesNotConfigured("no configured quality"))
+			.and_then(|q| {
+				Quality::try_from(q).map_err(|_| CodeError::UpdatesNotConfigured("unknown quality"))
+			}),
+	}
+}
+
 /// Returns whether the string looks like a commit hash.
 fn is_commit_hash(s: &str) -> bool {
 	s.len() == COMMIT_HASH_LEN && s.chars().all(|c| c.is_ascii_hexdigit())
@@ -438,6 +1119,42 @@ mod response {
 
 	use super::*;
 
+	/// Replaced with the JSON-encoded URL of the download progress events.
+	const PROGRESS_URL_PLACEHOLDER: &str = "$PROGRESS_URL";
+
+	/// Page shown while a server downloads, which shows progress from the
+	/// `DOWNLOAD_PROGRESS_PATH` and reloads once the download is done.
+	const WAIT_FOR_DOWNLOAD_PAGE: &str = concatcp!(
+		"<p id=\"status\">The latest version of the ",
+		QUALITYLESS_SERVER_NAME,
+		" is downloading, please wait a moment...</p>",
+		"<script>{",
+		"const status = document.getElementById('status');",
+		"const events = new EventSource(",
+		PROGRESS_URL_PLACEHOLDER,
+		" + location.search);",
+		"events.onmessage = e => {",
+		"const p = JSON.parse(e.data);",
+		"if (p.state === 'downloading') {",
+		"if (p.totalBytes) status.textContent = `Downloading the ",
+		QUALITYLESS_SERVER_NAME,
+		": ${Math.floor(p.bytesSoFar / p.totalBytes * 100)}%`;",
+		"} else if (p.state === 'unpacking') {",
+		"status.textContent = 'Unpacking the ",
+		QUALITYLESS_SERVER_NAME,
+		"...';",
+		"} else if (p.state === 'failed') {",
+		"events.close();",
+		"status.textContent = `Error downloading the server, reload the page in a minute to try again: ${p.error}`;",
+		"} else {",
+		"events.close();",
+		"location.reload();",
+		"}",
+		"};",
+		"events.onerror = () => { events.close(); setTimeout(() => location.reload(), 1500); };",
+		"}</script>",
+	);
+
 	pub fn connection_err(err: hyper::Error) -> Response<Body> {
 		Response::builder()
 			.status(503)
@@ -452,55 +1169,366 @@ mod response {
 			.unwrap()
 	}
 
-	pub fn wait_for_download() -> Response<Body> {
+	pub fn version_drained() -> Response<Body> {
 		Response::builder()
-			.status(202)
-			.header("Content-Type", "text/html") // todo: get latest
-			.body(Body::from(concatcp!("The latest version of the ", QUALITYLESS_SERVER_NAME, " is downloading, please wait a moment...<script>setTimeout(()=>location.reload(),1500)</script>", )))
+			.status(410)
+			.body(Body::from(concatcp!(
+				"This version of the ",
+				QUALITYLESS_SERVER_NAME,
+				" was shut down, reload the page to use the latest version."
+			)))
 			.unwrap()
 	}
 
-	pub fn secret_key(hash: Vec<u8>) -> Response<Body> {
+	pub fn unauthorized() -> Response<Body> {
 		Response::builder()
-			.status(200)
-			.header("Content-Type", "application/octet-stream") // todo: get latest
-			.body(Body::from(hash))
+			.status(401)
+			.body(Body::from("A valid connection token is required"))
 			.unwrap()
 	}
-}
 
-/// Handle returned when getting a stream to the server, used to refcount
-/// connections to a server so it can be disposed when there are no more clients.
-struct ConnectionHandle {
-	client_counter: Arc<tokio::sync::watch::Sender<usize>>,
-}
+	pub fn too_many_attempts(wait: Duration) -> Response<Body> {
+		let secs = wait.as_secs().max(1);
+		Response::builder()
+			.status(429)
+			.header(hyper::header::RETRY_AFTER, secs)
+			.body(Body::from(format!(
+				"Too many failed attempts, try again in {} seconds",
+				secs
+			)))
+			.unwrap()
+	}
 
-impl ConnectionHandle {
-	pub fn new(client_counter: Arc<tokio::sync::watch::Sender<usize>>) -> Self {
-		client_counter.send_modify(|v| {
-			*v += 1;
-		});
-		Self { client_counter }
+	pub fn login_required() -> Response<Body> {
+		Response::builder()
+			.status(401)
+			.body(Body::from("Sign in is required"))
+			.unwrap()
 	}
-}
 
-impl Drop for ConnectionHandle {
-	fn drop(&mut self) {
-		self.client_counter.send_modify(|v| {
-			*v -= 1;
-		});
+	pub fn not_found() -> Response<Body> {
+		Response::builder()
+			.status(404)
+			.body(Body::from("Not found"))
+			.unwrap()
 	}
-}
 
-type StartData = (PathBuf, Arc<tokio::sync::watch::Sender<usize>>);
+	pub fn bad_request(message: &'static str) -> Response<Body> {
+		Response::builder()
+			.status(400)
+			.body(Body::from(message))
+			.unwrap()
+	}
 
-/// State stored in the ConnectionManager for each server version.
-struct VersionState {
-	downloaded: bool,
-	socket_path: Barrier<Result<StartData, String>>,
-}
+	pub fn bad_gateway(message: &'static str) -> Response<Body> {
+		Response::builder()
+			.status(502)
+			.body(Body::from(message))
+			.unwrap()
+	}
+
+	pub fn redirect(location: &str) -> Response<Body> {
+		Response::builder()
+			.status(303)
+			.header(hyper::header::LOCATION, location)
+			.body(Body::empty())
+			.unwrap()
+	}
+
+	/// Redirects to the login page, which returns to the requested page once
+	/// the user has signed in.
+	pub fn redirect_to_login(base_path: &str, req: &Request<Body>) -> Response<Body> {
+		let return_to = strip_connection_token(
+			req.uri()
+				.path_and_query()
+				.map(|p| p.as_str())
+				.unwrap_or("/"),
+			CONNECTION_TOKEN_QUERY_NAME,
+		);
+		let query: String = url::form_urlencoded::Serializer::new(String::new())
+			.append_pair("return_to", &return_to)
+			.finish();
+
+		redirect(&format!("{}{}?{}", base_path, auth::LOGIN_PATH, query))
+	}
+
+	pub fn login_page(
+		base_path: &str,
+		return_to: &str,
+		auth: &Authenticator,
+		error: Option<&str>,
+	) -> Response<Body> {
+		let mut html = String::from(concatcp!(
+			"<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Sign in to ",
+			QUALITYLESS_SERVER_NAME,
+			"</title></head><body><h1>Sign in</h1>"
+		));
+		if let Some(e) = error {
+			html.push_str(&format!("<p role=\"alert\">{}</p>", escape_html(e)));
+		}
+		if auth.has_password_login() {
+			html.push_str(&format!(
+				concat!(
+					"<form method=\"post\" action=\"{}\">",
+					"<input type=\"hidden\" name=\"return_to\" value=\"{}\">",
+					"<p><label>User <input name=\"user\" autocomplete=\"username\" required autofocus></label></p>",
+					"<p><label>Password <input name=\"password\" type=\"password\" autocomplete=\"current-password\" required></label></p>",
+					"<p><button type=\"submit\">Sign in</button></p></form>"
+				),
+				escape_html(&format!("{}{}", base_path, auth::LOGIN_PATH)),
+				escape_html(return_to),
+			));
+		}
+		if auth.has_oidc_login() {
+			let query: String = url::form_urlencoded::Serializer::new(String::new())
+				.append_pair("return_to", return_to)
+				.finish();
+			html.push_str(&format!(
+				"<p><a href=\"{}\">Sign in with single sign-on</a></p>",
+				escape_html(&format!("{}{}?{}", base_path, auth::OIDC_LOGIN_PATH, query)),
+			));
+		}
+		html.push_str("</body></html>");
+
+		Response::builder()
+			.status(if error.is_some() { 401 } else { 200 })
+			.header("Content-Type", "text/html")
+			.body(Body::from(html))
+			.unwrap()
+	}
+
+	fn escape_html(s: &str) -> String {
+		s.replace('&', "&amp;")
+			.replace('<', "&lt;")
+			.replace('>', "&gt;")
+			.replace('"', "&quot;")
+			.replace('\'', "&#39;")
+	}
+
+	pub fn json<T: serde::Serialize>(value: &T) -> Response<Body> {
+		Response::builder()
+			.status(200)
+			.header("Content-Type", "application/json")
+			.body(Body::from(serde_json::to_vec(value).unwrap()))
+			.unwrap()
+	}
+
+	pub fn wait_for_download(
+		base_path: &str,
+		(quality, commit): &(Quality, String),
+	) -> Response<Body> {
+		let progress_url = format!(
+			"{}{}/{}-{}",
+			base_path,
+			DOWNLOAD_PROGRESS_PATH,
+			quality.get_machine_name(),
+			commit
+		);
+
+		Response::builder()
+			.status(202)
+			.header("Content-Type", "text/html")
+			.body(Body::from(WAIT_FOR_DOWNLOAD_PAGE.replace(
+				PROGRESS_URL_PLACEHOLDER,
+				&serde_json::to_string(&progress_url).unwrap(),
+			)))
+			.unwrap()
+	}
+
+	pub fn progress_events(
+		mut rx: tokio::sync::watch::Receiver<DownloadProgress>,
+	) -> Response<Body> {
+		let (mut tx, body) = Body::channel();
+		tokio::spawn(async move {
+			loop {
+				let progress = rx.borrow_and_update().clone();
+				let event = format!("data: {}\n\n", serde_json::to_string(&progress).unwrap());
+				if tx.send_data(event.into()).await.is_err() || progress.is_finished() {
+					return;
+				}
+
+				if rx.changed().await.is_err() {
+					return;
+				}
+			}
+		});
+
+		Response::builder()
+			.status(200)
+			.header("Content-Type", "text/event-stream")
+			.header("Cache-Control", "no-cache")
+			.body(body)
+			.unwrap()
+	}
+
+	pub fn secret_key(hash: Vec<u8>) -> Response<Body> {
+		Response::builder()
+			.status(200)
+			.header("Content-Type", "application/octet-stream") // todo: get latest
+			.body(Body::from(hash))
+			.unwrap()
+	}
+}
+
+/// Handle returned when getting a stream to the server, used to refcount
+/// connections to a server so it can be disposed when there are no more clients.
+struct ConnectionHandle {
+	client_counter: Arc<tokio::sync::watch::Sender<usize>>,
+}
+
+impl ConnectionHandle {
+	pub fn new(client_counter: Arc<tokio::sync::watch::Sender<usize>>) -> Self {
+		client_counter.send_modify(|v| {
+			*v += 1;
+		});
+		Self { client_counter }
+	}
+}
+
+impl Drop for ConnectionHandle {
+	fn drop(&mut self) {
+		self.client_counter.send_modify(|v| {
+			*v -= 1;
+		});
+	}
+}
+
+type StartData = (PathBuf, Arc<tokio::sync::watch::Sender<usize>>);
+
+/// Time at which a running server will be shut down, if it stays idle.
+type ShutdownAt = Arc<Mutex<Option<Instant>>>;
+
+/// Gets when a server should be shut down, given its number of clients and
+/// when its drain grace period ends, if it was drained. Drained servers shut
+/// down as soon as their last client disconnects, and at the end of the grace
+/// period otherwise, which disconnects their clients so they reload onto the
+/// latest release.
+fn kill_deadline(
+	now: Instant,
+	clients: usize,
+	drain_at: Option<Instant>,
+	idle_timeout: Duration,
+	active_timeout: Duration,
+) -> Instant {
+	match (clients, drain_at) {
+		(0, Some(_)) => now,
+		(0, None) => now + idle_timeout,
+		(_, Some(drain_at)) => drain_at.min(now + active_timeout),
+		(_, None) => now + active_timeout,
+	}
+}
+
+/// Progress of a server download, streamed to clients waiting for it.
+#[derive(Clone, Serialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+enum DownloadProgress {
+	#[serde(rename_all = "camelCase")]
+	Downloading {
+		bytes_so_far: u64,
+		total_bytes: u64,
+	},
+	Unpacking,
+	Failed {
+		error: String,
+	},
+	Done,
+}
+
+impl DownloadProgress {
+	fn is_finished(&self) -> bool {
+		matches!(
+			self,
+			DownloadProgress::Failed { .. } | DownloadProgress::Done
+		)
+	}
+}
+
+/// Reports download progress to the log as well as to clients waiting for the
+/// download to finish.
+struct DownloadProgressReporter<'a> {
+	logger: DownloadLogger<'a>,
+	progress: &'a tokio::sync::watch::Sender<DownloadProgress>,
+}
+
+impl<'a> ReportCopyProgress for DownloadProgressReporter<'a> {
+	fn report_progress(&mut self, bytes_so_far: u64, total_bytes: u64) {
+		self.logger.report_progress(bytes_so_far, total_bytes);
+		self.progress.send_replace(DownloadProgress::Downloading {
+			bytes_so_far,
+			total_bytes,
+		});
+	}
+}
+
+/// State stored in the ConnectionManager for each server version.
+struct VersionState {
+	downloaded: bool,
+	socket_path: Barrier<Result<StartData, String>>,
+	shutdown_at: ShutdownAt,
+	progress: tokio::sync::watch::Receiver<DownloadProgress>,
+	/// Set to the end of the grace period once the version is drained.
+	drain: Arc<tokio::sync::watch::Sender<Option<Instant>>>,
+}
+
+/// Status of the serve-web process, returned from the `STATUS_PATH`.
+#[derive(Serialize)]
+struct ServeWebStatus {
+	servers: Vec<ServerStatus>,
+	latest_release: Option<LatestReleaseStatus>,
+	/// Failed connection token and login attempts since startup.
+	auth_failures: u64,
+	/// Servers that crashed repeatedly and are not started until their mark expires.
+	bad_servers: Vec<BadServer>,
+}
 
-type ConnectionStateMap = Arc<Mutex<HashMap<(Quality, String), VersionState>>>;
+#[derive(Serialize)]
+struct ServerStatus {
+	user: Option<String>,
+	quality: Quality,
+	commit: String,
+	downloaded: bool,
+	socket_path: Option<PathBuf>,
+	clients: usize,
+	/// Seconds until the server is shut down if no more clients connect.
+	idle_shutdown_in_secs: Option<u64>,
+	/// Recent crashes of servers for the commit.
+	crashes: u32,
+	/// Error encountered while downloading or starting the server, if any.
+	error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LatestReleaseStatus {
+	quality: Quality,
+	commit: String,
+	name: String,
+	age_secs: u64,
+}
+
+type ConnectionStateMap = Arc<Mutex<HashMap<ServerKey, VersionState>>>;
+
+/// Identifies a server process. One runs for each release, and for each
+/// user when users are identified with `--auth-config` or `--user-header`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ServerKey {
+	user: Option<String>,
+	quality: Quality,
+	commit: String,
+}
+
+impl ServerKey {
+	fn new(release: &Release, user: Option<String>) -> Self {
+		Self {
+			user,
+			quality: release.quality,
+			commit: release.commit.clone(),
+		}
+	}
+
+	fn is_release(&self, release: &Release) -> bool {
+		self.quality == release.quality && self.commit == release.commit
+	}
+}
 
 /// Manages the connections to running web UI instances. Multiple web servers
 /// can run concurrently, with routing based on the URL path.
@@ -512,18 +1540,52 @@ struct ConnectionManager {
 	base_path: String,
 	/// Cache where servers are stored
 	cache: DownloadCache,
-	/// Mapping of (Quality, Commit) to the state each server is in
+	/// Mapping of servers to the state each is in
 	state: ConnectionStateMap,
+	/// Directory under which identified users' data is kept by default
+	users_dir: PathBuf,
 	/// Update service instance
 	update_service: UpdateService,
-	/// Cache of the latest released version, storing the time we checked as well
+	/// Cache of the release served by default, storing the time we checked as well
 	latest_version: tokio::sync::Mutex<Option<(Instant, Release)>>,
+	/// Versions that were drained, and when their grace period ends
+	drained: Mutex<HashMap<(Quality, String), Instant>>,
+	/// Limits failed connection token and login attempts
+	auth_limiter: AuthLimiter,
+	/// Crashes of servers, by commit
+	crashes: Arc<CrashTracker>,
+	/// Keep-alive connections to running servers
+	pool: Arc<ConnectionPool>,
 }
 
 fn key_for_release(release: &Release) -> (Quality, String) {
 	(release.quality, release.commit.clone())
 }
 
+/// Encodes a user name to be safe to use as a path segment. Bytes other than
+/// lowercase letters, digits, `-`, `_`, `@`, and `.` after the first
+/// character are percent-encoded, so the encoding is reversible and different
+/// users never share a directory, even on case-insensitive file systems.
+fn encode_user_name(user: &str) -> String {
+	use std::fmt::Write as _;
+
+	if user.is_empty() {
+		return "%".to_string();
+	}
+
+	let mut name = String::with_capacity(user.len());
+	for (i, b) in user.bytes().enumerate() {
+		match b {
+			b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'@' => name.push(b as char),
+			b'.' if i > 0 => name.push('.'),
+			_ => {
+				let _ = write!(name, "%{:02X}", b);
+			}
+		}
+	}
+	name
+}
+
 fn normalize_base_path(p: &str) -> String {
 	let p = p.trim_matches('/');
 
@@ -549,45 +1611,121 @@ impl ConnectionManager {
 				Arc::new(ReqwestSimpleHttp::with_client(ctx.http.clone())),
 			),
 			state: ConnectionStateMap::default(),
+			users_dir: ctx.paths.root().join("serve-web-users"),
 			latest_version: tokio::sync::Mutex::default(),
+			drained: Mutex::default(),
+			auth_limiter: AuthLimiter::default(),
+			crashes: Arc::default(),
+			pool: Arc::default(),
 		})
 	}
 
-	/// Gets a connection to a server version
+	/// Gets a connection to a server version, run for the given user if any.
 	pub async fn get_connection(
 		&self,
 		release: Release,
+		user: Option<String>,
 	) -> Result<(AsyncPipe, ConnectionHandle), CodeError> {
-		// todo@connor4312: there is likely some performance benefit to
-		// implementing a 'keepalive' for these connections.
-		let (path, counter) = self.get_version_data(release).await?;
-		let handle = ConnectionHandle::new(counter);
+		let (path, handle) = self.get_server(release, user).await?;
 		let rw = get_socket_rw_stream(&path).await?;
 		Ok((rw, handle))
 	}
 
-	/// Gets the latest release for the CLI quality, caching its result for some
-	/// time to allow for fast loads.
-	pub async fn get_latest_release(&self) -> Result<Release, CodeError> {
+	/// Gets the socket path of a server version, run for the given user if
+	/// any, with a handle that counts the caller as a client until dropped.
+	pub async fn get_server(
+		&self,
+		release: Release,
+		user: Option<String>,
+	) -> Result<(PathBuf, ConnectionHandle), CodeError> {
+		let (path, counter) = self.get_version_data(release, user).await?;
+		Ok((path, ConnectionHandle::new(counter)))
+	}
+
+	/// Gets the release served to requests that don't ask for a specific
+	/// commit. If the latest release was marked bad for the user after
+	/// crashing repeatedly, the most recently used cached release that wasn't
+	/// is served instead.
+	pub async fn get_default_release(&self, user: Option<&str>) -> Result<Release, CodeError> {
+		let release = self.get_latest_release().await?;
+		if !self.crashes.is_bad(&release.commit, user) {
+			return Ok(release);
+		}
+
+		let fallback = self
+			.cache
+			.get_lru()
+			.into_iter()
+			.find(|c| {
+				is_commit_hash(c)
+					&& !self.crashes.is_bad(c, user)
+					&& self.cache.path().join(c).exists()
+			})
+			.ok_or_else(|| CodeError::ServerCrashedRepeatedly(release.commit.clone()))?;
+
+		warning!(
+			self.log,
+			"Release {} crashed repeatedly, serving {} instead",
+			release.commit,
+			fallback
+		);
+		Ok(Release {
+			name: "".to_string(),
+			quality: ReleaseProduct::read_from(&self.cache.path().join(&fallback))
+				.map(|p| p.quality)
+				.unwrap_or(release.quality),
+			commit: fallback,
+			..release
+		})
+	}
+
+	/// Gets the commit or version pinned in the arguments, or otherwise the
+	/// latest release for the quality, caching its result for some time to
+	/// allow for fast loads.
+	async fn get_latest_release(&self) -> Result<Release, CodeError> {
 		let mut latest = self.latest_version.lock().await;
 		let now = Instant::now();
 		if let Some((checked_at, release)) = &*latest {
-			if checked_at.elapsed() < Duration::from_secs(RELEASE_CACHE_SECS) {
+			// a pinned version always resolves to the same release
+			if self.args.server_version.is_some()
+				|| self.args.commit_id.is_some()
+				|| checked_at.elapsed() < Duration::from_secs(RELEASE_CACHE_SECS)
+			{
 				return Ok(release.clone());
 			}
 		}
 
-		let quality = VSCODE_CLI_QUALITY
-			.ok_or_else(|| CodeError::UpdatesNotConfigured("no configured quality"))
-			.and_then(|q| {
-				Quality::try_from(q).map_err(|_| CodeError::UpdatesNotConfigured("unknown quality"))
-			})?;
+		let quality = self.quality()?;
+
+		let local = match &self.args.commit_id {
+			Some(commit) => Some(Release {
+				name: "".to_string(),
+				platform: self.platform,
+				target: TargetKind::Web,
+				quality,
+				commit: commit.to_string(),
+			}),
+			None if self.args.offline => Some(self.get_cached_release(quality)?),
+			None => None,
+		};
+		if let Some(release) = local {
+			self.set_latest_release(&mut latest, now, release.clone());
+			return Ok(release);
+		}
 
-		let release = self
-			.update_service
-			.get_latest_commit(self.platform, TargetKind::Web, quality)
-			.await
-			.map_err(|e| CodeError::UpdateCheckFailed(e.to_string()));
+		let release = match &self.args.server_version {
+			Some(version) => {
+				self.update_service
+					.get_release_by_semver_version(self.platform, TargetKind::Web, quality, version)
+					.await
+			}
+			None => {
+				self.update_service
+					.get_latest_commit(self.platform, TargetKind::Web, quality)
+					.await
+			}
+		}
+		.map_err(|e| CodeError::UpdateCheckFailed(e.to_string()));
 
 		// If the update service is unavailable and we have stale data, use that
 		if let (Err(e), Some((_, previous))) = (&release, &*latest) {
@@ -596,17 +1734,320 @@ impl ConnectionManager {
 		}
 
 		let release = release?;
-		debug!(self.log, "refreshed latest release: {}", release);
-		*latest = Some((now, release.clone()));
+		debug!(self.log, "refreshed release to serve: {}", release);
+		self.set_latest_release(&mut latest, now, release.clone());
 
 		Ok(release)
 	}
 
+	/// Updates the latest release, draining older versions if it changed.
+	fn set_latest_release(
+		&self,
+		latest: &mut Option<(Instant, Release)>,
+		checked_at: Instant,
+		release: Release,
+	) {
+		let changed = latest
+			.as_ref()
+			.map(|(_, r)| key_for_release(r) != key_for_release(&release))
+			.unwrap_or(false);
+		if changed {
+			self.drain_old_versions(&release);
+		}
+
+		*latest = Some((checked_at, release));
+	}
+
+	/// Applies the drain policy, if configured, once a newer release becomes
+	/// the latest. Other versions shut down as soon as they have no clients,
+	/// or once the grace period has passed, after which clients are routed
+	/// to the latest version.
+	fn drain_old_versions(&self, latest: &Release) {
+		let grace_period = match self.args.drain_grace_period {
+			Some(secs) => Duration::from_secs(secs),
+			None => return,
+		};
+
+		let drain_at = Instant::now() + grace_period;
+		let state = self.state.lock().unwrap();
+		let mut drained = self.drained.lock().unwrap();
+		drained.remove(&key_for_release(latest));
+
+		for (key, s) in state.iter() {
+			if key.is_release(latest) || s.drain.borrow().is_some() {
+				continue;
+			}
+
+			info!(
+				self.log,
+				"Draining server {}, it will shut down once its clients disconnect, or in {}s",
+				key.commit,
+				grace_period.as_secs()
+			);
+			s.drain.send_replace(Some(drain_at));
+			drained.insert((key.quality, key.commit.clone()), drain_at);
+		}
+	}
+
+	/// Gets when the grace period of the version ends, if it was drained.
+	/// Servers started for it during the grace period are drained as well.
+	fn drain_at(&self, release: &Release) -> Option<Instant> {
+		self.drained
+			.lock()
+			.unwrap()
+			.get(&key_for_release(release))
+			.copied()
+	}
+
+	/// Gets whether the version was drained and its grace period has passed,
+	/// in which case clients should not be routed to it anymore.
+	fn is_drained(&self, release: &Release) -> bool {
+		self.drained
+			.lock()
+			.unwrap()
+			.get(&key_for_release(release))
+			.map(|drain_at| *drain_at <= Instant::now())
+			.unwrap_or(false)
+	}
+
+	/// Gets the quality of servers to serve.
+	fn quality(&self) -> Result<Quality, CodeError> {
+		resolve_quality(self.args.quality, VSCODE_CLI_QUALITY)
+	}
+
+	/// Gets whether servers are run separately for each identified user.
+	fn is_per_user(&self) -> bool {
+		self.args.auth_config.is_some() || self.args.user_header.is_some()
+	}
+
+	/// Periodically checks for new releases, downloading them into the cache
+	/// before they're requested. If `warm` is set, they're also started. Once
+	/// ready, the release becomes the latest release served to new clients.
+	pub async fn prefetch_releases(self: Arc<Self>, interval: Duration, warm: bool) {
+		let mut interval = tokio::time::interval(interval);
+		loop {
+			interval.tick().await;
+			if let Err(e) = self.prefetch_latest_release(warm).await {
+				warning!(self.log, "error prefetching latest release: {}", e);
+			}
+		}
+	}
+
+	async fn prefetch_latest_release(&self, warm: bool) -> Result<(), CodeError> {
+		let release = self
+			.update_service
+			.get_latest_commit(self.platform, TargetKind::Web, self.quality()?)
+			.await
+			.map_err(|e| CodeError::UpdateCheckFailed(e.to_string()))?;
+
+		if let Some((_, latest)) = &*self.latest_version.lock().await {
+			if latest.commit == release.commit {
+				return Ok(());
+			}
+		}
+
+		debug!(self.log, "prefetching release {}", release);
+		self.prefetch_download(&release).await?;
+		// servers are started per user once users are identified, so there's
+		// no shared server to warm up
+		if warm && !self.is_per_user() {
+			let _ = self.get_version_data(release.clone(), None).await?;
+		}
+
+		info!(self.log, "Prefetched new release {}", release);
+		let mut latest = self.latest_version.lock().await;
+		self.set_latest_release(&mut latest, Instant::now(), release);
+		Ok(())
+	}
+
+	/// Downloads the release into the cache without starting it. The download
+	/// is tracked in the state so clients requesting the release in the
+	/// meantime see its progress rather than starting a second download.
+	async fn prefetch_download(&self, release: &Release) -> Result<(), CodeError> {
+		let key = ServerKey::new(release, None);
+		let (progress_tx, progress) = tokio::sync::watch::channel(DownloadProgress::Downloading {
+			bytes_so_far: 0,
+			total_bytes: 0,
+		});
+
+		// kept until the state is removed, since nothing waits on the barrier
+		// of a version that's not downloaded yet
+		let _opener = {
+			let mut state = self.state.lock().unwrap();
+			if state.keys().any(|k| k.is_release(release))
+				|| self.cache.exists(&release.commit).is_some()
+			{
+				return Ok(());
+			}
+
+			let (socket_path, opener) = new_barrier();
+			state.insert(
+				key.clone(),
+				VersionState {
+					socket_path,
+					downloaded: false,
+					shutdown_at: ShutdownAt::default(),
+					progress,
+					drain: Arc::new(tokio::sync::watch::channel(None).0),
+				},
+			);
+			opener
+		};
+
+		let result = Self::download_release(
+			&self.log,
+			release,
+			self.update_service.clone(),
+			&self.cache,
+			&progress_tx,
+		)
+		.await;
+
+		// remove the state before publishing the result, so that clients who
+		// reload once it's done will start the server from the cache.
+		self.state.lock().unwrap().remove(&key);
+		match result {
+			Ok(_) => {
+				progress_tx.send_replace(DownloadProgress::Done);
+				Ok(())
+			}
+			Err(e) => {
+				progress_tx.send_replace(DownloadProgress::Failed {
+					error: e.to_string(),
+				});
+				Err(CodeError::ServerDownloadError(e.to_string()))
+			}
+		}
+	}
+
+	/// Gets a receiver for the download progress of the release, if it's
+	/// currently being downloaded or its download failed recently.
+	fn get_download_progress(
+		&self,
+		release: &Release,
+	) -> Option<tokio::sync::watch::Receiver<DownloadProgress>> {
+		self.state
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|(k, _)| k.is_release(release))
+			.map(|(_, s)| s.progress.clone())
+			.find(|p| !matches!(*p.borrow(), DownloadProgress::Done))
+	}
+
+	/// Gets the status of servers managed by the connection manager.
+	async fn get_status(&self) -> ServeWebStatus {
+		let latest_release = self
+			.latest_version
+			.lock()
+			.await
+			.as_ref()
+			.map(|(checked_at, r)| LatestReleaseStatus {
+				quality: r.quality,
+				commit: r.commit.clone(),
+				name: r.name.clone(),
+				age_secs: checked_at.elapsed().as_secs(),
+			});
+
+		let state = self.state.lock().unwrap();
+		let now = Instant::now();
+		let mut servers: Vec<ServerStatus> = state
+			.iter()
+			.map(|(key, s)| {
+				let started = s.socket_path.peek();
+				let (socket_path, clients, error) = match started {
+					Some(Ok((path, counter))) => (Some(path), *counter.borrow(), None),
+					Some(Err(e)) => (None, 0, Some(e)),
+					None => (None, 0, None),
+				};
+
+				ServerStatus {
+					user: key.user.clone(),
+					quality: key.quality,
+					commit: key.commit.clone(),
+					downloaded: s.downloaded || s.socket_path.is_open(),
+					socket_path,
+					clients,
+					idle_shutdown_in_secs: match *s.shutdown_at.lock().unwrap() {
+						Some(t) if clients == 0 => Some(t.saturating_duration_since(now).as_secs()),
+						_ => None,
+					},
+					crashes: self.crashes.crash_count(&key.commit, key.user.as_deref()),
+					error,
+				}
+			})
+			.collect();
+		servers.sort_by(|a, b| (&a.commit, &a.user).cmp(&(&b.commit, &b.user)));
+
+		ServeWebStatus {
+			servers,
+			latest_release,
+			auth_failures: self.auth_limiter.total_failures(),
+			bad_servers: self.crashes.bad_servers(),
+		}
+	}
+
+	/// Gets the user data and extensions directories to start a server with.
+	/// Identified users each get their own, from the templates in the
+	/// arguments or in the CLI data directory.
+	fn get_user_dirs(&self, user: Option<&str>) -> (Option<String>, Option<String>) {
+		let user = match user {
+			Some(u) => encode_user_name(u),
+			None => {
+				return (
+					self.args.user_data_dir.clone(),
+					self.args.extensions_dir.clone(),
+				)
+			}
+		};
+
+		let user_dir = self.users_dir.join(&user);
+		let from_template = |template: &Option<String>, default: &str| match template {
+			Some(t) => t.replace("{user}", &user),
+			None => user_dir.join(default).to_string_lossy().to_string(),
+		};
+
+		(
+			Some(from_template(&self.args.user_data_dir_template, "data")),
+			Some(from_template(
+				&self.args.extensions_dir_template,
+				"extensions",
+			)),
+		)
+	}
+
+	/// Gets the most recently used release in the cache, for use when offline.
+	/// The quality is read from the server, falling back to the given quality.
+	fn get_cached_release(&self, quality: Quality) -> Result<Release, CodeError> {
+		let commit = self
+			.cache
+			.get_lru()
+			.into_iter()
+			.find(|c| is_commit_hash(c) && self.cache.path().join(c).exists())
+			.ok_or(CodeError::NoCachedServerOffline)?;
+
+		let quality = ReleaseProduct::read_from(&self.cache.path().join(&commit))
+			.map(|p| p.quality)
+			.unwrap_or(quality);
+
+		Ok(Release {
+			name: "".to_string(),
+			platform: self.platform,
+			target: TargetKind::Web,
+			quality,
+			commit,
+		})
+	}
+
 	/// Gets the StartData for the a version of the VS Code server, triggering
 	/// download/start if necessary. It returns `CodeError::ServerNotYetDownloaded`
 	/// while the server is downloading, which is used to have a refresh loop on the page.
-	async fn get_version_data(&self, release: Release) -> Result<StartData, CodeError> {
-		self.get_version_data_inner(release)?
+	async fn get_version_data(
+		&self,
+		release: Release,
+		user: Option<String>,
+	) -> Result<StartData, CodeError> {
+		self.get_version_data_inner(release, user)?
 			.wait()
 			.await
 			.unwrap()
@@ -616,12 +2057,24 @@ impl ConnectionManager {
 	fn get_version_data_inner(
 		&self,
 		release: Release,
+		user: Option<String>,
 	) -> Result<Barrier<Result<StartData, String>>, CodeError> {
+		if self.crashes.is_bad(&release.commit, user.as_deref()) {
+			return Err(CodeError::ServerCrashedRepeatedly(release.commit));
+		}
+
 		let mut state = self.state.lock().unwrap();
-		let key = key_for_release(&release);
+		let key = ServerKey::new(&release, user);
 		if let Some(s) = state.get_mut(&key) {
 			if !s.downloaded {
-				if s.socket_path.is_open() {
+				// failed downloads are shown by the progress page until they expire
+				if matches!(*s.progress.borrow(), DownloadProgress::Failed { .. }) {
+					return Err(CodeError::ServerNotYetDownloaded);
+				}
+
+				// once the download is done, wait for the server to start
+				if s.socket_path.is_open() || matches!(*s.progress.borrow(), DownloadProgress::Done)
+				{
 					s.downloaded = true;
 				} else {
 					return Err(CodeError::ServerNotYetDownloaded);
@@ -633,11 +2086,27 @@ impl ConnectionManager {
 
 		let (socket_path, opener) = new_barrier();
 		let state_map_dup = self.state.clone();
+		let shutdown_at = ShutdownAt::default();
+		let (drain, drain_rx) = tokio::sync::watch::channel(self.drain_at(&release));
+		let drain = Arc::new(drain);
+		let (user_data_dir, extensions_dir) = self.get_user_dirs(key.user.as_deref());
 		let args = StartArgs {
 			args: self.args.clone(),
 			log: self.log.clone(),
+			log_file: child_log_path(
+				&self.cache.path().join("logs"),
+				&release.commit,
+				key.user.as_deref().map(encode_user_name).as_deref(),
+			),
+			crashes: self.crashes.clone(),
+			pool: self.pool.clone(),
+			user: key.user.clone(),
+			user_data_dir,
+			extensions_dir,
 			opener,
 			release,
+			shutdown_at: shutdown_at.clone(),
+			drain: drain_rx,
 		};
 
 		if let Some(p) = self.cache.exists(&args.release.commit) {
@@ -646,26 +2115,53 @@ impl ConnectionManager {
 				VersionState {
 					socket_path: socket_path.clone(),
 					downloaded: true,
+					shutdown_at,
+					progress: tokio::sync::watch::channel(DownloadProgress::Done).1,
+					drain,
 				},
 			);
 
+			let pin = self.cache.pin(&args.release.commit);
 			tokio::spawn(async move {
 				Self::start_version(args, p).await;
 				state_map_dup.lock().unwrap().remove(&key);
+				drop(pin);
 			});
 			Ok(socket_path)
+		} else if self.args.offline {
+			Err(CodeError::ServerNotCachedOffline(args.release.commit))
+		} else if state.iter().any(|(k, s)| {
+			k.is_release(&args.release) && !matches!(*s.progress.borrow(), DownloadProgress::Done)
+		}) {
+			// downloading, or recently failed to download, for another user,
+			// which this user can wait on too
+			Err(CodeError::ServerNotYetDownloaded)
 		} else {
+			let (progress_tx, progress) =
+				tokio::sync::watch::channel(DownloadProgress::Downloading {
+					bytes_so_far: 0,
+					total_bytes: 0,
+				});
 			state.insert(
 				key.clone(),
 				VersionState {
 					socket_path,
 					downloaded: false,
+					shutdown_at,
+					progress: progress.clone(),
+					drain,
 				},
 			);
 			let update_service = self.update_service.clone();
 			let cache = self.cache.clone();
 			tokio::spawn(async move {
-				Self::download_version(args, update_service.clone(), cache.clone()).await;
+				Self::download_version(args, update_service.clone(), cache.clone(), progress_tx)
+					.await;
+				// keep a failed download around for a while, so clients see the
+				// error instead of starting the download again on each reload
+				if matches!(*progress.borrow(), DownloadProgress::Failed { .. }) {
+					tokio::time::sleep(FAILED_DOWNLOAD_TTL).await;
+				}
 				state_map_dup.lock().unwrap().remove(&key);
 			});
 			Err(CodeError::ServerNotYetDownloaded)
@@ -677,33 +2173,68 @@ impl ConnectionManager {
 		args: StartArgs,
 		update_service: UpdateService,
 		cache: DownloadCache,
+		progress: tokio::sync::watch::Sender<DownloadProgress>,
 	) {
-		let release_for_fut = args.release.clone();
-		let log_for_fut = args.log.clone();
-		let dir_fut = cache.create(&args.release.commit, |target_dir| async move {
-			info!(log_for_fut, "Downloading server {}", release_for_fut.commit);
+		let _pin = cache.pin(&args.release.commit);
+		match Self::download_release(&args.log, &args.release, update_service, &cache, &progress)
+			.await
+		{
+			Err(e) => {
+				progress.send_replace(DownloadProgress::Failed {
+					error: e.to_string(),
+				});
+				args.opener.open(Err(e.to_string()))
+			}
+			Ok(dir) => {
+				progress.send_replace(DownloadProgress::Done);
+				Self::start_version(args, dir).await
+			}
+		}
+	}
+
+	/// Downloads a server version into the cache, reporting progress as it
+	/// goes, and returns the path it was extracted to.
+	async fn download_release(
+		log: &log::Logger,
+		release: &Release,
+		update_service: UpdateService,
+		cache: &DownloadCache,
+		progress: &tokio::sync::watch::Sender<DownloadProgress>,
+	) -> Result<PathBuf, AnyError> {
+		let dir_fut = cache.create(&release.commit, |target_dir| async move {
+			info!(log, "Downloading server {}", release.commit);
 			let tmpdir = tempfile::tempdir().unwrap();
-			let response = update_service.get_download_stream(&release_for_fut).await?;
+			let response = update_service.get_download_stream(release).await?;
 
 			let name = response.url_path_basename().unwrap();
 			let archive_path = tmpdir.path().join(name);
 			http::download_into_file(
 				&archive_path,
-				log_for_fut.get_download_logger("Downloading server:"),
+				DownloadProgressReporter {
+					logger: log.get_download_logger("Downloading server:"),
+					progress,
+				},
 				response,
 			)
 			.await?;
+			progress.send_replace(DownloadProgress::Unpacking);
 			unzip_downloaded_release(&archive_path, &target_dir, SilentCopyProgress())?;
 			Ok(())
 		});
 
-		match dir_fut.await {
-			Err(e) => args.opener.open(Err(e.to_string())),
-			Ok(dir) => Self::start_version(args, dir).await,
+		METRICS.downloads_started.inc();
+		let result = dir_fut.await;
+		match &result {
+			Ok(_) => METRICS.downloads_finished.inc(),
+			Err(_) => METRICS.downloads_failed.inc(),
 		}
+
+		result
 	}
 
-	/// Starts a downloaded server that can be found in the given `path`.
+	/// Starts a downloaded server that can be found in the given `path`. If
+	/// it crashes, it's restarted with a backoff on the same socket, until
+	/// its commit has crashed too often and is marked bad.
 	async fn start_version(args: StartArgs, path: PathBuf) {
 		info!(args.log, "Starting server {}", args.release.commit);
 
@@ -713,100 +2244,222 @@ impl ConnectionManager {
 
 		let socket_path = get_socket_name();
 
-		let mut cmd = new_script_command(&executable);
-		cmd.stdin(std::process::Stdio::null());
-		cmd.stderr(std::process::Stdio::piped());
-		cmd.stdout(std::process::Stdio::piped());
-		cmd.arg("--socket-path");
-		cmd.arg(&socket_path);
-
-		// License agreement already checked by the `server_web` function.
-		cmd.args(["--accept-server-license-terms"]);
-
-		if let Some(a) = &args.args.server_base_path {
-			cmd.arg("--server-base-path");
-			cmd.arg(a);
-		}
-		if let Some(a) = &args.args.server_data_dir {
-			cmd.arg("--server-data-dir");
-			cmd.arg(a);
-		}
-		if let Some(a) = &args.args.user_data_dir {
-			cmd.arg("--user-data-dir");
-			cmd.arg(a);
-		}
-		if let Some(a) = &args.args.extensions_dir {
-			cmd.arg("--extensions-dir");
-			cmd.arg(a);
-		}
-		if args.args.without_connection_token {
-			cmd.arg("--without-connection-token");
-		}
-		// Note: intentional that we don't pass --connection-token here, we always
-		// convert it into the file variant.
-		if let Some(ct) = &args.args.connection_token_file {
-			cmd.arg("--connection-token-file");
-			cmd.arg(ct);
-		}
-
-		// removed, otherwise the workbench will not be usable when running the CLI from sources.
-		cmd.env_remove("VSCODE_DEV");
-
-		let mut child = match cmd.spawn() {
-			Ok(c) => c,
+		// read for each server so that changes to the config file apply to new releases
+		let options = match ServerOptions::read(&args.args) {
+			Ok(o) => o,
 			Err(e) => {
 				args.opener.open(Err(e.to_string()));
 				return;
 			}
 		};
 
-		let (mut stdout, mut stderr) = (
-			BufReader::new(child.stdout.take().unwrap()).lines(),
-			BufReader::new(child.stderr.take().unwrap()).lines(),
-		);
+		let mut child_log = match open_child_log(&args.log_file) {
+			Ok(f) => Some(f),
+			Err(e) => {
+				warning!(
+					args.log,
+					"Could not open server log file {}: {}",
+					args.log_file.display(),
+					e
+				);
+				None
+			}
+		};
 
-		// wrapped option to prove that we only use this once in the loop
 		let (counter_tx, mut counter_rx) = tokio::sync::watch::channel(0);
-		let mut opener = Some((args.opener, socket_path, Arc::new(counter_tx)));
+		let counter_tx = Arc::new(counter_tx);
+		// wrapped option to prove that we only open the barrier once
+		let mut opener = Some(args.opener);
 		let commit_prefix = &args.release.commit[..7];
-		let kill_timer = tokio::time::sleep(Duration::from_secs(SERVER_IDLE_TIMEOUT_SECS));
+		let idle_timeout =
+			Duration::from_secs(args.args.idle_timeout.unwrap_or(SERVER_IDLE_TIMEOUT_SECS));
+		let active_timeout = Duration::from_secs(
+			args.args
+				.active_timeout
+				.unwrap_or(SERVER_ACTIVE_TIMEOUT_SECS),
+		);
+		let mut drain_rx = args.drain;
+		let get_kill_deadline = |clients: usize, drain_at: Option<Instant>| {
+			kill_deadline(
+				Instant::now(),
+				clients,
+				drain_at,
+				idle_timeout,
+				active_timeout,
+			)
+		};
+
+		// a server started while drained waits for its first client as usual,
+		// up to the end of the grace period
+		let kill_deadline = match *drain_rx.borrow() {
+			Some(drain_at) => drain_at.min(get_kill_deadline(0, None)),
+			None => get_kill_deadline(0, None),
+		};
+		*args.shutdown_at.lock().unwrap() = Some(kill_deadline);
+		let kill_timer = tokio::time::sleep_until(kill_deadline.into());
 		pin!(kill_timer);
 
 		loop {
-			tokio::select! {
-				Ok(Some(l)) = stdout.next_line() => {
-					info!(args.log, "[{} stdout]: {}", commit_prefix, l);
+			// a crashed server may leave its socket behind
+			let _ = std::fs::remove_file(&socket_path);
+
+			let mut cmd = new_script_command(&executable);
+			cmd.stdin(std::process::Stdio::null());
+			cmd.stderr(std::process::Stdio::piped());
+			cmd.stdout(std::process::Stdio::piped());
+			cmd.arg("--socket-path");
+			cmd.arg(&socket_path);
+
+			// License agreement already checked by the `server_web` function.
+			cmd.args(["--accept-server-license-terms"]);
+
+			if let Some(a) = &args.args.server_base_path {
+				cmd.arg("--server-base-path");
+				cmd.arg(a);
+			}
+			if let Some(a) = &args.args.server_data_dir {
+				cmd.arg("--server-data-dir");
+				cmd.arg(a);
+			}
+			if let Some(a) = &args.user_data_dir {
+				cmd.arg("--user-data-dir");
+				cmd.arg(a);
+			}
+			if let Some(a) = &args.extensions_dir {
+				cmd.arg("--extensions-dir");
+				cmd.arg(a);
+			}
+			if args.args.without_connection_token {
+				cmd.arg("--without-connection-token");
+			}
+			// Note: intentional that we don't pass --connection-token here, we always
+			// convert it into the file variant.
+			if let Some(ct) = &args.args.connection_token_file {
+				cmd.arg("--connection-token-file");
+				cmd.arg(ct);
+			}
 
-					if l.contains("Server bound to") {
-						if let Some((opener, path, counter_tx)) = opener.take() {
-							opener.open(Ok((path, counter_tx)));
-						}
+			for extension in &options.install_extensions {
+				cmd.arg(format!("--install-extension={}", extension));
+			}
+			if !options.install_extensions.is_empty() {
+				cmd.arg("--start-server");
+			}
+			cmd.args(&options.args);
+
+			// removed, otherwise the workbench will not be usable when running the CLI from sources.
+			cmd.env_remove("VSCODE_DEV");
+			cmd.envs(options.env.iter().map(|(k, v)| (k, v)));
+
+			let mut child = match cmd.spawn() {
+				Ok(c) => c,
+				Err(e) => {
+					if let Some(opener) = opener.take() {
+						opener.open(Err(e.to_string()));
 					}
+					return;
 				}
-				Ok(Some(l)) = stderr.next_line() => {
-					info!(args.log, "[{} stderr]: {}", commit_prefix, l);
-				},
-				n = counter_rx.changed() => {
-					kill_timer.as_mut().reset(match n {
-						// err means that the record was dropped
-						Err(_) => tokio::time::Instant::now(),
-						Ok(_) => {
-							if *counter_rx.borrow() == 0 {
-								tokio::time::Instant::now() + Duration::from_secs(SERVER_IDLE_TIMEOUT_SECS)
-							} else {
-								tokio::time::Instant::now() + Duration::from_secs(SERVER_ACTIVE_TIMEOUT_SECS)
+			};
+			METRICS.process_spawns.inc_by(&[("kind", "server")], 1);
+			let started_at = Instant::now();
+			write_child_log(
+				&mut child_log,
+				&format!("== Server {} started ==", args.release.commit),
+			);
+
+			let (mut stdout, mut stderr) = (
+				BufReader::new(child.stdout.take().unwrap()).lines(),
+				BufReader::new(child.stderr.take().unwrap()).lines(),
+			);
+
+			let status = loop {
+				tokio::select! {
+					Ok(Some(l)) = stdout.next_line() => {
+						info!(args.log, "[{} stdout]: {}", commit_prefix, l);
+						write_child_log(&mut child_log, &l);
+
+						if l.contains("Server bound to") {
+							if let Some(opener) = opener.take() {
+								opener.open(Ok((socket_path.clone(), counter_tx.clone())));
 							}
 						}
-					});
+					}
+					Ok(Some(l)) = stderr.next_line() => {
+						info!(args.log, "[{} stderr]: {}", commit_prefix, l);
+						write_child_log(&mut child_log, &l);
+					},
+					n = counter_rx.changed() => {
+						let deadline = match n {
+							// err means that the record was dropped
+							Err(_) => Instant::now(),
+							Ok(_) => get_kill_deadline(*counter_rx.borrow(), *drain_rx.borrow()),
+						};
+						*args.shutdown_at.lock().unwrap() = Some(deadline);
+						kill_timer.as_mut().reset(deadline.into());
+					}
+					Ok(_) = drain_rx.changed() => {
+						let deadline = get_kill_deadline(*counter_rx.borrow(), *drain_rx.borrow());
+						*args.shutdown_at.lock().unwrap() = Some(deadline);
+						kill_timer.as_mut().reset(deadline.into());
+					}
+					_ = &mut kill_timer => {
+						if drain_rx.borrow().is_some() {
+							info!(args.log, "[{} process]: drained, ending", commit_prefix);
+						} else {
+							info!(args.log, "[{} process]: idle timeout reached, ending", commit_prefix);
+						}
+						let _ = child.kill().await;
+						METRICS.process_exits.inc_by(&[("kind", "server")], 1);
+						args.pool.evict(&socket_path);
+						return;
+					}
+					e = child.wait() => {
+						info!(args.log, "[{} process]: exited: {:?}", commit_prefix, e);
+						METRICS.process_exits.inc_by(&[("kind", "server")], 1);
+						break e;
+					}
 				}
-				_ = &mut kill_timer => {
-					info!(args.log, "[{} process]: idle timeout reached, ending", commit_prefix);
-					let _ = child.kill().await;
-					break;
+			};
+			args.pool.evict(&socket_path);
+
+			write_child_log(
+				&mut child_log,
+				&format!("== Server exited: {:?} ==", status),
+			);
+			if matches!(&status, Ok(s) if s.success()) || drain_rx.borrow().is_some() {
+				return;
+			}
+
+			METRICS.process_crashes.inc_by(&[("kind", "server")], 1);
+			match args.crashes.record_crash(
+				&args.release.commit,
+				args.user.as_deref(),
+				started_at.elapsed(),
+			) {
+				Some(delay) => {
+					warning!(
+						args.log,
+						"[{} process]: crashed, restarting in {}s",
+						commit_prefix,
+						delay.as_secs()
+					);
+					tokio::time::sleep(delay).await;
 				}
-				e = child.wait() => {
-					info!(args.log, "[{} process]: exited: {:?}", commit_prefix, e);
-					break;
+				None => {
+					error!(
+						args.log,
+						"[{} process]: crashed repeatedly, marking the release as bad for a while. See {} for its output.",
+						commit_prefix,
+						args.log_file.display()
+					);
+					if let Some(opener) = opener.take() {
+						opener.open(Err(format!(
+							"server {} crashed repeatedly, see {}",
+							args.release.commit,
+							args.log_file.display()
+						)));
+					}
+					return;
 				}
 			}
 		}
@@ -816,8 +2469,18 @@ impl ConnectionManager {
 struct StartArgs {
 	log: log::Logger,
 	args: ServeWebArgs,
+	/// File the server's output is written to
+	log_file: PathBuf,
+	crashes: Arc<CrashTracker>,
+	pool: Arc<ConnectionPool>,
+	/// User the server is run for, if any
+	user: Option<String>,
+	user_data_dir: Option<String>,
+	extensions_dir: Option<String>,
 	release: Release,
 	opener: BarrierOpener<Result<StartData, String>>,
+	shutdown_at: ShutdownAt,
+	drain: tokio::sync::watch::Receiver<Option<Instant>>,
 }
 
 fn mint_connection_token(path: &Path, prefer_token: Option<String>) -> std::io::Result<String> {
@@ -846,3 +2509,459 @@ fn mint_connection_token(path: &Path, prefer_token: Option<String>) -> std::io::
 	f.write_all(prefer_token.as_bytes())?;
 	Ok(prefer_token)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_resolve_quality() {
+		assert_eq!(
+			resolve_quality(Some(Quality::Insiders), Some("stable")).unwrap(),
+			Quality::Insiders
+		);
+		assert_eq!(
+			resolve_quality(None, Some("stable")).unwrap(),
+			Quality::Stable
+		);
+		assert!(resolve_quality(None, Some("nightly")).is_err());
+		assert!(resolve_quality(None, None).is_err());
+	}
+
+	fn request(uri: &str, headers: &[(&str, &str)]) -> Request<Body> {
+		let mut req = Request::builder().uri(uri);
+		for (name, value) in headers {
+			req = req.header(*name, *value);
+		}
+		req.body(Body::empty()).unwrap()
+	}
+
+	fn test_manager(root: &Path) -> Arc<ConnectionManager> {
+		test_manager_with_args(root, ServeWebArgs::default())
+	}
+
+	fn test_manager_with_args(root: &Path, args: ServeWebArgs) -> Arc<ConnectionManager> {
+		let ctx = CommandContext {
+			log: log::Logger::test(),
+			paths: LauncherPaths::new_without_replacements(root.to_owned()),
+			args: Default::default(),
+			http: reqwest::Client::new(),
+		};
+		ConnectionManager::new(&ctx, Platform::LinuxX64, args)
+	}
+
+	fn test_release(commit: &str) -> Release {
+		Release {
+			name: "test".to_string(),
+			platform: Platform::LinuxX64,
+			target: TargetKind::Web,
+			quality: Quality::Stable,
+			commit: commit.to_string(),
+		}
+	}
+
+	#[tokio::test]
+	async fn test_pinned_release_is_cached() {
+		let dir = tempfile::tempdir().unwrap();
+		let commit = "a".repeat(40);
+		let manager = test_manager_with_args(
+			dir.path(),
+			ServeWebArgs {
+				commit_id: Some(commit.clone()),
+				quality: Some(Quality::Stable),
+				..Default::default()
+			},
+		);
+
+		assert_eq!(manager.get_latest_release().await.unwrap().commit, commit);
+		let status = manager.get_status().await.latest_release.unwrap();
+		assert_eq!(status.commit, commit);
+	}
+
+	#[tokio::test]
+	async fn test_failed_download_is_kept() {
+		let dir = tempfile::tempdir().unwrap();
+		let cm = test_manager(dir.path());
+		let release = test_release(&"a".repeat(COMMIT_HASH_LEN));
+
+		let (socket_path, opener) = new_barrier();
+		opener.open(Err("network error".to_string()));
+		cm.state.lock().unwrap().insert(
+			ServerKey::new(&release, Some("alice".to_string())),
+			VersionState {
+				downloaded: false,
+				socket_path,
+				shutdown_at: ShutdownAt::default(),
+				progress: tokio::sync::watch::channel(DownloadProgress::Failed {
+					error: "network error".to_string(),
+				})
+				.1,
+				drain: Arc::new(tokio::sync::watch::channel(None).0),
+			},
+		);
+
+		// neither the user whose download failed nor another user start a new
+		// download, both are sent to the progress page showing the error
+		for user in ["alice", "bob"] {
+			assert!(matches!(
+				cm.get_version_data_inner(release.clone(), Some(user.to_string())),
+				Err(CodeError::ServerNotYetDownloaded)
+			));
+		}
+		assert_eq!(cm.state.lock().unwrap().len(), 1);
+
+		let progress = cm.get_download_progress(&release).unwrap();
+		assert!(matches!(
+			&*progress.borrow(),
+			DownloadProgress::Failed { error } if error == "network error"
+		));
+	}
+
+	fn insert_version(cm: &ConnectionManager, release: &Release, user: Option<&str>) {
+		cm.state.lock().unwrap().insert(
+			ServerKey::new(release, user.map(str::to_string)),
+			VersionState {
+				downloaded: true,
+				socket_path: new_barrier().0,
+				shutdown_at: ShutdownAt::default(),
+				progress: tokio::sync::watch::channel(DownloadProgress::Done).1,
+				drain: Arc::new(tokio::sync::watch::channel(None).0),
+			},
+		);
+	}
+
+	#[test]
+	fn test_drain_old_versions() {
+		let dir = tempfile::tempdir().unwrap();
+		let old = test_release(&"a".repeat(COMMIT_HASH_LEN));
+		let new = test_release(&"b".repeat(COMMIT_HASH_LEN));
+		let is_draining = |cm: &ConnectionManager, release: &Release, user: Option<&str>| {
+			cm.state.lock().unwrap()[&ServerKey::new(release, user.map(str::to_string))]
+				.drain
+				.borrow()
+				.is_some()
+		};
+
+		// nothing is drained without a grace period
+		let cm = test_manager(dir.path());
+		insert_version(&cm, &old, None);
+		let mut latest = Some((Instant::now(), old.clone()));
+		cm.set_latest_release(&mut latest, Instant::now(), new.clone());
+		assert!(!is_draining(&cm, &old, None));
+
+		let args = ServeWebArgs {
+			drain_grace_period: Some(60),
+			..Default::default()
+		};
+		let cm = test_manager_with_args(dir.path(), args);
+		insert_version(&cm, &old, None);
+		insert_version(&cm, &old, Some("alice"));
+		insert_version(&cm, &new, None);
+
+		// setting the same release again doesn't drain anything
+		let mut latest = Some((Instant::now(), old.clone()));
+		cm.set_latest_release(&mut latest, Instant::now(), old.clone());
+		assert!(!is_draining(&cm, &old, None));
+
+		// all servers of other versions are drained, including per-user ones
+		cm.set_latest_release(&mut latest, Instant::now(), new.clone());
+		assert!(is_draining(&cm, &old, None));
+		assert!(is_draining(&cm, &old, Some("alice")));
+		assert!(!is_draining(&cm, &new, None));
+
+		// clients are still routed to the old version during the grace period,
+		// and servers started for it then are drained too
+		assert!(!cm.is_drained(&old));
+		assert!(cm.drain_at(&old).is_some());
+		assert!(cm.drain_at(&new).is_none());
+		cm.drained
+			.lock()
+			.unwrap()
+			.insert(key_for_release(&old), Instant::now());
+		assert!(cm.is_drained(&old));
+		assert!(!cm.is_drained(&new));
+	}
+
+	#[test]
+	fn test_encode_user_name() {
+		assert_eq!(encode_user_name("alice"), "alice");
+		assert_eq!(
+			encode_user_name("alice.smith@example.com"),
+			"alice.smith@example.com"
+		);
+		assert_eq!(encode_user_name("Alice"), "%41lice");
+		assert_eq!(encode_user_name("../x"), "%2E.%2Fx");
+		assert_eq!(encode_user_name("a%2F"), "a%252%46");
+		assert_eq!(encode_user_name("é"), "%C3%A9");
+		assert_eq!(encode_user_name(""), "%");
+
+		// names that a lossy encoding would mix up stay distinct
+		let names = ["a/b", "a_b", "a b", "A_b", "a%2Fb", ".a", "%2Ea"];
+		let encoded: std::collections::HashSet<_> =
+			names.iter().map(|n| encode_user_name(n)).collect();
+		assert_eq!(encoded.len(), names.len());
+	}
+
+	#[test]
+	fn test_check_user_header() {
+		let args = |host: &str| ServeWebArgs {
+			host: vec![host.to_string()],
+			user_header: Some("X-Forwarded-User".to_string()),
+			without_connection_token: true,
+			..Default::default()
+		};
+
+		assert!(check_user_header(&args("127.0.0.1")).is_ok());
+		assert!(check_user_header(&args("unix:/run/code.sock")).is_ok());
+		assert!(matches!(
+			check_user_header(&args("0.0.0.0")),
+			Err(CodeError::UnsafeUserHeader(h)) if h == "0.0.0.0"
+		));
+
+		let with_token = ServeWebArgs {
+			without_connection_token: false,
+			..args("0.0.0.0")
+		};
+		assert!(check_user_header(&with_token).is_ok());
+		let with_tls = ServeWebArgs {
+			self_signed_cert: true,
+			..args("0.0.0.0")
+		};
+		assert!(check_user_header(&with_tls).is_ok());
+		let without_header = ServeWebArgs {
+			user_header: None,
+			..args("0.0.0.0")
+		};
+		assert!(check_user_header(&without_header).is_ok());
+	}
+
+	#[tokio::test]
+	async fn test_check_user_header_listeners() {
+		let args = ServeWebArgs {
+			user_header: Some("X-Forwarded-User".to_string()),
+			without_connection_token: true,
+			..Default::default()
+		};
+		let bind = |addr: &str| {
+			let addr = addr.to_string();
+			async move { listeners::Listener::Tcp(tokio::net::TcpListener::bind(addr).await.unwrap()) }
+		};
+
+		let local = vec![bind("127.0.0.1:0").await];
+		assert!(check_user_header_listeners(&args, &local).is_ok());
+
+		// such as one passed by the service manager, whatever the --host
+		let any = vec![bind("127.0.0.1:0").await, bind("0.0.0.0:0").await];
+		assert!(matches!(
+			check_user_header_listeners(&args, &any),
+			Err(CodeError::UnsafeUserHeader(addr)) if addr.starts_with("0.0.0.0:")
+		));
+		let with_token = ServeWebArgs {
+			without_connection_token: false,
+			..args.clone()
+		};
+		assert!(check_user_header_listeners(&with_token, &any).is_ok());
+	}
+
+	#[test]
+	fn test_kill_deadline() {
+		let now = Instant::now();
+		let idle = Duration::from_secs(60);
+		let active = Duration::from_secs(3600);
+
+		assert_eq!(kill_deadline(now, 0, None, idle, active), now + idle);
+		assert_eq!(kill_deadline(now, 2, None, idle, active), now + active);
+		// drained servers stop once idle, or at the end of the grace period
+		let drain_at = now + Duration::from_secs(30);
+		assert_eq!(kill_deadline(now, 0, Some(drain_at), idle, active), now);
+		assert_eq!(
+			kill_deadline(now, 1, Some(drain_at), idle, active),
+			drain_at
+		);
+		assert_eq!(
+			kill_deadline(now, 1, Some(now + active * 2), idle, active),
+			now + active
+		);
+	}
+
+	#[test]
+	fn test_get_token_status() {
+		let token = Some("secret");
+		let status = |req: &Request<Body>| match get_token_status(req, token) {
+			TokenStatus::Valid => "valid",
+			TokenStatus::Invalid => "invalid",
+			TokenStatus::Missing => "missing",
+		};
+
+		assert_eq!(status(&request("/?tkn=secret", &[])), "valid");
+		assert_eq!(
+			status(&request("/", &[("Cookie", "a=b; vscode-tkn=secret")])),
+			"valid"
+		);
+		assert_eq!(
+			status(&request("/", &[("Authorization", "Bearer secret")])),
+			"valid"
+		);
+		assert_eq!(status(&request("/?tkn=secre", &[])), "invalid");
+		assert_eq!(
+			status(&request("/", &[("Authorization", "Basic secret")])),
+			"missing"
+		);
+		assert_eq!(status(&request("/", &[])), "missing");
+		assert!(matches!(
+			get_token_status(&request("/", &[]), None),
+			TokenStatus::Valid
+		));
+	}
+
+	#[test]
+	fn test_check_token_with_limit() {
+		let dir = tempfile::tempdir().unwrap();
+		let args = ServeWebArgs {
+			connection_token: Some("secret".to_string()),
+			..Default::default()
+		};
+		let context = |remote_addr: Option<&str>| HandleContext {
+			cm: test_manager_with_args(dir.path(), args.clone()),
+			log: log::Logger::test(),
+			server_secret_key: SecretKeyPart::new(),
+			access_log: None,
+			auth: None,
+			response_headers: None,
+			remote_addr: remote_addr.map(|a| a.parse().unwrap()),
+		};
+		let status = |ctx: &HandleContext, uri: &str, required: bool| {
+			check_token_with_limit(ctx, &request(uri, &[]), required)
+				.map(|r| r.status().as_u16())
+				.unwrap_or(200)
+		};
+
+		let ctx = context(Some("10.0.0.1:1234"));
+		assert_eq!(status(&ctx, "/", false), 200);
+		assert_eq!(status(&ctx, "/", true), 401);
+		assert!((0..10).any(|_| status(&ctx, "/?tkn=wrong", true) == 429));
+
+		// nothing gets through while the address is locked out, so guesses
+		// aren't answered
+		assert_eq!(status(&ctx, "/?tkn=secret", true), 429);
+		assert_eq!(status(&ctx, "/?tkn=secret", false), 429);
+		assert_eq!(status(&ctx, "/?tkn=wrong", false), 429);
+
+		// connections without an address are never locked out
+		let ctx = context(None);
+		for _ in 0..20 {
+			assert_eq!(status(&ctx, "/?tkn=wrong", true), 401);
+		}
+		assert_eq!(status(&ctx, "/?tkn=secret", true), 200);
+	}
+
+	/// Gets a connection that looks ready, but is closed by the other end as
+	/// soon as a request is sent on it, which is signaled on the receiver.
+	async fn stale_connection() -> (
+		hyper::client::conn::SendRequest<Body>,
+		tokio::sync::oneshot::Receiver<()>,
+	) {
+		use tokio::io::AsyncReadExt;
+
+		let (client, mut server) = tokio::io::duplex(4096);
+		let (used_tx, used_rx) = tokio::sync::oneshot::channel();
+		tokio::spawn(async move {
+			let mut buf = [0; 1024];
+			let _ = server.read(&mut buf).await;
+			let _ = used_tx.send(());
+		});
+		let (sender, connection) = hyper::client::conn::Builder::new()
+			.handshake(client)
+			.await
+			.unwrap();
+		tokio::spawn(connection);
+		tokio::task::yield_now().await;
+		(sender, used_rx)
+	}
+
+	#[tokio::test]
+	async fn test_forward_retries_stale_pooled_connection() {
+		use hyper::server::conn::Http;
+		use hyper::service::service_fn;
+
+		let socket_path = get_socket_name();
+		let mut listener = crate::async_pipe::listen_socket_rw_stream(&socket_path)
+			.await
+			.unwrap();
+		tokio::spawn(async move {
+			while let Ok(rw) = listener.accept().await {
+				tokio::spawn(Http::new().serve_connection(
+					rw,
+					service_fn(|_req| async {
+						Ok::<_, Infallible>(Response::new(Body::from("ok")))
+					}),
+				));
+			}
+		});
+
+		let pool = ConnectionPool::default();
+		let forward = |req: Request<Body>| {
+			let handle = ConnectionHandle::new(Arc::new(tokio::sync::watch::channel(0).0));
+			forward_http_req_to_server(&pool, (socket_path.clone(), handle), req)
+		};
+
+		// the stale connection is evicted and the request sent again
+		let (stale, used) = stale_connection().await;
+		pool.put(&socket_path, stale);
+		let res = forward(request("/", &[])).await;
+		assert!(used.await.is_ok());
+		assert_eq!(res.status(), 200);
+		assert_eq!(hyper::body::to_bytes(res.into_body()).await.unwrap(), "ok");
+
+		// requests that aren't safe to send twice are not retried
+		pool.evict(&socket_path);
+		let (stale, used) = stale_connection().await;
+		pool.put(&socket_path, stale);
+		let post = Request::post("/").body(Body::from("data")).unwrap();
+		assert_eq!(forward(post).await.status(), 503);
+		assert!(used.await.is_ok());
+
+		let _ = std::fs::remove_file(&socket_path);
+	}
+
+	#[test]
+	fn test_copy_for_retry() {
+		let get = request("/a?b", &[("Cookie", "c=d")]);
+		let copy = copy_for_retry(&get).unwrap();
+		assert_eq!(copy.uri(), "/a?b");
+		assert_eq!(copy.headers()["Cookie"], "c=d");
+
+		assert!(copy_for_retry(&Request::post("/").body(Body::empty()).unwrap()).is_none());
+		assert!(copy_for_retry(&Request::put("/").body(Body::from("x")).unwrap()).is_none());
+	}
+
+	#[test]
+	fn test_is_oidc_login_bound() {
+		let cookie = format!(
+			"{}={}",
+			OIDC_LOGIN_COOKIE_NAME,
+			auth::oidc_state_binding("state")
+		);
+		let req = request("/", &[("Cookie", &cookie)]);
+		assert!(is_oidc_login_bound(&req, "state"));
+		// the callback of another login, such as the attacker's own
+		assert!(!is_oidc_login_bound(&req, "other-state"));
+		assert!(!is_oidc_login_bound(&request("/", &[]), "state"));
+	}
+
+	#[test]
+	fn test_constant_time_eq() {
+		assert!(constant_time_eq(b"secret", b"secret"));
+		assert!(!constant_time_eq(b"secret", b"secreT"));
+		assert!(!constant_time_eq(b"secret", b"secret2"));
+		assert!(constant_time_eq(b"", b""));
+	}
+
+	#[test]
+	fn test_is_commit_hash() {
+		assert!(is_commit_hash("0123456789abcdef0123456789ABCDEF01234567"));
+		assert!(!is_commit_hash("0123456789abcdef"));
+		assert!(!is_commit_hash("0123456789abcdef0123456789abcdef0123456g"));
+		assert!(!is_commit_hash("1.90.0"));
+	}
+}
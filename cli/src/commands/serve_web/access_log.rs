@@ -0,0 +1,255 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::fs;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::commands::args::{AccessLogFormat, ServeWebArgs};
+use crate::util::errors::CodeError;
+
+/// `--access-log` value that writes the log to stdout.
+const STDOUT_PATH: &str = "-";
+/// Number of rotated access log files to keep, as `<path>.1` to `<path>.N`.
+const MAX_ROTATED_FILES: u32 = 5;
+
+/// A single request, as recorded in the access log.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessLogEntry {
+	pub time: DateTime<Local>,
+	pub remote_addr: Option<SocketAddr>,
+	/// User signed in with `--auth-config`, if any.
+	pub user: Option<String>,
+	pub method: String,
+	/// Request path and query, with the connection token removed.
+	pub path: String,
+	pub version: String,
+	pub status: u16,
+	/// Size of the response body, if known up front.
+	pub bytes: Option<u64>,
+	pub duration_ms: u64,
+	/// Commit of the server release the request was served by, if any.
+	pub commit: Option<String>,
+	pub websocket: bool,
+	pub referer: Option<String>,
+	pub user_agent: Option<String>,
+}
+
+impl AccessLogEntry {
+	pub fn set_duration(&mut self, duration: Duration) {
+		self.duration_ms = duration.as_millis() as u64;
+	}
+
+	/// Formats the entry in the combined log format, followed by the duration
+	/// in milliseconds, the release commit, and whether it was a WebSocket.
+	fn to_combined(&self) -> String {
+		format!(
+			"{} - {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {} {} {}\n",
+			self.remote_addr
+				.map(|a| a.ip().to_string())
+				.unwrap_or_else(|| "-".to_string()),
+			self.user
+				.as_deref()
+				.map(|u| u.replace(' ', "_"))
+				.unwrap_or_else(|| "-".to_string()),
+			self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+			self.method,
+			escape(&self.path),
+			self.version,
+			self.status,
+			self.bytes
+				.map(|b| b.to_string())
+				.unwrap_or_else(|| "-".to_string()),
+			escape(self.referer.as_deref().unwrap_or("-")),
+			escape(self.user_agent.as_deref().unwrap_or("-")),
+			self.duration_ms,
+			self.commit.as_deref().unwrap_or("-"),
+			if self.websocket { "ws" } else { "-" },
+		)
+	}
+
+	fn to_json(&self) -> String {
+		let mut line = serde_json::to_string(self).unwrap();
+		line.push('\n');
+		line
+	}
+}
+
+/// Escapes a value that's written within quotes in the combined log format.
+fn escape(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Removes the connection token from the query string of a request path, so
+/// that it doesn't end up in logs.
+pub fn strip_connection_token(path_and_query: &str, token_param: &str) -> String {
+	let (path, query) = match path_and_query.split_once('?') {
+		Some(pq) => pq,
+		None => return path_and_query.to_string(),
+	};
+
+	let query: Vec<&str> = query
+		.split('&')
+		.filter(|p| p.split('=').next() != Some(token_param))
+		.collect();
+	if query.is_empty() {
+		path.to_string()
+	} else {
+		format!("{}?{}", path, query.join("&"))
+	}
+}
+
+enum Sink {
+	Stdout,
+	File(RotatingFile),
+}
+
+/// Log of the HTTP requests handled by serve-web, written to stdout or to a
+/// file that's rotated once it grows past a maximum size.
+pub struct AccessLog {
+	format: AccessLogFormat,
+	sink: Mutex<Sink>,
+}
+
+impl AccessLog {
+	/// Opens the access log requested in the arguments, if any.
+	pub fn open(args: &ServeWebArgs) -> Result<Option<AccessLog>, CodeError> {
+		let sink = match args.access_log.as_deref() {
+			None => return Ok(None),
+			Some(STDOUT_PATH) => Sink::Stdout,
+			Some(path) => Sink::File(
+				RotatingFile::open(
+					PathBuf::from(path),
+					args.access_log_max_size.saturating_mul(1024 * 1024),
+					MAX_ROTATED_FILES,
+				)
+				.map_err(CodeError::CouldNotOpenAccessLog)?,
+			),
+		};
+
+		Ok(Some(AccessLog {
+			format: args.access_log_format,
+			sink: Mutex::new(sink),
+		}))
+	}
+
+	pub fn write(&self, entry: &AccessLogEntry) {
+		let line = match self.format {
+			AccessLogFormat::Combined => entry.to_combined(),
+			AccessLogFormat::Json => entry.to_json(),
+		};
+
+		// ignore any errors, not much we can do if logging fails...
+		match &mut *self.sink.lock().unwrap() {
+			Sink::Stdout => {
+				io::stdout().write_all(line.as_bytes()).ok();
+			}
+			Sink::File(f) => {
+				f.write(line.as_bytes()).ok();
+			}
+		}
+	}
+}
+
+/// File that's appended to, and rotated once it grows past a maximum size.
+pub struct RotatingFile {
+	path: PathBuf,
+	file: fs::File,
+	size: u64,
+	max_size: u64,
+	/// Number of rotated files to keep, as `<path>.1` to `<path>.N`
+	max_rotated: u32,
+}
+
+impl RotatingFile {
+	pub fn open(path: PathBuf, max_size: u64, max_rotated: u32) -> io::Result<Self> {
+		let file = open_append(&path)?;
+		let size = file.metadata()?.len();
+		Ok(Self {
+			path,
+			file,
+			size,
+			max_size,
+			max_rotated,
+		})
+	}
+
+	pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+		if self.size > 0 && self.size + data.len() as u64 > self.max_size {
+			self.rotate()?;
+		}
+
+		self.file.write_all(data)?;
+		self.size += data.len() as u64;
+		Ok(())
+	}
+
+	/// Moves the current file to `<path>.1`, shifting older files up by one
+	/// and deleting the oldest, then starts a new file.
+	fn rotate(&mut self) -> io::Result<()> {
+		let _ = fs::remove_file(rotated_path(&self.path, self.max_rotated));
+		for i in (1..self.max_rotated).rev() {
+			let _ = fs::rename(rotated_path(&self.path, i), rotated_path(&self.path, i + 1));
+		}
+		fs::rename(&self.path, rotated_path(&self.path, 1))?;
+
+		self.file = open_append(&self.path)?;
+		self.size = 0;
+		Ok(())
+	}
+}
+
+fn open_append(path: &Path) -> io::Result<fs::File> {
+	fs::OpenOptions::new().append(true).create(true).open(path)
+}
+
+fn rotated_path(path: &Path, n: u32) -> PathBuf {
+	let mut p = path.as_os_str().to_owned();
+	p.push(format!(".{}", n));
+	PathBuf::from(p)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_strip_connection_token() {
+		assert_eq!(strip_connection_token("/a/b", "tkn"), "/a/b");
+		assert_eq!(strip_connection_token("/?tkn=secret", "tkn"), "/");
+		assert_eq!(
+			strip_connection_token("/?folder=x&tkn=secret&y", "tkn"),
+			"/?folder=x&y"
+		);
+		assert_eq!(strip_connection_token("/?tknx=1", "tkn"), "/?tknx=1");
+	}
+
+	#[test]
+	fn test_rotating_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("access.log");
+		let mut f = RotatingFile::open(path.clone(), 10, 2).unwrap();
+		f.write(b"12345678\n").unwrap();
+		f.write(b"abcdefgh\n").unwrap();
+		f.write(b"ABCDEFGH\n").unwrap();
+
+		assert_eq!(fs::read_to_string(&path).unwrap(), "ABCDEFGH\n");
+		assert_eq!(
+			fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+			"abcdefgh\n"
+		);
+		assert_eq!(
+			fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+			"12345678\n"
+		);
+	}
+}
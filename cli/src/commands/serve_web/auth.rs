@@ -0,0 +1,712 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::util::errors::{wrap, AnyError, CodeError};
+
+/// Login page, where local users can sign in or the OIDC flow is started.
+pub const LOGIN_PATH: &str = "_vscode-cli/login";
+/// Redirects to the OIDC provider to sign in.
+pub const OIDC_LOGIN_PATH: &str = "_vscode-cli/login/oidc";
+/// Where the OIDC provider redirects back to after signing in.
+pub const OIDC_CALLBACK_PATH: &str = "_vscode-cli/login/callback";
+/// Ends the session and returns to the login page.
+pub const LOGOUT_PATH: &str = "_vscode-cli/logout";
+
+/// Gets whether the path, relative to the base path, is handled by the login flow.
+pub fn is_auth_path(path: &str) -> bool {
+	matches!(
+		path,
+		LOGIN_PATH | OIDC_LOGIN_PATH | OIDC_CALLBACK_PATH | LOGOUT_PATH
+	)
+}
+
+/// Prefix of password hashes in the config file.
+const PASSWORD_HASH_SCHEME: &str = "pbkdf2-sha256";
+/// Default lifetime of a session after logging in.
+const DEFAULT_SESSION_TTL_SECS: u64 = 60 * 60 * 24 * 7;
+/// Time a user has to finish signing in with the OIDC provider.
+pub const PENDING_LOGIN_TTL: Duration = Duration::from_secs(60 * 10);
+/// Scopes requested from the OIDC provider if none are configured.
+const DEFAULT_OIDC_SCOPES: &str = "openid profile email";
+/// Prefix of the names of users signed in with OIDC. Local user names can't
+/// contain a `:`, so the two never collide.
+const OIDC_USER_PREFIX: &str = "oidc:";
+
+/// Config file given in `--auth-config`, in JSON.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthConfig {
+	/// Users who can sign in with a password.
+	#[serde(default)]
+	pub users: Vec<LocalUser>,
+	/// OpenID Connect provider users can sign in with.
+	pub oidc: Option<OidcConfig>,
+	/// How long a login lasts, in seconds.
+	pub session_ttl_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalUser {
+	pub name: String,
+	/// Hash in the format `pbkdf2-sha256$<iterations>$<base64 salt>$<base64 hash>`
+	pub password_hash: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcConfig {
+	/// Issuer URL, under which `.well-known/openid-configuration` is served.
+	pub issuer: String,
+	pub client_id: String,
+	pub client_secret: Option<String>,
+	/// URL of the OIDC callback on this server, as registered with the
+	/// provider, e.g. `https://example.com/_vscode-cli/login/callback`
+	pub redirect_uri: String,
+	pub scopes: Option<String>,
+	/// If given, only these users, matched by their subject or by their email
+	/// if the provider verified it, are allowed to sign in.
+	#[serde(default)]
+	pub allowed_users: Vec<String>,
+}
+
+impl AuthConfig {
+	pub fn read(path: &str) -> Result<AuthConfig, CodeError> {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|e| CodeError::InvalidAuthConfig(e.to_string()))?;
+		let config: AuthConfig = serde_json::from_str(&contents)
+			.map_err(|e| CodeError::InvalidAuthConfig(e.to_string()))?;
+
+		if config.users.is_empty() && config.oidc.is_none() {
+			return Err(CodeError::InvalidAuthConfig(
+				"at least one user or an OIDC provider must be configured".to_string(),
+			));
+		}
+		for user in &config.users {
+			if user.name.contains(':') {
+				return Err(CodeError::InvalidAuthConfig(format!(
+					"user name {} can't contain a ':'",
+					user.name
+				)));
+			}
+			if PasswordHash::parse(&user.password_hash).is_none() {
+				return Err(CodeError::InvalidAuthConfig(format!(
+					"invalid password hash for user {}",
+					user.name
+				)));
+			}
+		}
+
+		Ok(config)
+	}
+}
+
+struct Session {
+	user: String,
+	expires_at: Instant,
+}
+
+struct PendingLogin {
+	nonce: String,
+	return_to: String,
+	created_at: Instant,
+}
+
+/// Endpoints of the OIDC provider, from its discovery document.
+#[derive(Deserialize, Clone)]
+struct ProviderMetadata {
+	issuer: String,
+	authorization_endpoint: String,
+	token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+	id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+	iss: String,
+	sub: String,
+	aud: Audience,
+	exp: u64,
+	nonce: Option<String>,
+	email: Option<String>,
+	email_verified: Option<bool>,
+}
+
+impl IdTokenClaims {
+	/// Gets the user's email, if the provider verified it belongs to them.
+	fn verified_email(&self) -> Option<&str> {
+		match self.email_verified {
+			Some(true) => self.email.as_deref(),
+			_ => None,
+		}
+	}
+
+	/// Gets the name the user is known by, from the issuer and subject which
+	/// together uniquely and permanently identify them.
+	fn user_name(&self) -> String {
+		format!(
+			"{}{}#{}",
+			OIDC_USER_PREFIX,
+			self.iss.trim_end_matches('/'),
+			self.sub
+		)
+	}
+
+	fn is_allowed(&self, allowed_users: &[String]) -> bool {
+		allowed_users.is_empty()
+			|| allowed_users
+				.iter()
+				.any(|a| *a == self.sub || Some(a.as_str()) == self.verified_email())
+	}
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+	One(String),
+	Many(Vec<String>),
+}
+
+impl Audience {
+	fn contains(&self, client_id: &str) -> bool {
+		match self {
+			Audience::One(a) => a == client_id,
+			Audience::Many(a) => a.iter().any(|a| a == client_id),
+		}
+	}
+}
+
+/// Signs in users with a password or through an OIDC provider, and tracks
+/// their sessions. Sessions are kept in memory, so users need to sign in
+/// again when serve-web restarts.
+pub struct Authenticator {
+	users: HashMap<String, String>,
+	oidc: Option<OidcConfig>,
+	http: reqwest::Client,
+	session_ttl: Duration,
+	provider: tokio::sync::Mutex<Option<ProviderMetadata>>,
+	sessions: Mutex<HashMap<String, Session>>,
+	pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+impl Authenticator {
+	pub fn new(config: AuthConfig, http: reqwest::Client) -> Self {
+		Self {
+			users: config
+				.users
+				.into_iter()
+				.map(|u| (u.name, u.password_hash))
+				.collect(),
+			oidc: config.oidc,
+			http,
+			session_ttl: Duration::from_secs(
+				config.session_ttl_secs.unwrap_or(DEFAULT_SESSION_TTL_SECS),
+			),
+			provider: tokio::sync::Mutex::default(),
+			sessions: Mutex::default(),
+			pending: Mutex::default(),
+		}
+	}
+
+	pub fn session_ttl(&self) -> Duration {
+		self.session_ttl
+	}
+
+	pub fn has_password_login(&self) -> bool {
+		!self.users.is_empty()
+	}
+
+	pub fn has_oidc_login(&self) -> bool {
+		self.oidc.is_some()
+	}
+
+	/// Gets the user signed in with the given session ID, if it's valid.
+	pub fn get_session_user(&self, session_id: &str) -> Option<String> {
+		let mut sessions = self.sessions.lock().unwrap();
+		let now = Instant::now();
+		sessions.retain(|_, s| s.expires_at > now);
+		sessions.get(session_id).map(|s| s.user.clone())
+	}
+
+	/// Starts a session for the user, returning its ID.
+	pub fn create_session(&self, user: String) -> String {
+		let id = random_token();
+		self.sessions.lock().unwrap().insert(
+			id.clone(),
+			Session {
+				user,
+				expires_at: Instant::now() + self.session_ttl,
+			},
+		);
+		id
+	}
+
+	pub fn end_session(&self, session_id: &str) {
+		self.sessions.lock().unwrap().remove(session_id);
+	}
+
+	/// Checks a local user's password.
+	pub fn check_password(&self, user: &str, password: &str) -> bool {
+		// Hash even for unknown users, so timing doesn't reveal which exist
+		let hash = self
+			.users
+			.get(user)
+			.and_then(|h| PasswordHash::parse(h))
+			.unwrap_or_else(PasswordHash::dummy);
+		hash.verify(password) && self.users.contains_key(user)
+	}
+
+	/// Gets the URL to send the user to in order to sign in with the OIDC
+	/// provider, and the binding of the login to set in their browser, see
+	/// `oidc_state_binding`. They'll be sent back to `return_to` once signed in.
+	pub async fn start_oidc_login(&self, return_to: String) -> Result<(String, String), AnyError> {
+		let oidc = self.oidc.as_ref().ok_or(CodeError::OidcNotConfigured)?;
+		let provider = self.get_provider().await?;
+
+		let state = random_token();
+		let nonce = random_token();
+		let mut url = url::Url::parse(&provider.authorization_endpoint)
+			.map_err(|e| wrap(e, "invalid authorization_endpoint"))?;
+		url.query_pairs_mut()
+			.append_pair("response_type", "code")
+			.append_pair("client_id", &oidc.client_id)
+			.append_pair("redirect_uri", &oidc.redirect_uri)
+			.append_pair(
+				"scope",
+				oidc.scopes.as_deref().unwrap_or(DEFAULT_OIDC_SCOPES),
+			)
+			.append_pair("state", &state)
+			.append_pair("nonce", &nonce);
+
+		let binding = oidc_state_binding(&state);
+		let mut pending = self.pending.lock().unwrap();
+		pending.retain(|_, p| p.created_at.elapsed() < PENDING_LOGIN_TTL);
+		pending.insert(
+			state,
+			PendingLogin {
+				nonce,
+				return_to,
+				created_at: Instant::now(),
+			},
+		);
+
+		Ok((url.to_string(), binding))
+	}
+
+	/// Exchanges the code the OIDC provider redirected back with for the
+	/// user's identity. Returns the user's name, see `IdTokenClaims::user_name`,
+	/// and where to send them next.
+	pub async fn finish_oidc_login(
+		&self,
+		code: &str,
+		state: &str,
+	) -> Result<(String, String), AnyError> {
+		let oidc = self.oidc.as_ref().ok_or(CodeError::OidcNotConfigured)?;
+		let pending = self
+			.pending
+			.lock()
+			.unwrap()
+			.remove(state)
+			.filter(|p| p.created_at.elapsed() < PENDING_LOGIN_TTL)
+			.ok_or_else(|| CodeError::OidcLoginFailed("unknown or expired state".to_string()))?;
+
+		let provider = self.get_provider().await?;
+		let mut form = vec![
+			("grant_type", "authorization_code"),
+			("code", code),
+			("redirect_uri", oidc.redirect_uri.as_str()),
+			("client_id", oidc.client_id.as_str()),
+		];
+		if let Some(secret) = &oidc.client_secret {
+			form.push(("client_secret", secret.as_str()));
+		}
+
+		let res = self
+			.http
+			.post(&provider.token_endpoint)
+			.form(&form)
+			.send()
+			.await?;
+		if !res.status().is_success() {
+			return Err(CodeError::OidcLoginFailed(format!(
+				"token endpoint returned {}",
+				res.status()
+			))
+			.into());
+		}
+		let tokens: TokenResponse = res.json().await?;
+
+		// The ID token came directly from the token endpoint over a connection
+		// we initiated, so per OIDC Core 3.1.3.7 its signature doesn't need to
+		// be checked, but its claims do.
+		let claims = decode_id_token(&tokens.id_token)?;
+		if claims.iss.trim_end_matches('/') != provider.issuer.trim_end_matches('/') {
+			return Err(CodeError::OidcLoginFailed("issuer mismatch".to_string()).into());
+		}
+		if !claims.aud.contains(&oidc.client_id) {
+			return Err(CodeError::OidcLoginFailed("audience mismatch".to_string()).into());
+		}
+		if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+			return Err(CodeError::OidcLoginFailed("nonce mismatch".to_string()).into());
+		}
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs();
+		if claims.exp < now {
+			return Err(CodeError::OidcLoginFailed("ID token expired".to_string()).into());
+		}
+
+		if !claims.is_allowed(&oidc.allowed_users) {
+			return Err(
+				CodeError::OidcLoginFailed(format!("user {} is not allowed", claims.sub)).into(),
+			);
+		}
+
+		Ok((claims.user_name(), pending.return_to))
+	}
+
+	/// Gets the provider's endpoints, fetching its discovery document the
+	/// first time this is called.
+	async fn get_provider(&self) -> Result<ProviderMetadata, AnyError> {
+		let mut provider = self.provider.lock().await;
+		if let Some(p) = &*provider {
+			return Ok(p.clone());
+		}
+
+		let oidc = self.oidc.as_ref().ok_or(CodeError::OidcNotConfigured)?;
+		let url = format!(
+			"{}/.well-known/openid-configuration",
+			oidc.issuer.trim_end_matches('/')
+		);
+		let res = self.http.get(&url).send().await?;
+		if !res.status().is_success() {
+			return Err(
+				CodeError::OidcLoginFailed(format!("{} returned {}", url, res.status())).into(),
+			);
+		}
+
+		let metadata: ProviderMetadata = res.json().await?;
+		*provider = Some(metadata.clone());
+		Ok(metadata)
+	}
+}
+
+/// Reads the claims of an ID token, without checking its signature.
+fn decode_id_token(token: &str) -> Result<IdTokenClaims, CodeError> {
+	let payload = token
+		.split('.')
+		.nth(1)
+		.ok_or_else(|| CodeError::OidcLoginFailed("malformed ID token".to_string()))?;
+	let payload = general_purpose::URL_SAFE_NO_PAD
+		.decode(payload.trim_end_matches('='))
+		.map_err(|e| CodeError::OidcLoginFailed(format!("malformed ID token: {}", e)))?;
+	serde_json::from_slice(&payload)
+		.map_err(|e| CodeError::OidcLoginFailed(format!("malformed ID token: {}", e)))
+}
+
+/// Gets the value stored in the browser that started an OIDC login, which
+/// the callback must come with. Otherwise anyone could send a victim the
+/// callback URL of their own login, signing the victim in as themselves.
+pub fn oidc_state_binding(state: &str) -> String {
+	general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(state.as_bytes()))
+}
+
+fn random_token() -> String {
+	let bytes: [u8; 32] = rand::random();
+	general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Gets a path that's safe to redirect to after login, which must be a path
+/// on this server rather than an absolute URL.
+pub fn safe_return_to(return_to: Option<&str>, base_path: &str) -> String {
+	match return_to {
+		Some(r) if r.starts_with('/') && !r.starts_with("//") && !r.contains('\\') => r.to_string(),
+		_ => base_path.to_string(),
+	}
+}
+
+/// A PBKDF2-HMAC-SHA256 password hash.
+struct PasswordHash {
+	iterations: u32,
+	salt: Vec<u8>,
+	hash: Vec<u8>,
+}
+
+impl PasswordHash {
+	/// Parses a hash in the format `pbkdf2-sha256$<iterations>$<salt>$<hash>`,
+	/// where the salt and hash are base64 encoded.
+	fn parse(s: &str) -> Option<Self> {
+		let mut parts = s.split('$');
+		if parts.next() != Some(PASSWORD_HASH_SCHEME) {
+			return None;
+		}
+
+		let iterations = parts.next()?.parse().ok().filter(|i| *i > 0)?;
+		let salt = general_purpose::STANDARD.decode(parts.next()?).ok()?;
+		let hash = general_purpose::STANDARD.decode(parts.next()?).ok()?;
+		if parts.next().is_some() || hash.is_empty() {
+			return None;
+		}
+
+		Some(Self {
+			iterations,
+			salt,
+			hash,
+		})
+	}
+
+	/// Hash that never matches, used to check passwords of unknown users.
+	fn dummy() -> Self {
+		Self {
+			iterations: 100_000,
+			salt: vec![0; 16],
+			hash: vec![0; 32],
+		}
+	}
+
+	fn verify(&self, password: &str) -> bool {
+		let actual = pbkdf2_sha256(
+			password.as_bytes(),
+			&self.salt,
+			self.iterations,
+			self.hash.len(),
+		);
+		super::constant_time_eq(&actual, &self.hash)
+	}
+}
+
+fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+	const BLOCK_SIZE: usize = 64;
+
+	let mut block = [0u8; BLOCK_SIZE];
+	if key.len() > BLOCK_SIZE {
+		block[..32].copy_from_slice(&Sha256::digest(key));
+	} else {
+		block[..key.len()].copy_from_slice(key);
+	}
+
+	let mut inner = Sha256::new();
+	inner.update(block.map(|b| b ^ 0x36));
+	for d in data {
+		inner.update(d);
+	}
+
+	let mut outer = Sha256::new();
+	outer.update(block.map(|b| b ^ 0x5c));
+	outer.update(inner.finalize());
+	outer.finalize().into()
+}
+
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, len: usize) -> Vec<u8> {
+	let mut out = Vec::with_capacity(len);
+	let mut block_index: u32 = 1;
+	while out.len() < len {
+		let mut u = hmac_sha256(password, &[salt, &block_index.to_be_bytes()]);
+		let mut t = u;
+		for _ in 1..iterations {
+			u = hmac_sha256(password, &[&u]);
+			for (t, u) in t.iter_mut().zip(u.iter()) {
+				*t ^= u;
+			}
+		}
+
+		let take = (len - out.len()).min(t.len());
+		out.extend_from_slice(&t[..take]);
+		block_index += 1;
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_pbkdf2_sha256() {
+		// RFC 7914 section 11 test vector
+		let out = pbkdf2_sha256(b"passwd", b"salt", 1, 64);
+		assert_eq!(
+			out[..16],
+			[
+				0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f, 0xec, 0x16, 0x91, 0xc2, 0x25, 0x44,
+				0xb6, 0x05
+			]
+		);
+	}
+
+	#[test]
+	fn test_password_hash() {
+		let salt = b"0123456789abcdef";
+		let hash = pbkdf2_sha256(b"hunter2", salt, 1000, 32);
+		let encoded = format!(
+			"{}$1000${}${}",
+			PASSWORD_HASH_SCHEME,
+			general_purpose::STANDARD.encode(salt),
+			general_purpose::STANDARD.encode(hash)
+		);
+
+		let parsed = PasswordHash::parse(&encoded).unwrap();
+		assert!(parsed.verify("hunter2"));
+		assert!(!parsed.verify("hunter3"));
+		assert!(PasswordHash::parse("md5$1$abc$def").is_none());
+	}
+
+	#[test]
+	fn test_safe_return_to() {
+		assert_eq!(safe_return_to(Some("/a?b"), "/"), "/a?b");
+		assert_eq!(safe_return_to(Some("//evil.com"), "/"), "/");
+		assert_eq!(safe_return_to(Some("https://evil.com"), "/base/"), "/base/");
+		assert_eq!(safe_return_to(None, "/"), "/");
+	}
+
+	#[test]
+	fn test_id_token_identity() {
+		let claims = |email_verified: Option<bool>| IdTokenClaims {
+			iss: "https://issuer.example.com/".to_string(),
+			sub: "1234".to_string(),
+			aud: Audience::One("client".to_string()),
+			exp: 0,
+			nonce: None,
+			email: Some("alice@example.com".to_string()),
+			email_verified,
+		};
+		let allowed = |users: &[&str]| users.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+
+		let verified = claims(Some(true));
+		assert_eq!(verified.user_name(), "oidc:https://issuer.example.com#1234");
+		assert!(verified.is_allowed(&[]));
+		assert!(verified.is_allowed(&allowed(&["alice@example.com"])));
+		assert!(verified.is_allowed(&allowed(&["1234"])));
+		assert!(!verified.is_allowed(&allowed(&["bob@example.com"])));
+
+		// unverified emails could be set to anything by the user
+		for unverified in [claims(None), claims(Some(false))] {
+			assert_eq!(unverified.verified_email(), None);
+			assert!(!unverified.is_allowed(&allowed(&["alice@example.com"])));
+			assert!(unverified.is_allowed(&allowed(&["1234"])));
+		}
+	}
+
+	#[test]
+	fn test_local_user_names_cant_look_like_oidc_users() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("auth.json");
+		let hash = format!(
+			"{}$1$c2FsdA==${}",
+			PASSWORD_HASH_SCHEME,
+			general_purpose::STANDARD.encode(pbkdf2_sha256(b"pw", b"salt", 1, 32))
+		);
+		let write = |name: &str| {
+			let config = serde_json::json!({ "users": [{ "name": name, "passwordHash": hash }] });
+			std::fs::write(&path, config.to_string()).unwrap();
+			AuthConfig::read(path.to_str().unwrap())
+		};
+
+		assert!(write("alice").is_ok());
+		assert!(write("oidc:https://issuer.example.com#1234").is_err());
+	}
+
+	#[tokio::test]
+	async fn test_oidc_login_with_mock_provider() {
+		use hyper::service::{make_service_fn, service_fn};
+		use hyper::{Body, Request, Response, Server};
+		use std::convert::Infallible;
+		use std::sync::Arc;
+
+		// Nonce the mock provider puts in the ID token, set once the login
+		// URL is created.
+		let nonce = Arc::new(Mutex::new(String::new()));
+		let issuer = Arc::new(Mutex::new(String::new()));
+
+		let (n, i) = (nonce.clone(), issuer.clone());
+		let server =
+			Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service_fn(move |_| {
+				let (n, i) = (n.clone(), i.clone());
+				async move {
+					Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+						let (n, i) = (n.clone(), i.clone());
+						async move {
+							let issuer = i.lock().unwrap().clone();
+							let body = match req.uri().path() {
+								"/.well-known/openid-configuration" => serde_json::json!({
+									"issuer": issuer,
+									"authorization_endpoint": format!("{}/authorize", issuer),
+									"token_endpoint": format!("{}/token", issuer),
+								}),
+								"/token" => {
+									let claims = serde_json::json!({
+										"iss": issuer,
+										"sub": "1234",
+										"aud": "client",
+										"exp": u64::MAX / 2,
+										"nonce": *n.lock().unwrap(),
+										"email": "alice@example.com",
+										"email_verified": true,
+									});
+									let claims = general_purpose::URL_SAFE_NO_PAD
+										.encode(serde_json::to_vec(&claims).unwrap());
+									serde_json::json!({ "id_token": format!("e30.{}.sig", claims) })
+								}
+								_ => serde_json::json!({}),
+							};
+							Ok::<_, Infallible>(Response::new(Body::from(body.to_string())))
+						}
+					}))
+				}
+			}));
+		*issuer.lock().unwrap() = format!("http://{}", server.local_addr());
+		tokio::spawn(server);
+
+		let auth = Authenticator::new(
+			AuthConfig {
+				users: vec![],
+				oidc: Some(OidcConfig {
+					issuer: issuer.lock().unwrap().clone(),
+					client_id: "client".to_string(),
+					client_secret: Some("secret".to_string()),
+					redirect_uri: "http://localhost/_vscode-cli/login/callback".to_string(),
+					scopes: None,
+					allowed_users: vec!["alice@example.com".to_string()],
+				}),
+				session_ttl_secs: None,
+			},
+			reqwest::Client::new(),
+		);
+
+		let (url, binding) = auth.start_oidc_login("/folder".to_string()).await.unwrap();
+		let url = url::Url::parse(&url).unwrap();
+		let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+		*nonce.lock().unwrap() = query["nonce"].clone();
+		assert_eq!(binding, oidc_state_binding(&query["state"]));
+		assert_ne!(binding, oidc_state_binding("wrong-state"));
+
+		assert!(auth.finish_oidc_login("code", "wrong-state").await.is_err());
+		let (user, return_to) = auth
+			.finish_oidc_login("code", &query["state"])
+			.await
+			.unwrap();
+		let expected = format!("oidc:{}#1234", issuer.lock().unwrap());
+		assert_eq!(user, expected);
+		assert_eq!(return_to, "/folder");
+
+		let session = auth.create_session(user);
+		assert_eq!(auth.get_session_user(&session), Some(expected));
+		auth.end_session(&session);
+		assert!(auth.get_session_user(&session).is_none());
+	}
+}
@@ -0,0 +1,392 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::collections::HashMap;
+
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+
+use crate::commands::args::ServeWebArgs;
+use crate::log;
+use crate::util::errors::CodeError;
+
+/// How long browsers may cache the result of a CORS preflight request.
+const PREFLIGHT_MAX_AGE_SECS: u32 = 600;
+/// Methods allowed in cross-origin requests.
+const CORS_ALLOW_METHODS: &str = "GET, HEAD, POST, OPTIONS";
+/// Request headers allowed in cross-origin requests, besides the ones that
+/// are always allowed.
+const CORS_ALLOW_HEADERS: &str = "Authorization, Content-Type";
+/// Entry of the CORS allow-list that allows any origin.
+const ANY_ORIGIN: &str = "*";
+
+/// Origin allowed to make a cross-origin request.
+#[derive(Debug, PartialEq, Eq)]
+enum AllowedOrigin {
+	/// The request's origin was listed explicitly, so it may send credentials.
+	Listed(HeaderValue),
+	/// Any origin is allowed, without credentials, since browsers must not
+	/// share credentialed responses with arbitrary sites.
+	Any,
+}
+
+/// Contents of the `--headers-config` file.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct HeadersConfigFile {
+	headers: HashMap<String, String>,
+	hsts_max_age: Option<u64>,
+	frame_ancestors: Vec<String>,
+	cors_allow_origins: Vec<String>,
+}
+
+/// Headers added to or overridden on responses, configured with the
+/// `--response-header`, `--hsts-max-age`, `--frame-ancestors`, and
+/// `--cors-allow-origin` arguments and the `--headers-config` file.
+#[derive(Default)]
+pub struct ResponseHeaders {
+	headers: Vec<(HeaderName, HeaderValue)>,
+	hsts: Option<HeaderValue>,
+	/// `frame-ancestors` directive of the content security policy
+	frame_ancestors: Option<String>,
+	cors_origins: Vec<String>,
+}
+
+impl ResponseHeaders {
+	/// Reads the configured headers, returning None if there are none.
+	pub fn read(
+		log: &log::Logger,
+		args: &ServeWebArgs,
+		tls: bool,
+	) -> Result<Option<ResponseHeaders>, CodeError> {
+		let file = match &args.headers_config {
+			Some(path) => {
+				let contents = std::fs::read_to_string(path)
+					.map_err(|e| CodeError::InvalidHeadersConfig(e.to_string()))?;
+				serde_json::from_str(&contents)
+					.map_err(|e| CodeError::InvalidHeadersConfig(e.to_string()))?
+			}
+			None => HeadersConfigFile::default(),
+		};
+
+		let mut headers = Vec::new();
+		let mut file_headers: Vec<_> = file.headers.into_iter().collect();
+		file_headers.sort();
+		for (name, value) in file_headers {
+			headers.push(parse_header(&name, &value)?);
+		}
+		for header in &args.response_header {
+			let (name, value) = header.split_once(':').ok_or_else(|| {
+				CodeError::InvalidHeadersConfig(format!("expected `Name: value`, got `{}`", header))
+			})?;
+			headers.push(parse_header(name.trim(), value.trim())?);
+		}
+
+		let hsts = match args.hsts_max_age.or(file.hsts_max_age) {
+			Some(_) if !tls => {
+				warning!(log, "HSTS is only sent when serving over TLS, ignoring it");
+				None
+			}
+			Some(secs) => HeaderValue::from_str(&format!("max-age={}", secs)).ok(),
+			None => None,
+		};
+
+		let mut ancestors = file.frame_ancestors;
+		ancestors.extend(args.frame_ancestors.iter().cloned());
+		let frame_ancestors = match ancestors.is_empty() {
+			true => None,
+			false => Some(format!("frame-ancestors {}", ancestors.join(" "))),
+		};
+
+		let mut cors_origins = file.cors_allow_origins;
+		cors_origins.extend(args.cors_allow_origin.iter().cloned());
+
+		let headers = ResponseHeaders {
+			headers,
+			hsts,
+			frame_ancestors,
+			cors_origins,
+		};
+
+		if headers.headers.is_empty()
+			&& headers.hsts.is_none()
+			&& headers.frame_ancestors.is_none()
+			&& headers.cors_origins.is_empty()
+		{
+			Ok(None)
+		} else {
+			Ok(Some(headers))
+		}
+	}
+
+	/// Adds or overrides the configured headers on a response to a request
+	/// from the given origin.
+	pub fn apply(&self, origin: Option<&HeaderValue>, res: &mut Response<Body>) {
+		let headers = res.headers_mut();
+		for (name, value) in &self.headers {
+			headers.insert(name.clone(), value.clone());
+		}
+
+		if let Some(hsts) = &self.hsts {
+			headers.insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+		}
+
+		if let Some(frame_ancestors) = &self.frame_ancestors {
+			let csp = merge_frame_ancestors(
+				headers
+					.get(header::CONTENT_SECURITY_POLICY)
+					.and_then(|v| v.to_str().ok()),
+				frame_ancestors,
+			);
+			if let Ok(csp) = HeaderValue::from_str(&csp) {
+				headers.insert(header::CONTENT_SECURITY_POLICY, csp);
+			}
+			// superseded by frame-ancestors, and would otherwise still block embedding
+			headers.remove(header::X_FRAME_OPTIONS);
+		}
+
+		match self.allowed_origin(origin) {
+			Some(AllowedOrigin::Listed(origin)) => {
+				headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+				headers.insert(
+					header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+					HeaderValue::from_static("true"),
+				);
+			}
+			Some(AllowedOrigin::Any) => {
+				headers.insert(
+					header::ACCESS_CONTROL_ALLOW_ORIGIN,
+					HeaderValue::from_static(ANY_ORIGIN),
+				);
+				headers.remove(header::ACCESS_CONTROL_ALLOW_CREDENTIALS);
+			}
+			None => {}
+		}
+
+		// whether and which origin is allowed depends on the request's origin,
+		// even when none is, so caches must keep responses apart by origin
+		if !self.cors_origins.is_empty() && !varies_by_origin(headers) {
+			headers.append(header::VARY, HeaderValue::from_static("Origin"));
+		}
+	}
+
+	/// Gets whether the request is a CORS preflight from an allowed origin,
+	/// which is answered by `preflight_response`.
+	pub fn is_preflight(&self, req: &Request<Body>) -> bool {
+		req.method() == Method::OPTIONS
+			&& req
+				.headers()
+				.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+			&& self
+				.allowed_origin(req.headers().get(header::ORIGIN))
+				.is_some()
+	}
+
+	/// Answers a CORS preflight request with the fixed set of methods and
+	/// headers that cross-origin requests may use, regardless of what was
+	/// requested. The allowed origin is added by `apply`.
+	pub fn preflight_response(&self) -> Response<Body> {
+		Response::builder()
+			.status(StatusCode::NO_CONTENT)
+			.header(header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE_SECS)
+			.header(header::ACCESS_CONTROL_ALLOW_METHODS, CORS_ALLOW_METHODS)
+			.header(header::ACCESS_CONTROL_ALLOW_HEADERS, CORS_ALLOW_HEADERS)
+			.body(Body::empty())
+			.unwrap()
+	}
+
+	/// Gets the origin to allow in CORS responses, if the request's origin is
+	/// in the allow-list. Listed origins take precedence over `*`, which
+	/// allows any origin but without credentials.
+	fn allowed_origin(&self, origin: Option<&HeaderValue>) -> Option<AllowedOrigin> {
+		let origin = origin?;
+		let origin_str = origin.to_str().ok()?;
+		if self
+			.cors_origins
+			.iter()
+			.any(|o| o.trim_end_matches('/') == origin_str)
+		{
+			Some(AllowedOrigin::Listed(origin.clone()))
+		} else if self.cors_origins.iter().any(|o| o == ANY_ORIGIN) {
+			Some(AllowedOrigin::Any)
+		} else {
+			None
+		}
+	}
+}
+
+/// Gets whether the `Vary` headers already include `Origin`.
+fn varies_by_origin(headers: &hyper::HeaderMap) -> bool {
+	headers
+		.get_all(header::VARY)
+		.iter()
+		.filter_map(|v| v.to_str().ok())
+		.flat_map(|v| v.split(','))
+		.any(|v| v.trim().eq_ignore_ascii_case("origin") || v.trim() == "*")
+}
+
+fn parse_header(name: &str, value: &str) -> Result<(HeaderName, HeaderValue), CodeError> {
+	let name = HeaderName::from_bytes(name.as_bytes())
+		.map_err(|_| CodeError::InvalidHeadersConfig(format!("invalid header name `{}`", name)))?;
+	let value = HeaderValue::from_str(value).map_err(|_| {
+		CodeError::InvalidHeadersConfig(format!("invalid value for header `{}`", name))
+	})?;
+	Ok((name, value))
+}
+
+/// Replaces any `frame-ancestors` directive in a content security policy.
+fn merge_frame_ancestors(csp: Option<&str>, frame_ancestors: &str) -> String {
+	let mut directives: Vec<&str> = csp
+		.unwrap_or_default()
+		.split(';')
+		.map(|d| d.trim())
+		.filter(|d| !d.is_empty() && !d.starts_with("frame-ancestors"))
+		.collect();
+	directives.push(frame_ancestors);
+	directives.join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_merge_frame_ancestors() {
+		assert_eq!(
+			merge_frame_ancestors(None, "frame-ancestors 'self'"),
+			"frame-ancestors 'self'"
+		);
+		assert_eq!(
+			merge_frame_ancestors(
+				Some("default-src 'self'; frame-ancestors 'none';"),
+				"frame-ancestors https://portal.example.com"
+			),
+			"default-src 'self'; frame-ancestors https://portal.example.com"
+		);
+	}
+
+	#[test]
+	fn test_apply() {
+		let headers = ResponseHeaders {
+			headers: vec![parse_header("X-Custom", "1").unwrap()],
+			hsts: Some(HeaderValue::from_static("max-age=60")),
+			frame_ancestors: Some("frame-ancestors https://portal.example.com".to_string()),
+			cors_origins: vec!["https://portal.example.com/".to_string()],
+		};
+
+		let mut res = Response::builder()
+			.header(header::X_FRAME_OPTIONS, "DENY")
+			.body(Body::empty())
+			.unwrap();
+		let allowed = HeaderValue::from_static("https://portal.example.com");
+		headers.apply(Some(&allowed), &mut res);
+
+		let h = res.headers();
+		assert_eq!(h.get("x-custom").unwrap(), "1");
+		assert_eq!(
+			h.get(header::STRICT_TRANSPORT_SECURITY).unwrap(),
+			"max-age=60"
+		);
+		assert!(h.get(header::X_FRAME_OPTIONS).is_none());
+		assert_eq!(
+			h.get(header::CONTENT_SECURITY_POLICY).unwrap(),
+			"frame-ancestors https://portal.example.com"
+		);
+		assert_eq!(
+			h.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+			&allowed
+		);
+
+		let mut res = Response::new(Body::empty());
+		let other = HeaderValue::from_static("https://evil.example.com");
+		headers.apply(Some(&other), &mut res);
+		assert!(res
+			.headers()
+			.get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+			.is_none());
+
+		let req = Request::builder()
+			.method(Method::OPTIONS)
+			.header(header::ORIGIN, "https://portal.example.com")
+			.header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+			.header(header::ACCESS_CONTROL_REQUEST_HEADERS, "X-Anything")
+			.body(Body::empty())
+			.unwrap();
+		assert!(headers.is_preflight(&req));
+		let res = headers.preflight_response();
+		assert_eq!(res.status(), StatusCode::NO_CONTENT);
+		// requested methods and headers are not reflected
+		assert_eq!(
+			res.headers()
+				.get(header::ACCESS_CONTROL_ALLOW_METHODS)
+				.unwrap(),
+			CORS_ALLOW_METHODS
+		);
+		assert_eq!(
+			res.headers()
+				.get(header::ACCESS_CONTROL_ALLOW_HEADERS)
+				.unwrap(),
+			CORS_ALLOW_HEADERS
+		);
+	}
+
+	#[test]
+	fn test_any_origin_without_credentials() {
+		let headers = ResponseHeaders {
+			cors_origins: vec![
+				ANY_ORIGIN.to_string(),
+				"https://portal.example.com".to_string(),
+			],
+			..Default::default()
+		};
+		let listed = HeaderValue::from_static("https://portal.example.com");
+		let other = HeaderValue::from_static("https://other.example.com");
+		assert_eq!(
+			headers.allowed_origin(Some(&listed)),
+			Some(AllowedOrigin::Listed(listed.clone()))
+		);
+		assert_eq!(
+			headers.allowed_origin(Some(&other)),
+			Some(AllowedOrigin::Any)
+		);
+		assert_eq!(headers.allowed_origin(None), None);
+
+		let mut res = Response::new(Body::empty());
+		headers.apply(Some(&other), &mut res);
+		let h = res.headers();
+		assert_eq!(h.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+		assert!(h.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+		assert_eq!(h.get(header::VARY).unwrap(), "Origin");
+
+		let mut res = Response::new(Body::empty());
+		headers.apply(Some(&listed), &mut res);
+		let h = res.headers();
+		assert_eq!(h.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), &listed);
+		assert_eq!(
+			h.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(),
+			"true"
+		);
+		assert_eq!(h.get(header::VARY).unwrap(), "Origin");
+
+		// responses without an allowed origin still vary by it, and an
+		// existing `Vary: Origin` isn't repeated
+		let mut res = Response::new(Body::empty());
+		res.headers_mut().insert(
+			header::VARY,
+			HeaderValue::from_static("Accept-Encoding, origin"),
+		);
+		headers.apply(None, &mut res);
+		let vary: Vec<_> = res.headers().get_all(header::VARY).iter().collect();
+		assert_eq!(vary, vec!["Accept-Encoding, origin"]);
+		let mut res = Response::new(Body::empty());
+		headers.apply(None, &mut res);
+		assert_eq!(res.headers().get(header::VARY).unwrap(), "Origin");
+
+		let mut res = Response::new(Body::empty());
+		ResponseHeaders::default().apply(Some(&listed), &mut res);
+		assert!(res.headers().get(header::VARY).is_none());
+	}
+}
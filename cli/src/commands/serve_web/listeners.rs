@@ -0,0 +1,495 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::server::accept::Accept;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_native_tls::TlsAcceptor;
+
+use super::tls;
+
+use crate::async_pipe::{listen_socket_rw_stream, AsyncPipeListener};
+use crate::commands::args::ServeWebArgs;
+use crate::log;
+use crate::util::errors::CodeError;
+
+/// Prefix of `--host` values that are paths to a unix socket.
+pub const UNIX_HOST_PREFIX: &str = "unix:";
+/// Number of accepted connections that may wait to be picked up by the server.
+const ACCEPTED_QUEUE_SIZE: usize = 32;
+/// How long to wait before accepting again after an error, such as running
+/// out of file descriptors.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A socket the web server listens on.
+pub enum Listener {
+	Tcp(TcpListener),
+	/// Unix socket or named pipe. `cleanup` is set for socket files that were
+	/// created by us, and should be deleted when the server stops.
+	Socket {
+		listener: AsyncPipeListener,
+		path: PathBuf,
+		cleanup: bool,
+	},
+}
+
+impl Listener {
+	/// Describes where the web UI is available on this listener, given the
+	/// base path and query string of the web UI.
+	pub fn describe(&self, secure: bool, path_and_query: &str) -> String {
+		match self {
+			Listener::Tcp(l) => {
+				let scheme = if secure { "https" } else { "http" };
+				match l.local_addr() {
+					Ok(addr) => format!("at {}://{}{}", scheme, addr, path_and_query),
+					Err(_) => format!("at {}://<unknown>{}", scheme, path_and_query),
+				}
+			}
+			Listener::Socket { path, .. } => format!("on {}", path.display()),
+		}
+	}
+
+	/// Gets whether the listener only accepts connections from this machine,
+	/// going by the address it's actually bound to.
+	pub fn is_loopback(&self) -> bool {
+		match self {
+			Listener::Tcp(l) => l
+				.local_addr()
+				.map(|a| a.ip().is_loopback())
+				.unwrap_or(false),
+			Listener::Socket { .. } => true,
+		}
+	}
+}
+
+/// Binds all listeners requested in the arguments: sockets passed by the
+/// service manager through `LISTEN_FDS`, each `--host`, and the `--socket-path`.
+/// If none are given, listens on localhost.
+pub async fn bind_listeners(
+	log: &log::Logger,
+	args: &ServeWebArgs,
+) -> Result<Vec<Listener>, CodeError> {
+	let mut listeners = take_activated_listeners()?;
+	if !listeners.is_empty() {
+		info!(
+			log,
+			"Using {} socket(s) passed by the service manager",
+			listeners.len()
+		);
+	}
+
+	for host in &args.host {
+		if let Some(path) = host.strip_prefix(UNIX_HOST_PREFIX) {
+			listeners.push(bind_socket(PathBuf::from(path)).await?);
+		} else {
+			listeners.push(bind_tcp(parse_host(host, args.port)?).await?);
+		}
+	}
+
+	if let Some(path) = &args.socket_path {
+		listeners.push(bind_socket(PathBuf::from(path)).await?);
+	}
+
+	if listeners.is_empty() {
+		let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), args.port);
+		listeners.push(bind_tcp(addr).await?);
+	}
+
+	Ok(listeners)
+}
+
+/// Gets the socket files created for the listeners, which should be removed
+/// once the server stops.
+pub fn socket_files(listeners: &[Listener]) -> Vec<PathBuf> {
+	listeners
+		.iter()
+		.filter_map(|l| match l {
+			Listener::Socket {
+				path,
+				cleanup: true,
+				..
+			} => Some(path.clone()),
+			_ => None,
+		})
+		.collect()
+}
+
+/// Gets the IP address or name in a `--host` value, if it's not a unix socket.
+pub fn host_name(host: &str) -> Option<&str> {
+	if host.starts_with(UNIX_HOST_PREFIX) {
+		return None;
+	}
+
+	let name = match host.parse::<SocketAddr>() {
+		Ok(_) => host.rsplit_once(':').map(|(ip, _)| ip).unwrap_or(host),
+		Err(_) => host,
+	};
+	Some(name.trim_start_matches('[').trim_end_matches(']'))
+}
+
+/// Gets whether a `--host` value only accepts connections from this machine,
+/// as loopback addresses and unix sockets do.
+pub fn is_loopback_host(host: &str) -> bool {
+	match host_name(host) {
+		None | Some("localhost") => true,
+		Some(name) => name
+			.parse::<IpAddr>()
+			.map(|ip| ip.is_loopback())
+			.unwrap_or(false),
+	}
+}
+
+/// Parses a `--host` value, which is either an IP address, in which case the
+/// given default port is used, or a socket address.
+fn parse_host(host: &str, port: u16) -> Result<SocketAddr, CodeError> {
+	if let Ok(addr) = host.parse::<SocketAddr>() {
+		return Ok(addr);
+	}
+
+	let ip = host
+		.trim_start_matches('[')
+		.trim_end_matches(']')
+		.parse::<IpAddr>()
+		.map_err(CodeError::InvalidHostAddress)?;
+	Ok(SocketAddr::new(ip, port))
+}
+
+async fn bind_tcp(addr: SocketAddr) -> Result<Listener, CodeError> {
+	TcpListener::bind(addr)
+		.await
+		.map(Listener::Tcp)
+		.map_err(|e| CodeError::CouldNotListenOnAddress(addr.to_string(), e))
+}
+
+async fn bind_socket(path: PathBuf) -> Result<Listener, CodeError> {
+	let listener = listen_socket_rw_stream(&path).await?;
+	Ok(Listener::Socket {
+		listener,
+		path,
+		cleanup: true,
+	})
+}
+
+/// Takes sockets passed by systemd or another service manager, following the
+/// `sd_listen_fds` protocol. Listeners are passed starting at fd 3, and only
+/// apply to us if `LISTEN_PID` matches our process.
+#[cfg(unix)]
+fn take_activated_listeners() -> Result<Vec<Listener>, CodeError> {
+	use std::os::unix::io::FromRawFd;
+
+	/// First file descriptor passed by the service manager.
+	const LISTEN_FDS_START: i32 = 3;
+
+	let is_for_us = std::env::var("LISTEN_PID")
+		.ok()
+		.and_then(|p| p.parse::<u32>().ok())
+		== Some(std::process::id());
+	let count = match std::env::var("LISTEN_FDS") {
+		Ok(n) if is_for_us => n
+			.parse::<i32>()
+			.map_err(|_| CodeError::InvalidSocketActivation(format!("LISTEN_FDS={}", n)))?,
+		_ => return Ok(vec![]),
+	};
+
+	// Don't let child processes, such as the servers, think they were activated.
+	std::env::remove_var("LISTEN_PID");
+	std::env::remove_var("LISTEN_FDS");
+	std::env::remove_var("LISTEN_FDNAMES");
+
+	let mut listeners = Vec::with_capacity(count.max(0) as usize);
+	for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+		let family = socket_family(fd).map_err(|e| {
+			CodeError::InvalidSocketActivation(format!("fd {} is not a socket: {}", fd, e))
+		})?;
+
+		unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+
+		let listener = match family {
+			libc::AF_UNIX => {
+				let l = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+				let path = l
+					.local_addr()
+					.ok()
+					.and_then(|a| a.as_pathname().map(|p| p.to_owned()))
+					.unwrap_or_else(|| PathBuf::from(format!("fd:{}", fd)));
+				l.set_nonblocking(true)
+					.and_then(|_| tokio::net::UnixListener::from_std(l))
+					.map(|l| Listener::Socket {
+						listener: l.into(),
+						path,
+						cleanup: false,
+					})
+			}
+			libc::AF_INET | libc::AF_INET6 => {
+				let l = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+				l.set_nonblocking(true)
+					.and_then(|_| TcpListener::from_std(l))
+					.map(Listener::Tcp)
+			}
+			f => {
+				return Err(CodeError::InvalidSocketActivation(format!(
+					"fd {} has unsupported address family {}",
+					fd, f
+				)))
+			}
+		};
+
+		listeners.push(listener.map_err(|e| {
+			CodeError::InvalidSocketActivation(format!("could not use fd {}: {}", fd, e))
+		})?);
+	}
+
+	Ok(listeners)
+}
+
+#[cfg(unix)]
+fn socket_family(fd: i32) -> io::Result<i32> {
+	let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+	let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+	let r = unsafe {
+		libc::getsockname(
+			fd,
+			&mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+			&mut len,
+		)
+	};
+	if r != 0 {
+		return Err(io::Error::last_os_error());
+	}
+
+	Ok(addr.ss_family as i32)
+}
+
+#[cfg(not(unix))]
+fn take_activated_listeners() -> Result<Vec<Listener>, CodeError> {
+	Ok(vec![])
+}
+
+/// Merges all listeners into a single stream of connections for hyper. If a
+/// TLS acceptor is given, it's used for TCP connections, and connections are
+/// returned once their handshake completes. Handshakes happen concurrently, so
+/// a slow or malicious client cannot hold up other connections.
+pub fn incoming(log: log::Logger, listeners: Vec<Listener>, tls: Option<TlsAcceptor>) -> Incoming {
+	let (tx, rx) = mpsc::channel(ACCEPTED_QUEUE_SIZE);
+
+	for listener in listeners {
+		let log = log.clone();
+		let tx = tx.clone();
+		match listener {
+			Listener::Tcp(l) => {
+				tokio::spawn(accept_tcp(log, l, tls.clone(), tx));
+			}
+			Listener::Socket { listener, .. } => {
+				tokio::spawn(accept_socket(log, listener, tx));
+			}
+		}
+	}
+
+	Incoming { rx }
+}
+
+async fn accept_tcp(
+	log: log::Logger,
+	listener: TcpListener,
+	tls: Option<TlsAcceptor>,
+	tx: mpsc::Sender<Connection>,
+) {
+	loop {
+		let accepted = tokio::select! {
+			a = listener.accept() => a,
+			_ = tx.closed() => return,
+		};
+
+		let (stream, remote_addr) = match accepted {
+			Ok(s) => s,
+			Err(e) => {
+				debug!(log, "error accepting connection: {}", e);
+				tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+				continue;
+			}
+		};
+
+		let _ = stream.set_nodelay(true);
+		let acceptor = match &tls {
+			Some(a) => a.clone(),
+			None => {
+				tx.send(Connection::new(stream, Some(remote_addr)))
+					.await
+					.ok();
+				continue;
+			}
+		};
+
+		let log = log.clone();
+		let tx = tx.clone();
+		tokio::spawn(async move {
+			match tls::handshake(&acceptor, stream, tls::HANDSHAKE_TIMEOUT).await {
+				Ok(s) => {
+					tx.send(Connection::new(s, Some(remote_addr))).await.ok();
+				}
+				Err(e) => debug!(log, "TLS handshake with {} failed: {}", remote_addr, e),
+			}
+		});
+	}
+}
+
+async fn accept_socket(
+	log: log::Logger,
+	listener: AsyncPipeListener,
+	tx: mpsc::Sender<Connection>,
+) {
+	let mut listener = listener.into_pollable();
+	loop {
+		let accepted = tokio::select! {
+			a = futures::future::poll_fn(|cx| Pin::new(&mut listener).poll_accept(cx)) => a,
+			_ = tx.closed() => return,
+		};
+
+		match accepted {
+			Some(Ok(s)) => {
+				tx.send(Connection::new(s, None)).await.ok();
+			}
+			Some(Err(e)) => {
+				debug!(log, "error accepting connection: {}", e);
+				tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+			}
+			None => return,
+		}
+	}
+}
+
+/// Connections accepted on any of the server's listeners.
+pub struct Incoming {
+	rx: mpsc::Receiver<Connection>,
+}
+
+impl Accept for Incoming {
+	type Conn = Connection;
+	type Error = io::Error;
+
+	fn poll_accept(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+		self.rx.poll_recv(cx).map(|s| s.map(Ok))
+	}
+}
+
+trait AsyncReadWrite: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncReadWrite for T {}
+
+/// A connection accepted by the server, over TCP, TLS, or a socket.
+pub struct Connection {
+	stream: Box<dyn AsyncReadWrite>,
+	remote_addr: Option<SocketAddr>,
+}
+
+impl Connection {
+	fn new(
+		stream: impl AsyncRead + AsyncWrite + Send + Unpin + 'static,
+		remote_addr: Option<SocketAddr>,
+	) -> Self {
+		Self {
+			stream: Box::new(stream),
+			remote_addr,
+		}
+	}
+
+	/// Address of the peer, if connected over the network.
+	pub fn remote_addr(&self) -> Option<SocketAddr> {
+		self.remote_addr
+	}
+}
+
+impl AsyncRead for Connection {
+	fn poll_read(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.stream).poll_read(cx, buf)
+	}
+}
+
+impl AsyncWrite for Connection {
+	fn poll_write(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.stream).poll_write(cx, buf)
+	}
+
+	fn poll_write_vectored(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		bufs: &[io::IoSlice<'_>],
+	) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+	}
+
+	fn is_write_vectored(&self) -> bool {
+		self.stream.is_write_vectored()
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.stream).poll_flush(cx)
+	}
+
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.stream).poll_shutdown(cx)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_host() {
+		assert_eq!(
+			parse_host("127.0.0.1", 8000).unwrap(),
+			"127.0.0.1:8000".parse::<SocketAddr>().unwrap()
+		);
+		assert_eq!(
+			parse_host("[::1]:9000", 8000).unwrap(),
+			"[::1]:9000".parse::<SocketAddr>().unwrap()
+		);
+		assert_eq!(
+			parse_host("::1", 8000).unwrap(),
+			"[::1]:8000".parse::<SocketAddr>().unwrap()
+		);
+		assert!(parse_host("not a host", 8000).is_err());
+	}
+
+	#[test]
+	fn test_host_name() {
+		assert_eq!(host_name("10.0.0.1"), Some("10.0.0.1"));
+		assert_eq!(host_name("10.0.0.1:8080"), Some("10.0.0.1"));
+		assert_eq!(host_name("[::1]:8080"), Some("::1"));
+		assert_eq!(host_name("::1"), Some("::1"));
+		assert_eq!(host_name("unix:/run/code.sock"), None);
+	}
+
+	#[test]
+	fn test_is_loopback_host() {
+		assert!(is_loopback_host("localhost"));
+		assert!(is_loopback_host("127.0.0.1:8080"));
+		assert!(is_loopback_host("[::1]:8080"));
+		assert!(is_loopback_host("unix:/run/code.sock"));
+		assert!(!is_loopback_host("0.0.0.0"));
+		assert!(!is_loopback_host("10.0.0.1:8080"));
+		assert!(!is_loopback_host("example.com"));
+	}
+}
@@ -0,0 +1,110 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use futures::FutureExt;
+use hyper::client::conn::SendRequest;
+use hyper::Body;
+
+/// Most connections kept open to a single server.
+const MAX_CONNECTIONS_PER_SERVER: usize = 32;
+
+/// Keep-alive HTTP connections to running servers, by their socket path, which
+/// are reused for requests that aren't upgraded to WebSockets.
+#[derive(Default)]
+pub struct ConnectionPool {
+	servers: Mutex<HashMap<PathBuf, Vec<SendRequest<Body>>>>,
+}
+
+impl ConnectionPool {
+	/// Takes a connection to the server that's ready for another request,
+	/// dropping any that were closed.
+	pub fn take(&self, socket_path: &Path) -> Option<SendRequest<Body>> {
+		let mut servers = self.servers.lock().unwrap();
+		let conns = servers.get_mut(socket_path)?;
+		let mut i = 0;
+		while i < conns.len() {
+			let conn = &mut conns[i];
+			match futures::future::poll_fn(|cx| conn.poll_ready(cx)).now_or_never() {
+				Some(Ok(_)) => return Some(conns.swap_remove(i)),
+				Some(Err(_)) => {
+					conns.swap_remove(i);
+				}
+				None => i += 1, // still reading a response
+			}
+		}
+
+		None
+	}
+
+	/// Returns a connection to the pool once its request was sent. It can be
+	/// taken again once the response is read. Connections past the cap are
+	/// dropped, which closes them once their response is done.
+	pub fn put(&self, socket_path: &Path, conn: SendRequest<Body>) {
+		let mut servers = self.servers.lock().unwrap();
+		let conns = servers.entry(socket_path.to_owned()).or_default();
+		if conns.len() < MAX_CONNECTIONS_PER_SERVER {
+			conns.push(conn);
+		}
+	}
+
+	/// Drops all connections to a server, once it exits.
+	pub fn evict(&self, socket_path: &Path) {
+		self.servers.lock().unwrap().remove(socket_path);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::convert::Infallible;
+
+	use hyper::server::conn::Http;
+	use hyper::service::service_fn;
+	use hyper::{Request, Response};
+
+	use super::*;
+
+	async fn connect() -> SendRequest<Body> {
+		let (client, server) = tokio::io::duplex(4096);
+		tokio::spawn(Http::new().serve_connection(
+			server,
+			service_fn(|_req| async { Ok::<_, Infallible>(Response::new(Body::from("ok"))) }),
+		));
+		let (sender, connection) = hyper::client::conn::Builder::new()
+			.handshake(client)
+			.await
+			.unwrap();
+		tokio::spawn(connection);
+		sender
+	}
+
+	#[tokio::test]
+	async fn test_reuses_ready_connections() {
+		let pool = ConnectionPool::default();
+		let path = PathBuf::from("/tmp/server.sock");
+		assert!(pool.take(&path).is_none());
+
+		let mut sender = connect().await;
+		let res = sender
+			.send_request(Request::new(Body::empty()))
+			.await
+			.unwrap();
+		pool.put(&path, sender);
+		hyper::body::to_bytes(res.into_body()).await.unwrap();
+		tokio::task::yield_now().await;
+
+		let mut sender = pool.take(&path).expect("expected a ready connection");
+		sender
+			.send_request(Request::new(Body::empty()))
+			.await
+			.unwrap();
+		pool.put(&path, sender);
+		pool.evict(&path);
+		assert!(pool.take(&path).is_none());
+	}
+}
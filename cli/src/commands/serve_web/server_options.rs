@@ -0,0 +1,135 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::commands::args::ServeWebArgs;
+use crate::util::errors::CodeError;
+
+/// Contents of the `--server-config` file.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct ServerConfigFile {
+	args: Vec<String>,
+	env: HashMap<String, String>,
+	install_extensions: Vec<String>,
+}
+
+/// Additional options passed to every server that serve-web starts, from the
+/// `--server-*` arguments and the `--server-config` file.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct ServerOptions {
+	pub args: Vec<String>,
+	pub env: Vec<(String, String)>,
+	pub install_extensions: Vec<String>,
+}
+
+impl ServerOptions {
+	/// Reads the options, including the config file. Options given on the
+	/// command line come after those from the file, so they take precedence.
+	pub fn read(args: &ServeWebArgs) -> Result<ServerOptions, CodeError> {
+		let file = match &args.server_config {
+			Some(path) => {
+				let contents = std::fs::read_to_string(path)
+					.map_err(|e| CodeError::InvalidServerConfig(e.to_string()))?;
+				serde_json::from_str(&contents)
+					.map_err(|e| CodeError::InvalidServerConfig(e.to_string()))?
+			}
+			None => ServerConfigFile::default(),
+		};
+
+		let mut env: Vec<(String, String)> = file.env.into_iter().collect();
+		env.sort();
+		for var in &args.server_env {
+			env.push(parse_env_var(var)?);
+		}
+
+		Ok(ServerOptions {
+			args: file
+				.args
+				.into_iter()
+				.chain(args.server_arg.clone())
+				.collect(),
+			env,
+			install_extensions: file
+				.install_extensions
+				.into_iter()
+				.chain(args.install_extension.clone())
+				.collect(),
+		})
+	}
+}
+
+fn parse_env_var(var: &str) -> Result<(String, String), CodeError> {
+	match var.split_once('=') {
+		Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+		_ => Err(CodeError::InvalidServerConfig(format!(
+			"expected KEY=VALUE, got `{}`",
+			var
+		))),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_env_var() {
+		assert_eq!(
+			parse_env_var("HTTP_PROXY=http://proxy:3128/?a=b").unwrap(),
+			(
+				"HTTP_PROXY".to_string(),
+				"http://proxy:3128/?a=b".to_string()
+			)
+		);
+		assert_eq!(
+			parse_env_var("EMPTY=").unwrap(),
+			("EMPTY".to_string(), String::new())
+		);
+		assert!(parse_env_var("NO_VALUE").is_err());
+		assert!(parse_env_var("=value").is_err());
+	}
+
+	#[test]
+	fn test_read_merges_config_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("server.json");
+		std::fs::write(
+			&path,
+			r#"{ "args": ["--log=trace"], "env": { "B": "2", "A": "1" }, "installExtensions": ["ms-python.python"] }"#,
+		)
+		.unwrap();
+
+		let args = ServeWebArgs {
+			server_config: Some(path.to_string_lossy().to_string()),
+			server_arg: vec!["--disable-workspace-trust".to_string()],
+			server_env: vec!["A=3".to_string()],
+			install_extension: vec!["rust-lang.rust-analyzer".to_string()],
+			..Default::default()
+		};
+
+		assert_eq!(
+			ServerOptions::read(&args).unwrap(),
+			ServerOptions {
+				args: vec![
+					"--log=trace".to_string(),
+					"--disable-workspace-trust".to_string()
+				],
+				env: vec![
+					("A".to_string(), "1".to_string()),
+					("B".to_string(), "2".to_string()),
+					("A".to_string(), "3".to_string()),
+				],
+				install_extensions: vec![
+					"ms-python.python".to_string(),
+					"rust-lang.rust-analyzer".to_string()
+				],
+			}
+		);
+	}
+}
@@ -0,0 +1,243 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::commands::args::{CliCore, ServeWebArgs, ServeWebServiceSubCommands};
+use crate::commands::CommandContext;
+use crate::constants::APPLICATION_NAME;
+use crate::log;
+use crate::state::{LauncherPaths, PersistedState};
+use crate::tunnels::{
+	create_service_manager_for, legal, ServiceContainer, ServiceKind, ServiceManager,
+};
+use crate::util::errors::{wrap, AnyError, CodeError};
+use crate::util::machine::canonical_exe;
+
+use super::auth::AuthConfig;
+use super::headers::ResponseHeaders;
+use super::server_options::ServerOptions;
+use super::tls;
+
+/// Delay before the web server is restarted after it fails.
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// File the serve-web options are persisted to when installing the service.
+fn service_args_file(paths: &LauncherPaths) -> PathBuf {
+	paths.root().join("serve-web-service.json")
+}
+
+/// Runs `serve-web service` commands.
+pub async fn service(
+	ctx: CommandContext,
+	service_args: ServeWebServiceSubCommands,
+) -> Result<i32, AnyError> {
+	let manager = create_service_manager_for(ctx.log.clone(), &ctx.paths, ServiceKind::ServeWeb);
+	match service_args {
+		ServeWebServiceSubCommands::Install(mut args) => {
+			legal::require_consent(&ctx.paths, args.accept_server_license_terms)?;
+			validate_args(&ctx.log, &args)?;
+			make_paths_absolute(&mut args)
+				.map_err(|e| wrap(e, "could not get current directory"))?;
+
+			// contains the connection token, if any, so keep it private
+			PersistedState::<ServeWebArgs>::new_with_mode(service_args_file(&ctx.paths), 0o600)
+				.save(*args)?;
+
+			let current_exe = canonical_exe().map_err(|e| wrap(e, "could not get current exe"))?;
+			manager
+				.register(
+					current_exe,
+					&[
+						"--verbose",
+						"--cli-data-dir",
+						ctx.paths.root().as_os_str().to_string_lossy().as_ref(),
+						"serve-web",
+						"service",
+						"internal-run",
+					],
+				)
+				.await?;
+			ctx.log.result(format!("Service successfully installed! You can use `{} serve-web service log` to monitor it, and `{} serve-web service uninstall` to remove it.", APPLICATION_NAME, APPLICATION_NAME));
+		}
+		ServeWebServiceSubCommands::Uninstall => {
+			manager.unregister().await?;
+			let _ = std::fs::remove_file(service_args_file(&ctx.paths));
+		}
+		ServeWebServiceSubCommands::Log => {
+			manager.show_logs().await?;
+		}
+		ServeWebServiceSubCommands::InternalRun => {
+			let args = read_service_args(&service_args_file(&ctx.paths))?;
+			manager
+				.run(
+					ctx.paths.clone(),
+					ServeWebServiceContainer {
+						core: ctx.args,
+						http: ctx.http,
+						args,
+					},
+				)
+				.await?;
+		}
+	}
+
+	Ok(0)
+}
+
+/// Reads the options persisted when the service was installed. Unlike other
+/// persisted state, a file that can't be parsed is an error rather than
+/// falling back to the defaults, which could serve with different settings.
+fn read_service_args(path: &Path) -> Result<ServeWebArgs, AnyError> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(c) => c,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+			return Err(CodeError::ServeWebServiceNotInstalled.into())
+		}
+		Err(e) => return Err(wrap(e, "could not read the service options").into()),
+	};
+
+	serde_json::from_str(&contents).map_err(|e| {
+		wrap(
+			e,
+			format!(
+				"could not parse the service options in {}, reinstall the service",
+				path.display()
+			),
+		)
+		.into()
+	})
+}
+
+/// Checks options that would otherwise only fail once the service starts.
+fn validate_args(log: &log::Logger, args: &ServeWebArgs) -> Result<(), AnyError> {
+	if let Some(commit) = &args.commit_id {
+		if !super::is_commit_hash(commit) {
+			return Err(CodeError::InvalidCommitHash(commit.clone()).into());
+		}
+	}
+	if let Some(path) = &args.auth_config {
+		AuthConfig::read(path)?;
+	}
+	super::check_user_header(args)?;
+	ServerOptions::read(args)?;
+	tls::validate_args(args)?;
+	ResponseHeaders::read(log, args, args.cert_file.is_some() || args.self_signed_cert)?;
+
+	Ok(())
+}
+
+/// Resolves paths in the options against the current directory, since the
+/// service is started elsewhere.
+fn make_paths_absolute(args: &mut ServeWebArgs) -> std::io::Result<()> {
+	let cwd = std::env::current_dir()?;
+	let absolute = |p: &mut Option<String>| {
+		if let Some(path) = p.as_mut() {
+			*path = absolute_path(&cwd, path);
+		}
+	};
+
+	absolute(&mut args.socket_path);
+	absolute(&mut args.connection_token_file);
+	absolute(&mut args.server_data_dir);
+	absolute(&mut args.user_data_dir);
+	absolute(&mut args.extensions_dir);
+	absolute(&mut args.cert_file);
+	absolute(&mut args.cert_key_file);
+	absolute(&mut args.server_archive);
+	absolute(&mut args.auth_config);
+	absolute(&mut args.server_config);
+	absolute(&mut args.headers_config);
+	absolute(&mut args.user_data_dir_template);
+	absolute(&mut args.extensions_dir_template);
+	if args.access_log.as_deref() != Some("-") {
+		absolute(&mut args.access_log);
+	}
+	for host in args.host.iter_mut() {
+		if let Some(path) = host.strip_prefix("unix:") {
+			*host = format!("unix:{}", absolute_path(&cwd, path));
+		}
+	}
+
+	Ok(())
+}
+
+fn absolute_path(cwd: &Path, path: &str) -> String {
+	cwd.join(path).to_string_lossy().to_string()
+}
+
+struct ServeWebServiceContainer {
+	core: CliCore,
+	http: reqwest::Client,
+	args: ServeWebArgs,
+}
+
+#[async_trait]
+impl ServiceContainer for ServeWebServiceContainer {
+	async fn run_service(
+		&mut self,
+		log: log::Logger,
+		launcher_paths: LauncherPaths,
+	) -> Result<(), AnyError> {
+		let pid_file = ServiceKind::ServeWeb.pid_file(&launcher_paths);
+		if let Err(e) = std::fs::write(&pid_file, std::process::id().to_string()) {
+			warning!(log, "Could not write service pid file: {}", e);
+		}
+
+		loop {
+			let ctx = CommandContext {
+				log: log.clone(),
+				paths: launcher_paths.clone(),
+				args: self.core.clone(),
+				http: self.http.clone(),
+			};
+
+			match super::serve_web(ctx, self.args.clone()).await {
+				Ok(_) => break,
+				Err(e) => error!(
+					log,
+					"Web server failed, restarting in {}s: {}",
+					RESTART_DELAY.as_secs(),
+					e
+				),
+			}
+
+			tokio::time::sleep(RESTART_DELAY).await;
+		}
+
+		let _ = std::fs::remove_file(pid_file);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_read_service_args() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("serve-web-service.json");
+		assert!(matches!(
+			read_service_args(&path),
+			Err(AnyError::CodeError(CodeError::ServeWebServiceNotInstalled))
+		));
+
+		// options missing from the file get the same defaults as on the CLI
+		std::fs::write(&path, r#"{"host":["0.0.0.0"]}"#).unwrap();
+		let args = read_service_args(&path).unwrap();
+		assert_eq!(args.host, vec!["0.0.0.0".to_string()]);
+		assert_eq!(args.port, 8000);
+		assert_eq!(args.access_log_max_size, 100);
+
+		std::fs::write(&path, r#"{"host":"#).unwrap();
+		assert!(read_service_args(&path).is_err());
+		std::fs::write(&path, r#"{"port":"eighty"}"#).unwrap();
+		assert!(read_service_args(&path).is_err());
+	}
+}
@@ -0,0 +1,288 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::access_log::RotatingFile;
+
+/// Number of crashes after which a commit is marked bad and not restarted.
+const MAX_CRASHES: u32 = 5;
+/// Delay before restarting after the first crash, doubling with each crash.
+const BASE_RESTART_DELAY: Duration = Duration::from_secs(1);
+/// Longest delay before restarting a crashed server.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+/// A server that ran for this long before crashing is considered to have
+/// been healthy, and its earlier crashes are forgotten.
+const HEALTHY_UPTIME: Duration = Duration::from_secs(5 * 60);
+/// How long a server stays marked bad, after which it's tried again, such as
+/// once a broken extension or setting of the user was fixed.
+const BAD_SERVER_TTL: Duration = Duration::from_secs(10 * 60);
+/// A child log file is rotated once it grows larger than this.
+const MAX_CHILD_LOG_BYTES: u64 = 10 * 1024 * 1024;
+/// Number of rotated child log files to keep, as `<path>.1` to `<path>.N`.
+const MAX_ROTATED_CHILD_LOGS: u32 = 1;
+
+#[derive(Default)]
+struct ServerCrashes {
+	crashes: u32,
+	/// Set to when the server stops being bad, once it's marked bad
+	bad_until: Option<Instant>,
+}
+
+impl ServerCrashes {
+	fn is_bad(&self, now: Instant) -> bool {
+		self.bad_until.map(|until| until > now).unwrap_or(false)
+	}
+}
+
+/// Server that crashed repeatedly and is no longer started.
+#[derive(Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BadServer {
+	pub commit: String,
+	pub user: Option<String>,
+	/// Seconds until the server will be tried again
+	pub retry_in_secs: u64,
+}
+
+/// Commit and user, if any, that a server is started for.
+type ServerId = (String, Option<String>);
+
+fn server_id(commit: &str, user: Option<&str>) -> ServerId {
+	(commit.to_string(), user.map(str::to_string))
+}
+
+/// Counts crashes of the servers started for each commit and user. Once a
+/// server crashes repeatedly it's marked bad, after which it's not started
+/// until the mark expires. Users are tracked separately since a server may
+/// only crash with one user's settings or extensions.
+#[derive(Default)]
+pub struct CrashTracker {
+	servers: Mutex<HashMap<ServerId, ServerCrashes>>,
+}
+
+impl CrashTracker {
+	/// Records a crash of a server for the commit and user that ran for
+	/// `uptime`. Returns the delay before restarting it, or None if the
+	/// server has now crashed too often and was marked bad.
+	pub fn record_crash(
+		&self,
+		commit: &str,
+		user: Option<&str>,
+		uptime: Duration,
+	) -> Option<Duration> {
+		self.record_crash_at(commit, user, uptime, Instant::now())
+	}
+
+	/// Gets whether the server for the commit and user is marked bad.
+	pub fn is_bad(&self, commit: &str, user: Option<&str>) -> bool {
+		self.is_bad_at(commit, user, Instant::now())
+	}
+
+	/// Gets the number of recent crashes of the server for the commit and user.
+	pub fn crash_count(&self, commit: &str, user: Option<&str>) -> u32 {
+		self.servers
+			.lock()
+			.unwrap()
+			.get(&server_id(commit, user))
+			.map(|c| c.crashes)
+			.unwrap_or(0)
+	}
+
+	/// Gets the servers that are marked bad, sorted.
+	pub fn bad_servers(&self) -> Vec<BadServer> {
+		self.bad_servers_at(Instant::now())
+	}
+
+	fn record_crash_at(
+		&self,
+		commit: &str,
+		user: Option<&str>,
+		uptime: Duration,
+		now: Instant,
+	) -> Option<Duration> {
+		let mut servers = self.servers.lock().unwrap();
+		let c = servers.entry(server_id(commit, user)).or_default();
+		// crashes before the server was healthy, or marked bad and tried
+		// again, start the count over
+		if uptime >= HEALTHY_UPTIME || c.bad_until.is_some() {
+			c.crashes = 0;
+			c.bad_until = None;
+		}
+
+		c.crashes = (c.crashes + 1).min(MAX_CRASHES);
+		if c.crashes >= MAX_CRASHES {
+			c.bad_until = Some(now + BAD_SERVER_TTL);
+			return None;
+		}
+
+		Some(
+			BASE_RESTART_DELAY
+				.saturating_mul(1 << (c.crashes - 1))
+				.min(MAX_RESTART_DELAY),
+		)
+	}
+
+	fn is_bad_at(&self, commit: &str, user: Option<&str>, now: Instant) -> bool {
+		self.servers
+			.lock()
+			.unwrap()
+			.get(&server_id(commit, user))
+			.map(|c| c.is_bad(now))
+			.unwrap_or(false)
+	}
+
+	fn bad_servers_at(&self, now: Instant) -> Vec<BadServer> {
+		let mut bad: Vec<BadServer> = self
+			.servers
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|(_, c)| c.is_bad(now))
+			.map(|((commit, user), c)| BadServer {
+				commit: commit.clone(),
+				user: user.clone(),
+				retry_in_secs: c.bad_until.unwrap().duration_since(now).as_secs(),
+			})
+			.collect();
+		bad.sort();
+		bad
+	}
+}
+
+/// Gets the log file that the output of a server is written to.
+pub fn child_log_path(logs_dir: &Path, commit: &str, user: Option<&str>) -> PathBuf {
+	match user {
+		Some(u) => logs_dir.join(format!("{}-{}.log", commit, u)),
+		None => logs_dir.join(format!("{}.log", commit)),
+	}
+}
+
+/// Opens a server log file for appending. It's rotated as it's written once
+/// it grows too large.
+pub fn open_child_log(path: &Path) -> io::Result<RotatingFile> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+
+	RotatingFile::open(path.to_owned(), MAX_CHILD_LOG_BYTES, MAX_ROTATED_CHILD_LOGS)
+}
+
+/// Writes a line of server output to its log file, if it could be opened.
+pub fn write_child_log(file: &mut Option<RotatingFile>, line: &str) {
+	if let Some(f) = file {
+		// ignore any errors, not much we can do if logging fails...
+		let _ = f.write(format!("{}\n", line).as_bytes());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_crash_backoff() {
+		let tracker = CrashTracker::default();
+		let short = Duration::from_secs(1);
+
+		assert_eq!(
+			tracker.record_crash("a", None, short),
+			Some(BASE_RESTART_DELAY)
+		);
+		assert_eq!(
+			tracker.record_crash("a", None, short),
+			Some(BASE_RESTART_DELAY * 2)
+		);
+		assert_eq!(tracker.crash_count("a", None), 2);
+		assert_eq!(tracker.crash_count("b", None), 0);
+
+		// a healthy run resets the count
+		assert_eq!(
+			tracker.record_crash("a", None, HEALTHY_UPTIME),
+			Some(BASE_RESTART_DELAY)
+		);
+
+		for _ in 1..MAX_CRASHES - 1 {
+			assert!(tracker.record_crash("a", None, short).is_some());
+		}
+		assert!(!tracker.is_bad("a", None));
+		assert_eq!(tracker.record_crash("a", None, short), None);
+		assert!(tracker.is_bad("a", None));
+		assert_eq!(tracker.crash_count("a", None), MAX_CRASHES);
+		assert_eq!(
+			tracker
+				.bad_servers()
+				.iter()
+				.map(|b| &b.commit)
+				.collect::<Vec<_>>(),
+			vec!["a"]
+		);
+
+		// other users' servers of the commit are tracked separately
+		assert!(!tracker.is_bad("a", Some("alice")));
+		assert_eq!(tracker.crash_count("a", Some("alice")), 0);
+		assert_eq!(
+			tracker.record_crash("a", Some("alice"), short),
+			Some(BASE_RESTART_DELAY)
+		);
+	}
+
+	#[test]
+	fn test_bad_server_expires() {
+		let tracker = CrashTracker::default();
+		let short = Duration::from_secs(1);
+		let now = Instant::now();
+
+		for _ in 1..MAX_CRASHES {
+			tracker.record_crash_at("a", Some("alice"), short, now);
+		}
+		assert_eq!(
+			tracker.record_crash_at("a", Some("alice"), short, now),
+			None
+		);
+		assert!(tracker.is_bad_at("a", Some("alice"), now));
+		assert_eq!(
+			tracker.bad_servers_at(now),
+			vec![BadServer {
+				commit: "a".to_string(),
+				user: Some("alice".to_string()),
+				retry_in_secs: BAD_SERVER_TTL.as_secs(),
+			}]
+		);
+
+		// once expired, the server is tried again with a fresh crash count
+		let later = now + BAD_SERVER_TTL;
+		assert!(!tracker.is_bad_at("a", Some("alice"), later));
+		assert!(tracker.bad_servers_at(later).is_empty());
+		assert_eq!(
+			tracker.record_crash_at("a", Some("alice"), short, later),
+			Some(BASE_RESTART_DELAY)
+		);
+		assert_eq!(tracker.crash_count("a", Some("alice")), 1);
+	}
+
+	#[test]
+	fn test_write_child_log_rotates() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("server.log");
+		let mut file = Some(RotatingFile::open(path.clone(), 10, MAX_ROTATED_CHILD_LOGS).unwrap());
+		for line in ["first", "second", "third"] {
+			write_child_log(&mut file, line);
+		}
+
+		assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+		assert_eq!(
+			fs::read_to_string(dir.path().join("server.log.1")).unwrap(),
+			"second\n"
+		);
+		assert!(!dir.path().join("server.log.2").exists());
+	}
+}
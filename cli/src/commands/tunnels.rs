@@ -22,7 +22,8 @@ use tokio::{
 use super::{
 	args::{
 		AuthProvider, CliCore, CommandShellArgs, ExistingTunnelArgs, TunnelForwardArgs,
-		TunnelRenameArgs, TunnelServeArgs, TunnelServiceSubCommands, TunnelUserSubCommands,
+		TunnelImportServerArgs, TunnelRenameArgs, TunnelServeArgs, TunnelServiceSubCommands,
+		TunnelUserSubCommands,
 	},
 	CommandContext,
 };
@@ -40,20 +41,22 @@ use crate::{
 		create_service_manager,
 		dev_tunnels::{self, DevTunnels},
 		legal, local_forwarding,
-		paths::get_all_servers,
+		paths::{get_all_servers, get_server_folder_name, SERVER_FOLDER_NAME},
 		protocol, serve_stream,
 		shutdown_signal::ShutdownRequest,
 		singleton_client::do_single_rpc_call,
 		singleton_server::{
 			make_singleton_server, start_singleton_server, BroadcastLogSink, SingletonServerArgs,
 		},
-		AuthRequired, Next, ServeStreamParams, ServiceContainer, ServiceManager,
+		AuthAttempts, AuthRequired, Next, ServeStreamParams, ServiceContainer, ServiceManager,
 	},
+	update_service::import_release_archive,
 	util::{
 		app_lock::AppMutex,
 		command::new_std_command,
 		errors::{wrap, AnyError, CodeError},
 		machine::canonical_exe,
+		metrics::spawn_metrics_server,
 		prereqs::PreReqChecker,
 	},
 };
@@ -153,6 +156,7 @@ pub async fn command_shell(ctx: CommandContext, args: CommandShellArgs) -> Resul
 			.unwrap_or(AuthRequired::VSDA),
 		exit_barrier: ShutdownRequest::create_rx(shutdown_reqs),
 		code_server_args: (&ctx.args).into(),
+		auth_attempts: AuthAttempts::default(),
 	};
 
 	let mut listener: Box<dyn AsyncRWAccepter> =
@@ -209,7 +213,11 @@ pub async fn command_shell(ctx: CommandContext, args: CommandShellArgs) -> Resul
 			Some(_) = servers.next() => {},
 			socket = listener.accept_rw() => {
 				match socket {
-					Ok((read, write)) => servers.push(serve_stream(read, write, params.clone())),
+					Ok((read, write, addr)) => {
+						let mut params = params.clone();
+						params.auth_attempts.peer_addr = addr;
+						servers.push(serve_stream(read, write, params));
+					}
 					Err(e) => {
 						error!(params.log, &format!("Error accepting connection: {}", e));
 						return Ok(1);
@@ -420,6 +428,29 @@ pub async fn prune(ctx: CommandContext) -> Result<i32, AnyError> {
 	Ok(0)
 }
 
+pub async fn import_server(
+	ctx: CommandContext,
+	import_args: TunnelImportServerArgs,
+) -> Result<i32, AnyError> {
+	let (product, dir) = import_release_archive(
+		std::path::Path::new(&import_args.archive),
+		&ctx.paths.server_cache,
+		Some(SERVER_FOLDER_NAME),
+		|p| get_server_folder_name(p.quality, &p.commit),
+		ctx.log.get_download_logger("server inflate progress:"),
+	)
+	.await?;
+
+	ctx.log.result(format!(
+		"Imported {} server {} into {}",
+		product.quality,
+		product.commit,
+		dir.display()
+	));
+
+	Ok(0)
+}
+
 /// Starts the gateway server.
 pub async fn serve(ctx: CommandContext, gateway_args: TunnelServeArgs) -> Result<i32, AnyError> {
 	let CommandContext {
@@ -616,6 +647,13 @@ async fn serve_with_csa(
 
 	debug!(log, "starting as new singleton");
 
+	if let Some(port) = gateway_args.metrics_port {
+		spawn_metrics_server(
+			log.clone(),
+			SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+		)?;
+	}
+
 	let mut server =
 		make_singleton_server(log_broadcast.clone(), log.clone(), server, shutdown.clone());
 	let platform = spanf!(log, log.span("prereq"), PreReqChecker::new().verify())?;
@@ -79,6 +79,9 @@ pub const EDITOR_WEB_URL: Option<&'static str> = option_env!("VSCODE_CLI_TUNNEL_
 /// Name shown in places where we need to tell a user what a process is, e.g. in sleep inhibition.
 pub const TUNNEL_ACTIVITY_NAME: &str = concatcp!(PRODUCT_NAME_LONG, " Tunnel");
 
+/// Name shown to users for the serve-web service, e.g. in the Windows startup list.
+pub const SERVE_WEB_ACTIVITY_NAME: &str = concatcp!(PRODUCT_NAME_LONG, " Web Server");
+
 /// Download URL of the desktop product.
 pub const PRODUCT_DOWNLOAD_URL: Option<&'static str> = option_env!("VSCODE_CLI_DOWNLOAD_URL");
 
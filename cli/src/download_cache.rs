@@ -4,8 +4,10 @@
  *--------------------------------------------------------------------------------------------*/
 
 use std::{
+	collections::HashMap,
 	fs::create_dir_all,
 	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
 };
 
 use futures::Future;
@@ -25,12 +27,36 @@ const RENAME_DELAY: std::time::Duration = std::time::Duration::from_millis(200);
 pub struct DownloadCache {
 	path: PathBuf,
 	state: PersistedState<Vec<String>>,
+	/// Number of pins on each entry. Pinned entries are not evicted by this
+	/// process, but pins are only kept in memory: another process sharing the
+	/// cache directory can still evict an entry that's pinned here.
+	pins: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+/// Guard returned from `DownloadCache::pin`. The entry can be evicted again
+/// once all its pins are dropped.
+pub struct CachePin {
+	pins: Arc<Mutex<HashMap<String, usize>>>,
+	name: String,
+}
+
+impl Drop for CachePin {
+	fn drop(&mut self) {
+		let mut pins = self.pins.lock().unwrap();
+		if let Some(n) = pins.get_mut(&self.name) {
+			*n -= 1;
+			if *n == 0 {
+				pins.remove(&self.name);
+			}
+		}
+	}
 }
 
 impl DownloadCache {
 	pub fn new(path: PathBuf) -> DownloadCache {
 		DownloadCache {
 			state: PersistedState::new(path.join("lru.json")),
+			pins: Arc::default(),
 			path,
 		}
 	}
@@ -41,6 +67,11 @@ impl DownloadCache {
 		&self.path
 	}
 
+	/// Gets the names of entries in the cache, most recently used first.
+	pub fn get_lru(&self) -> Vec<String> {
+		self.state.load()
+	}
+
 	/// Gets whether a cache exists with the name already. Marks it as recently
 	/// used if it does exist.
 	pub fn exists(&self, name: &str) -> Option<PathBuf> {
@@ -53,6 +84,23 @@ impl DownloadCache {
 		Some(p)
 	}
 
+	/// Prevents the entry from being evicted while the returned guard is held,
+	/// such as while a server from the entry is running. Only evictions made
+	/// by this process are prevented, see `pins`.
+	pub fn pin(&self, name: &str) -> CachePin {
+		*self
+			.pins
+			.lock()
+			.unwrap()
+			.entry(name.to_string())
+			.or_default() += 1;
+
+		CachePin {
+			pins: self.pins.clone(),
+			name: name.to_string(),
+		}
+	}
+
 	/// Removes the item from the cache, if it exists
 	pub fn delete(&self, name: &str) -> Result<(), WrappedError> {
 		let f = self.path.join(name);
@@ -121,10 +169,12 @@ impl DownloadCache {
 				return;
 			}
 
-			if let Some(f) = l.last() {
-				let f = self.path.join(f);
+			// evict the least recently used entry that isn't pinned
+			let pins = self.pins.lock().unwrap();
+			if let Some(i) = (1..l.len()).rev().find(|i| !pins.contains_key(&l[*i])) {
+				let f = self.path.join(&l[i]);
 				if !f.exists() || std::fs::remove_dir_all(f).is_ok() {
-					l.pop();
+					l.remove(i);
 				}
 			}
 		})?;
@@ -132,3 +182,41 @@ impl DownloadCache {
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn fill(cache: &DownloadCache, names: &[&str]) {
+		for name in names {
+			std::fs::create_dir_all(cache.path().join(name)).unwrap();
+			cache.touch(name.to_string()).unwrap();
+		}
+	}
+
+	#[test]
+	fn test_evicts_least_recently_used() {
+		let dir = tempfile::tempdir().unwrap();
+		let cache = DownloadCache::new(dir.path().to_owned());
+		fill(&cache, &["a", "b", "c", "d", "e", "f"]);
+
+		assert_eq!(cache.get_lru(), vec!["f", "e", "d", "c", "b"]);
+		assert!(!dir.path().join("a").exists());
+	}
+
+	#[test]
+	fn test_does_not_evict_pinned() {
+		let dir = tempfile::tempdir().unwrap();
+		let cache = DownloadCache::new(dir.path().to_owned());
+		let pin = cache.pin("a");
+		fill(&cache, &["a", "b", "c", "d", "e", "f"]);
+
+		assert_eq!(cache.get_lru(), vec!["f", "e", "d", "c", "a"]);
+		assert!(dir.path().join("a").exists());
+		assert!(!dir.path().join("b").exists());
+
+		drop(pin);
+		fill(&cache, &["g"]);
+		assert_eq!(cache.get_lru(), vec!["g", "f", "e", "d", "c"]);
+	}
+}
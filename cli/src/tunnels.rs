@@ -15,6 +15,12 @@ pub mod singleton_server;
 
 mod challenge;
 mod control_server;
+mod fs_search;
+mod fs_watch;
+#[cfg(target_os = "linux")]
+mod fs_watch_linux;
+#[cfg(not(target_os = "linux"))]
+mod fs_watch_poll;
 mod nosleep;
 #[cfg(target_os = "linux")]
 mod nosleep_linux;
@@ -35,8 +41,11 @@ mod service_windows;
 mod socket_signal;
 mod wsl_detect;
 
-pub use control_server::{serve, serve_stream, AuthRequired, Next, ServeStreamParams};
+pub use control_server::{
+	serve, serve_stream, AuthAttempts, AuthRequired, Next, ServeStreamParams,
+};
 pub use nosleep::SleepInhibitor;
 pub use service::{
-	create_service_manager, ServiceContainer, ServiceManager, SERVICE_LOG_FILE_NAME,
+	create_service_manager, create_service_manager_for, ServiceContainer, ServiceKind,
+	ServiceManager, SERVICE_LOG_FILE_NAME,
 };
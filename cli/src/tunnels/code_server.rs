@@ -22,6 +22,7 @@ use crate::util::errors::{wrap, AnyError, CodeError, ExtensionInstallFailed, Wra
 use crate::util::http::{self, BoxedHttp};
 use crate::util::io::SilentCopyProgress;
 use crate::util::machine::process_exists;
+use crate::util::metrics::METRICS;
 use crate::util::prereqs::skip_requirements_check;
 use crate::{debug, info, log, spanf, trace, warning};
 use lazy_static::lazy_static;
@@ -32,6 +33,7 @@ use std::fs;
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::fs::remove_file;
@@ -417,10 +419,16 @@ impl<'a> ServerBuilder<'a> {
 			&self.server_params.release.commit,
 		);
 
+		// the cache entry may already exist, in which case nothing is downloaded
+		let downloading = AtomicBool::new(false);
+		let downloading_ref = &downloading;
 		let result = self
 			.launcher_paths
 			.server_cache
 			.create(name, |target_dir| async move {
+				downloading_ref.store(true, Ordering::Relaxed);
+				METRICS.downloads_started.inc();
+
 				let tmpdir =
 					tempfile::tempdir().map_err(|e| wrap(e, "error creating temp download dir"))?;
 
@@ -473,6 +481,13 @@ impl<'a> ServerBuilder<'a> {
 			})
 			.await;
 
+		if downloading.load(Ordering::Relaxed) {
+			match &result {
+				Ok(_) => METRICS.downloads_finished.inc(),
+				Err(_) => METRICS.downloads_failed.inc(),
+			}
+		}
+
 		if let Err(e) = result {
 			error!(self.logger, "Error installing server: {}", e);
 			return Err(e);
@@ -617,6 +632,7 @@ impl<'a> ServerBuilder<'a> {
 			.stdout(std::process::Stdio::piped())
 			.spawn()
 			.map_err(|e| CodeError::ServerUnexpectedExit(format!("{}", e)))?;
+		METRICS.process_spawns.inc_by(&[("kind", "server")], 1);
 
 		self.server_paths
 			.write_pid(child.id().expect("expected server to have pid"))?;
@@ -693,6 +709,7 @@ where
 			match line {
 				Err(e) => {
 					trace!(plog, "error reading from stdout/stderr: {}", e);
+					METRICS.process_exits.inc_by(&[("kind", "server")], 1);
 					return;
 				}
 				Ok(None) => break,
@@ -725,6 +742,9 @@ where
 				}
 			}
 		}
+
+		// the server's output is closed once it exits
+		METRICS.process_exits.inc_by(&[("kind", "server")], 1);
 	});
 
 	let origin = CodeServerOrigin::New(Box::new(child));
@@ -13,6 +13,7 @@ use crate::state::LauncherPaths;
 use crate::tunnels::protocol::{HttpRequestParams, PortPrivacy, METHOD_CHALLENGE_ISSUE};
 use crate::tunnels::socket_signal::CloseReason;
 use crate::update_service::{Platform, Release, TargetKind, UpdateService};
+use crate::util::auth_limit::AuthLimiter;
 use crate::util::command::new_tokio_command;
 use crate::util::errors::{
 	wrap, AnyError, CodeError, MismatchedLaunchModeError, NoAttachedServerError,
@@ -23,7 +24,9 @@ use crate::util::http::{
 use crate::util::io::SilentCopyProgress;
 use crate::util::is_integrated_cli;
 use crate::util::machine::kill_pid;
+use crate::util::metrics::METRICS;
 use crate::util::os::os_release;
+use crate::util::pty::Pty;
 use crate::util::sync::{new_barrier, Barrier, BarrierOpener};
 
 use futures::stream::FuturesUnordered;
@@ -31,17 +34,22 @@ use futures::FutureExt;
 use opentelemetry::trace::SpanKind;
 use opentelemetry::KeyValue;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use tokio::net::TcpStream;
 use tokio::pin;
 use tokio::process::{ChildStderr, ChildStdin};
 use tokio_util::codec::Decoder;
 
+use std::io::SeekFrom;
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream};
+use sysinfo::{DiskExt, PidExt, ProcessExt, System, SystemExt};
+use tokio::io::{
+	AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream,
+};
 use tokio::sync::{mpsc, Mutex};
 
 use super::challenge::{create_challenge, sign_challenge, verify_challenge};
@@ -50,15 +58,21 @@ use super::code_server::{
 	SocketCodeServer,
 };
 use super::dev_tunnels::ActiveTunnel;
+use super::fs_search::{self, SearchOptions};
+use super::fs_watch::{Excludes, FsWatcher};
 use super::paths::prune_stopped_servers;
 use super::port_forwarder::{PortForwarding, PortForwardingProcessor};
 use super::protocol::{
 	AcquireCliParams, CallServerHttpParams, CallServerHttpResult, ChallengeIssueParams,
 	ChallengeIssueResponse, ChallengeVerifyParams, ClientRequestMethod, EmptyObject, ForwardParams,
-	ForwardResult, FsReadDirEntry, FsReadDirResponse, FsRenameRequest, FsSinglePathRequest,
-	FsStatResponse, GetEnvResponse, GetHostnameResponse, HttpBodyParams, HttpHeadersParams,
-	NetConnectRequest, ServeParams, ServerLog, ServerMessageParams, SpawnParams, SpawnResult,
-	SysKillRequest, SysKillResponse, ToClientRequest, UnforwardParams, UpdateParams, UpdateResult,
+	ForwardResult, FsChmodRequest, FsCopyRequest, FsReadDirEntry, FsReadDirRequest,
+	FsReadDirResponse, FsReadRequest, FsRealpathResponse, FsRenameRequest, FsSearchRequest,
+	FsSearchResponse, FsSinglePathRequest, FsStatResponse, FsSymlinkRequest, FsWatchRequest,
+	FsWriteMode, FsWriteRequest, GetEnvResponse, GetHostnameResponse, HttpBodyParams,
+	HttpHeadersParams, NetConnectRequest, ProcessSignal, ServeParams, ServerLog,
+	ServerMessageParams, SpawnParams, SpawnResizeParams, SpawnResult, SpawnSignalParams,
+	SysDiskUsage, SysInfoResponse, SysKillRequest, SysKillResponse, SysProcess,
+	SysProcessesResponse, ToClientRequest, UnforwardParams, UpdateParams, UpdateResult,
 	VersionResponse, METHOD_CHALLENGE_VERIFY,
 };
 use super::server_bridge::ServerBridge;
@@ -78,6 +92,8 @@ struct HandlerContext {
 	did_update: Arc<AtomicBool>,
 	/// Whether authentication is still required on the socket.
 	auth_state: Arc<std::sync::Mutex<AuthState>>,
+	/// Limits failed authentication attempts from the socket's peer.
+	auth_attempts: AuthAttempts,
 	/// A loopback channel to talk to the socket server task.
 	socket_tx: mpsc::Sender<SocketSignal>,
 	/// Configured launcher paths.
@@ -96,6 +112,8 @@ struct HandlerContext {
 	http: Arc<FallbackSimpleHttp>,
 	/// requests being served by the client
 	http_requests: HttpRequestsMap,
+	/// processes started by `spawn` with an ID
+	spawned: SpawnedProcesses,
 }
 
 /// Handler auth state.
@@ -108,6 +126,9 @@ enum AuthState {
 	Authenticated,
 }
 
+/// Matches returned by `fs_search` when the client doesn't give a limit.
+const DEFAULT_SEARCH_MAX_RESULTS: usize = 10_000;
+
 static MESSAGE_ID_COUNTER: AtomicU32 = AtomicU32::new(0);
 
 // Gets a next incrementing number that can be used in logs
@@ -267,6 +288,7 @@ pub async fn serve(
 						platform,
 						exit_barrier: own_exit,
 						requires_auth: AuthRequired::None,
+						auth_attempts: AuthAttempts::default(),
 					}).with_context(cx.clone()).await;
 
 					cx.span().add_event(
@@ -299,6 +321,47 @@ pub struct ServeStreamParams {
 	pub platform: Platform,
 	pub requires_auth: AuthRequired,
 	pub exit_barrier: Barrier<ShutdownSignal>,
+	pub auth_attempts: AuthAttempts,
+}
+
+/// Failed authentication attempts on a stream are limited by the address of
+/// its peer. The limiter should be shared between all streams of a listener.
+#[derive(Clone, Default)]
+pub struct AuthAttempts {
+	pub limiter: Arc<AuthLimiter>,
+	/// Address of the peer, if it connected over the network.
+	pub peer_addr: Option<SocketAddr>,
+}
+
+impl AuthAttempts {
+	fn ensure_not_locked_out(&self) -> Result<(), AnyError> {
+		match self.limiter.locked_out_for(self.peer_addr.map(|a| a.ip())) {
+			Some(wait) => Err(CodeError::AuthLockedOut(wait.as_secs().max(1)).into()),
+			None => Ok(()),
+		}
+	}
+
+	fn record_failure(&self, log: &log::Logger, what: &str) {
+		METRICS
+			.auth_failures
+			.inc_by(&[("kind", "command-shell")], 1);
+		let lockout = self.limiter.record_failure(self.peer_addr.map(|a| a.ip()));
+		warning!(
+			log,
+			"Rejected invalid {} from {}{}",
+			what,
+			self.peer_addr
+				.map(|a| a.to_string())
+				.unwrap_or_else(|| "local socket".to_string()),
+			lockout
+				.map(|d| format!(", locked out for {}s", d.as_secs()))
+				.unwrap_or_default()
+		);
+	}
+
+	fn record_success(&self) {
+		self.limiter.record_success(self.peer_addr.map(|a| a.ip()));
+	}
 }
 
 pub async fn serve_stream(
@@ -328,6 +391,7 @@ fn make_socket_rpc(
 	code_server_args: CodeServerArgs,
 	port_forwarding: Option<PortForwarding>,
 	requires_auth: AuthRequired,
+	auth_attempts: AuthAttempts,
 	platform: Platform,
 	http_requests: HttpRequestsMap,
 ) -> RpcDispatcher<MsgPackSerializer, HandlerContext> {
@@ -339,6 +403,7 @@ fn make_socket_rpc(
 			AuthRequired::VSDA => AuthState::WaitingForChallenge(None),
 			AuthRequired::None => AuthState::Authenticated,
 		})),
+		auth_attempts,
 		socket_tx,
 		log: log.clone(),
 		launcher_paths,
@@ -352,13 +417,22 @@ fn make_socket_rpc(
 			http_delegated,
 		)),
 		http_requests,
+		spawned: SpawnedProcesses::default(),
 	});
 
 	rpc.register_sync("ping", |_: EmptyObject, _| Ok(EmptyObject {}));
 	rpc.register_sync("gethostname", |_: EmptyObject, _| handle_get_hostname());
 	rpc.register_sync("sys_kill", |p: SysKillRequest, c| {
 		ensure_auth(&c.auth_state)?;
-		handle_sys_kill(p.pid)
+		handle_sys_kill(p.pid, p.signal)
+	});
+	rpc.register_async("sys_info", |_: EmptyObject, c| async move {
+		ensure_auth(&c.auth_state)?;
+		handle_sys_info(&c.launcher_paths).await
+	});
+	rpc.register_async("sys_processes", |_: EmptyObject, c| async move {
+		ensure_auth(&c.auth_state)?;
+		handle_sys_processes().await
 	});
 	rpc.register_sync("fs_stat", |p: FsSinglePathRequest, c| {
 		ensure_auth(&c.auth_state)?;
@@ -367,17 +441,33 @@ fn make_socket_rpc(
 	rpc.register_duplex(
 		"fs_read",
 		1,
-		move |mut streams, p: FsSinglePathRequest, c| async move {
+		move |mut streams, p: FsReadRequest, c| async move {
 			ensure_auth(&c.auth_state)?;
-			handle_fs_read(streams.remove(0), p.path).await
+			handle_fs_read(streams.remove(0), p).await
 		},
 	);
 	rpc.register_duplex(
 		"fs_write",
 		1,
-		move |mut streams, p: FsSinglePathRequest, c| async move {
+		move |mut streams, p: FsWriteRequest, c| async move {
+			ensure_auth(&c.auth_state)?;
+			handle_fs_write(streams.remove(0), p).await
+		},
+	);
+	rpc.register_duplex(
+		"fs_watch",
+		1,
+		move |mut streams, p: FsWatchRequest, c| async move {
 			ensure_auth(&c.auth_state)?;
-			handle_fs_write(streams.remove(0), p.path).await
+			handle_fs_watch(streams.remove(0), p).await
+		},
+	);
+	rpc.register_duplex(
+		"fs_search",
+		1,
+		move |mut streams, p: FsSearchRequest, c| async move {
+			ensure_auth(&c.auth_state)?;
+			handle_fs_search(streams.remove(0), p).await
 		},
 	);
 	rpc.register_duplex(
@@ -408,19 +498,35 @@ fn make_socket_rpc(
 		ensure_auth(&c.auth_state)?;
 		handle_fs_rename(p.from_path, p.to_path)
 	});
-	rpc.register_sync("fs_readdir", |p: FsSinglePathRequest, c| {
+	rpc.register_async("fs_copy", move |p: FsCopyRequest, c| async move {
+		ensure_auth(&c.auth_state)?;
+		handle_fs_copy(p).await
+	});
+	rpc.register_sync("fs_chmod", |p: FsChmodRequest, c| {
+		ensure_auth(&c.auth_state)?;
+		handle_fs_chmod(p.path, p.mode)
+	});
+	rpc.register_sync("fs_symlink", |p: FsSymlinkRequest, c| {
+		ensure_auth(&c.auth_state)?;
+		handle_fs_symlink(p.target, p.path)
+	});
+	rpc.register_sync("fs_realpath", |p: FsSinglePathRequest, c| {
+		ensure_auth(&c.auth_state)?;
+		handle_fs_realpath(p.path)
+	});
+	rpc.register_sync("fs_readdir", |p: FsReadDirRequest, c| {
 		ensure_auth(&c.auth_state)?;
-		handle_fs_readdir(p.path)
+		handle_fs_readdir(p)
 	});
 	rpc.register_sync("get_env", |_: EmptyObject, c| {
 		ensure_auth(&c.auth_state)?;
 		handle_get_env()
 	});
 	rpc.register_sync(METHOD_CHALLENGE_ISSUE, |p: ChallengeIssueParams, c| {
-		handle_challenge_issue(p, &c.auth_state)
+		handle_challenge_issue(&c.log, p, &c.auth_state, &c.auth_attempts)
 	});
 	rpc.register_sync(METHOD_CHALLENGE_VERIFY, |p: ChallengeVerifyParams, c| {
-		handle_challenge_verify(p.response, &c.auth_state)
+		handle_challenge_verify(&c.log, p.response, &c.auth_state, &c.auth_attempts)
 	});
 	rpc.register_async("serve", move |params: ServeParams, c| async move {
 		ensure_auth(&c.auth_state)?;
@@ -450,12 +556,13 @@ fn make_socket_rpc(
 	});
 	rpc.register_async("acquire_cli", |p: AcquireCliParams, c| async move {
 		ensure_auth(&c.auth_state)?;
-		handle_acquire_cli(&c.launcher_paths, &c.http, &c.log, p).await
+		handle_acquire_cli(&c.launcher_paths, &c.http, &c.log, &c.spawned, p).await
 	});
 	rpc.register_duplex("spawn", 3, |mut streams, p: SpawnParams, c| async move {
 		ensure_auth(&c.auth_state)?;
 		handle_spawn(
 			&c.log,
+			&c.spawned,
 			p,
 			Some(streams.remove(0)),
 			Some(streams.remove(0)),
@@ -463,6 +570,14 @@ fn make_socket_rpc(
 		)
 		.await
 	});
+	rpc.register_sync("spawn_resize", |p: SpawnResizeParams, c| {
+		ensure_auth(&c.auth_state)?;
+		handle_spawn_resize(&c.spawned, p)
+	});
+	rpc.register_sync("spawn_signal", |p: SpawnSignalParams, c| {
+		ensure_auth(&c.auth_state)?;
+		handle_spawn_signal(&c.spawned, p)
+	});
 	rpc.register_duplex(
 		"spawn_cli",
 		3,
@@ -531,8 +646,10 @@ async fn process_socket(
 		code_server_args,
 		platform,
 		requires_auth,
+		auth_attempts,
 	} = params;
 
+	METRICS.sockets_active.inc();
 	let (http_delegated, mut http_rx) = DelegatedSimpleHttp::new(log.clone());
 	let (socket_tx, mut socket_rx) = mpsc::channel(4);
 	let rx_counter = Arc::new(AtomicUsize::new(0));
@@ -547,6 +664,7 @@ async fn process_socket(
 		code_server_args,
 		port_forwarding,
 		requires_auth,
+		auth_attempts,
 		platform,
 		http_requests.clone(),
 	);
@@ -609,6 +727,7 @@ async fn process_socket(
 				http_requests.lock().unwrap().insert(id, r);
 
 				tx_counter += serialized.len();
+				METRICS.socket_tx_bytes.inc_by(&[], serialized.len() as u64);
 				if let Err(e) = writehalf.write_all(&serialized).await {
 					debug!(log, "Closing connection: {}", e);
 					break;
@@ -619,6 +738,7 @@ async fn process_socket(
 				Some(message) => match message {
 					SocketSignal::Send(bytes) => {
 						tx_counter += bytes.len();
+						METRICS.socket_tx_bytes.inc_by(&[], bytes.len() as u64);
 						if let Err(e) = writehalf.write_all(&bytes).await {
 							debug!(log, "Closing connection: {}", e);
 							break;
@@ -633,10 +753,15 @@ async fn process_socket(
 		}
 	}
 
-	SocketStats {
+	let stats = SocketStats {
 		tx: tx_counter,
 		rx: rx_counter.load(Ordering::Acquire),
-	}
+	};
+
+	METRICS.sockets_served.inc();
+	METRICS.sockets_active.dec();
+
+	stats
 }
 
 async fn send_version(tx: &mpsc::Sender<SocketSignal>) {
@@ -670,6 +795,7 @@ async fn handle_socket_read(
 		}
 
 		rx_counter.fetch_add(read_len, Ordering::Relaxed);
+		METRICS.socket_rx_bytes.inc_by(&[], read_len as u64);
 
 		while let Some(frame) = decoder.decode(&mut decoder_buf)? {
 			match rpc.dispatch_with_partial(&frame.vec, frame.obj) {
@@ -942,39 +1068,228 @@ fn handle_get_hostname() -> Result<GetHostnameResponse, AnyError> {
 }
 
 fn handle_stat(path: String) -> Result<FsStatResponse, AnyError> {
-	Ok(std::fs::metadata(path)
-		.map(|m| FsStatResponse {
-			exists: true,
-			size: Some(m.len()),
-			kind: Some(m.file_type().into()),
-		})
-		.unwrap_or_default())
+	Ok(stat_path(Path::new(&path)))
+}
+
+fn stat_path(path: &Path) -> FsStatResponse {
+	let m = match std::fs::metadata(path) {
+		Ok(m) => m,
+		Err(_) => return FsStatResponse::default(),
+	};
+
+	let link_target = std::fs::symlink_metadata(path)
+		.ok()
+		.filter(|l| l.file_type().is_symlink())
+		.and_then(|_| std::fs::read_link(path).ok())
+		.map(|t| t.to_string_lossy().into_owned());
+
+	let mut stat = FsStatResponse {
+		exists: true,
+		size: Some(m.len()),
+		kind: Some(m.file_type().into()),
+		mtime: m.modified().ok().and_then(system_time_millis),
+		link_target,
+		..Default::default()
+	};
+
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::MetadataExt;
+		stat.ctime = u64::try_from(m.ctime())
+			.ok()
+			.map(|s| s * 1000 + m.ctime_nsec() as u64 / 1_000_000);
+		stat.mode = Some(m.mode() & 0o7777);
+		stat.uid = Some(m.uid());
+		stat.gid = Some(m.gid());
+	}
+	#[cfg(not(unix))]
+	{
+		stat.ctime = m.created().ok().and_then(system_time_millis);
+	}
+
+	stat
+}
+
+fn system_time_millis(t: std::time::SystemTime) -> Option<u64> {
+	t.duration_since(std::time::UNIX_EPOCH)
+		.ok()
+		.map(|d| d.as_millis() as u64)
 }
 
-async fn handle_fs_read(mut out: DuplexStream, path: String) -> Result<EmptyObject, AnyError> {
-	let mut f = tokio::fs::File::open(path)
+async fn handle_fs_read(
+	mut out: DuplexStream,
+	req: FsReadRequest,
+) -> Result<EmptyObject, AnyError> {
+	let mut f = tokio::fs::File::open(req.path)
 		.await
 		.map_err(|e| wrap(e, "file not found"))?;
 
-	tokio::io::copy(&mut f, &mut out)
-		.await
-		.map_err(|e| wrap(e, "error reading file"))?;
+	if req.offset != 0 {
+		let start = match u64::try_from(req.offset) {
+			Ok(offset) => offset,
+			Err(_) => {
+				let len = f
+					.metadata()
+					.await
+					.map_err(|e| wrap(e, "error reading file"))?
+					.len();
+				len.saturating_sub(req.offset.unsigned_abs())
+			}
+		};
+		f.seek(SeekFrom::Start(start))
+			.await
+			.map_err(|e| wrap(e, "error seeking in file"))?;
+	}
+
+	match req.length {
+		Some(length) => tokio::io::copy(&mut f.take(length), &mut out).await,
+		None => tokio::io::copy(&mut f, &mut out).await,
+	}
+	.map_err(|e| wrap(e, "error reading file"))?;
 
 	Ok(EmptyObject {})
 }
 
-async fn handle_fs_write(mut input: DuplexStream, path: String) -> Result<EmptyObject, AnyError> {
-	let mut f = tokio::fs::File::create(path)
+async fn handle_fs_write(
+	mut input: DuplexStream,
+	req: FsWriteRequest,
+) -> Result<EmptyObject, AnyError> {
+	let mut options = tokio::fs::OpenOptions::new();
+	options
+		.write(true)
+		.create(!req.create_exclusive)
+		.create_new(req.create_exclusive)
+		.truncate(req.mode == FsWriteMode::Truncate)
+		.append(req.mode == FsWriteMode::Append);
+
+	let mut f = options
+		.open(req.path)
 		.await
-		.map_err(|e| wrap(e, "file not found"))?;
+		.map_err(|e| wrap(e, "could not open file"))?;
+
+	if req.mode == FsWriteMode::Overwrite && req.offset > 0 {
+		f.seek(SeekFrom::Start(req.offset))
+			.await
+			.map_err(|e| wrap(e, "error seeking in file"))?;
+	}
 
 	tokio::io::copy(&mut input, &mut f)
 		.await
 		.map_err(|e| wrap(e, "error writing file"))?;
 
+	if req.fsync {
+		f.sync_all()
+			.await
+			.map_err(|e| wrap(e, "error flushing file"))?;
+	} else {
+		f.flush().await.map_err(|e| wrap(e, "error writing file"))?;
+	}
+
 	Ok(EmptyObject {})
 }
 
+async fn handle_fs_watch(
+	stream: DuplexStream,
+	req: FsWatchRequest,
+) -> Result<EmptyObject, AnyError> {
+	let mut watcher = FsWatcher::new(
+		PathBuf::from(req.path),
+		req.recursive,
+		Excludes::new(req.excludes),
+	)
+	.map_err(|e| wrap(e, "could not watch path"))?;
+
+	let (mut read, mut write) = tokio::io::split(stream);
+	let mut buf = [0u8; 64];
+	loop {
+		tokio::select! {
+			// nothing is expected on the stream, reading only notices it's closed
+			n = read.read(&mut buf) => match n {
+				Ok(0) | Err(_) => break,
+				Ok(_) => continue,
+			},
+			events = watcher.next() => {
+				let events = match events.map_err(|e| wrap(e, "error watching path"))? {
+					Some(e) => e,
+					None => break,
+				};
+				for event in events {
+					let mut line = serde_json::to_vec(&event).unwrap();
+					line.push(b'\n');
+					if write.write_all(&line).await.is_err() {
+						return Ok(EmptyObject {});
+					}
+				}
+			}
+		}
+	}
+
+	Ok(EmptyObject {})
+}
+
+async fn handle_fs_search(
+	stream: DuplexStream,
+	req: FsSearchRequest,
+) -> Result<FsSearchResponse, AnyError> {
+	let query = match req.is_regex {
+		true => req.query,
+		false => regex::escape(&req.query),
+	};
+	let pattern = regex::RegexBuilder::new(&query)
+		.case_insensitive(!req.case_sensitive)
+		.build()
+		.map_err(|e| wrap(e, "invalid search query"))?;
+
+	let options = SearchOptions {
+		root: PathBuf::from(req.path),
+		pattern,
+		includes: SearchOptions::normalize_globs(req.includes),
+		excludes: Excludes::new(SearchOptions::normalize_globs(req.excludes)),
+		use_ignore_files: !req.include_ignored,
+		max_results: req.max_results.unwrap_or(DEFAULT_SEARCH_MAX_RESULTS),
+		cancelled: Arc::new(AtomicBool::new(false)),
+	};
+
+	// the search stops once it's cancelled, or the receiver is dropped
+	let cancelled = options.cancelled.clone();
+	let (tx, mut rx) = mpsc::channel(128);
+	let search = tokio::task::spawn_blocking(move || {
+		fs_search::search(&options, |m| tx.blocking_send(m).is_ok())
+	});
+
+	let (mut read, mut write) = tokio::io::split(stream);
+	let mut buf = [0u8; 64];
+	loop {
+		tokio::select! {
+			// nothing is expected on the stream, reading only notices it's closed
+			n = read.read(&mut buf) => match n {
+				Ok(0) | Err(_) => break,
+				Ok(_) => continue,
+			},
+			m = rx.recv() => {
+				let m = match m {
+					Some(m) => m,
+					None => break,
+				};
+				let mut line = serde_json::to_vec(&m).unwrap();
+				line.push(b'\n');
+				if write.write_all(&line).await.is_err() {
+					break;
+				}
+			}
+		}
+	}
+
+	// stops walking the tree if the stream closed, even between matches
+	cancelled.store(true, Ordering::Relaxed);
+	drop(rx);
+	let summary = search.await.map_err(|e| wrap(e, "error searching files"))?;
+	Ok(FsSearchResponse {
+		result_count: summary.result_count,
+		limit_hit: summary.limit_hit,
+	})
+}
+
 async fn handle_net_connect(
 	mut stream: DuplexStream,
 	req: NetConnectRequest,
@@ -1021,26 +1336,235 @@ fn handle_fs_mkdirp(path: String) -> Result<EmptyObject, AnyError> {
 	Ok(EmptyObject {})
 }
 
-fn handle_fs_readdir(path: String) -> Result<FsReadDirResponse, AnyError> {
-	let mut entries = std::fs::read_dir(path).map_err(|e| wrap(e, "error listing directory"))?;
+async fn handle_fs_copy(req: FsCopyRequest) -> Result<EmptyObject, AnyError> {
+	tokio::task::spawn_blocking(move || {
+		let from = PathBuf::from(req.from_path);
+		let to = PathBuf::from(req.to_path);
+		if !req.overwrite && std::fs::symlink_metadata(&to).is_ok() {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::AlreadyExists,
+				format!("{} already exists", to.display()),
+			));
+		}
+
+		// copying a file onto itself would truncate it
+		let from_real = std::fs::canonicalize(&from)?;
+		if std::fs::canonicalize(&to).ok().as_ref() == Some(&from_real) {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				"cannot copy a path onto itself",
+			));
+		}
+
+		// copying a directory into itself would never end
+		let to_parent = to.parent().and_then(|p| std::fs::canonicalize(p).ok());
+		if let Some(to_parent) = to_parent {
+			if to_parent.starts_with(&from_real) && from_real.is_dir() {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::InvalidInput,
+					"cannot copy a directory into itself",
+				));
+			}
+		}
+
+		copy_recursive(&from, &to)
+	})
+	.await
+	.map_err(|e| wrap(e, "error copying"))?
+	.map_err(|e| wrap(e, "error copying"))?;
+
+	Ok(EmptyObject {})
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+	let meta = std::fs::symlink_metadata(from)?;
+	if meta.is_dir() {
+		std::fs::create_dir_all(to)?;
+		for entry in std::fs::read_dir(from)? {
+			let entry = entry?;
+			copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+		}
+		// set after copying the contents, in case it's read-only
+		std::fs::set_permissions(to, meta.permissions())?;
+	} else if meta.file_type().is_symlink() {
+		if std::fs::symlink_metadata(to).is_ok() {
+			std::fs::remove_file(to)?;
+		}
+		create_symlink(&std::fs::read_link(from)?, to)?;
+	} else {
+		std::fs::copy(from, to)?;
+	}
+
+	Ok(())
+}
+
+fn handle_fs_chmod(path: String, mode: u32) -> Result<EmptyObject, AnyError> {
+	#[cfg(unix)]
+	let permissions = {
+		use std::os::unix::fs::PermissionsExt;
+		std::fs::Permissions::from_mode(mode & 0o7777)
+	};
+	#[cfg(not(unix))]
+	let permissions = {
+		let mut p = std::fs::metadata(&path)
+			.map_err(|e| wrap(e, "error changing permissions"))?
+			.permissions();
+		p.set_readonly(mode & 0o222 == 0);
+		p
+	};
+
+	std::fs::set_permissions(path, permissions)
+		.map_err(|e| wrap(e, "error changing permissions"))?;
+	Ok(EmptyObject {})
+}
+
+fn handle_fs_symlink(target: String, path: String) -> Result<EmptyObject, AnyError> {
+	create_symlink(Path::new(&target), Path::new(&path))
+		.map_err(|e| wrap(e, "error creating symlink"))?;
+	Ok(EmptyObject {})
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, path: &Path) -> std::io::Result<()> {
+	std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, path: &Path) -> std::io::Result<()> {
+	// Windows needs to know whether the link is to a directory, and relative
+	// targets are relative to the link's directory
+	let resolved = match path.parent() {
+		Some(dir) => dir.join(target),
+		None => target.to_owned(),
+	};
+	if resolved.is_dir() {
+		std::os::windows::fs::symlink_dir(target, path)
+	} else {
+		std::os::windows::fs::symlink_file(target, path)
+	}
+}
+
+fn handle_fs_realpath(path: String) -> Result<FsRealpathResponse, AnyError> {
+	let path = std::fs::canonicalize(path)
+		.map_err(|e| wrap(e, "error resolving path"))?
+		.to_string_lossy()
+		.into_owned();
+	// drop the verbatim prefix that Windows adds to canonical paths
+	#[cfg(windows)]
+	let path = match path.strip_prefix(r"\\?\") {
+		Some(p) if !p.starts_with("UNC") => p.to_string(),
+		_ => path,
+	};
+
+	Ok(FsRealpathResponse { path })
+}
+
+fn handle_fs_readdir(req: FsReadDirRequest) -> Result<FsReadDirResponse, AnyError> {
+	let mut entries =
+		std::fs::read_dir(req.path).map_err(|e| wrap(e, "error listing directory"))?;
 
 	let mut contents = Vec::new();
 	while let Some(Ok(child)) = entries.next() {
 		contents.push(FsReadDirEntry {
 			name: child.file_name().to_string_lossy().into_owned(),
 			kind: child.file_type().ok().map(|v| v.into()),
+			stat: req.stat.then(|| stat_path(&child.path())),
 		});
 	}
 
 	Ok(FsReadDirResponse { contents })
 }
 
-fn handle_sys_kill(pid: u32) -> Result<SysKillResponse, AnyError> {
+fn handle_sys_kill(pid: u32, signal: Option<i32>) -> Result<SysKillResponse, AnyError> {
+	// 0 and negative pids would signal process groups, or every process
+	#[cfg(unix)]
+	let unix_pid = libc::pid_t::try_from(pid)
+		.ok()
+		.filter(|p| *p > 0)
+		.ok_or(CodeError::InvalidProcessId(pid))?;
+
+	#[cfg(unix)]
+	if let Some(signal) = signal {
+		return Ok(SysKillResponse {
+			success: unsafe { libc::kill(unix_pid, signal) == 0 },
+		});
+	}
+	#[cfg(not(unix))]
+	let _ = signal; // only the default is supported on Windows
+
 	Ok(SysKillResponse {
 		success: kill_pid(pid),
 	})
 }
 
+async fn handle_sys_info(paths: &LauncherPaths) -> Result<SysInfoResponse, AnyError> {
+	let data_dir = std::fs::canonicalize(paths.root()).unwrap_or_else(|_| paths.root().to_owned());
+
+	let info = tokio::task::spawn_blocking(move || {
+		let mut sys = System::new();
+		sys.refresh_cpu();
+		sys.refresh_memory();
+		sys.refresh_disks_list();
+		sys.refresh_disks();
+
+		let data_dir_disk = sys
+			.disks()
+			.iter()
+			.filter(|d| data_dir.starts_with(d.mount_point()))
+			.max_by_key(|d| d.mount_point().as_os_str().len())
+			.map(|d| SysDiskUsage {
+				mount_point: d.mount_point().to_string_lossy().into_owned(),
+				total_space: d.total_space(),
+				available_space: d.available_space(),
+			});
+
+		let load = sys.load_average();
+		SysInfoResponse {
+			cpu_count: sys.cpus().len(),
+			load_average: [load.one, load.five, load.fifteen],
+			total_memory: sys.total_memory(),
+			free_memory: sys.free_memory(),
+			available_memory: sys.available_memory(),
+			os_release: os_release().ok(),
+			uptime: sys.uptime(),
+			data_dir_disk,
+		}
+	})
+	.await
+	.map_err(|e| wrap(e, "error getting system info"))?;
+
+	Ok(info)
+}
+
+async fn handle_sys_processes() -> Result<SysProcessesResponse, AnyError> {
+	let processes = tokio::task::spawn_blocking(|| {
+		let mut sys = System::new();
+		// CPU usage is measured between two refreshes
+		sys.refresh_processes();
+		std::thread::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL);
+		sys.refresh_processes();
+
+		let mut processes: Vec<SysProcess> = sys
+			.processes()
+			.values()
+			.map(|p| SysProcess {
+				pid: p.pid().as_u32(),
+				ppid: p.parent().map(|p| p.as_u32()),
+				name: p.name().to_string(),
+				command: p.cmd().to_vec(),
+				cpu: p.cpu_usage(),
+				rss: p.memory(),
+			})
+			.collect();
+		processes.sort_by_key(|p| p.pid);
+		processes
+	})
+	.await
+	.map_err(|e| wrap(e, "error listing processes"))?;
+
+	Ok(SysProcessesResponse { processes })
+}
+
 fn handle_get_env() -> Result<GetEnvResponse, AnyError> {
 	Ok(GetEnvResponse {
 		env: std::env::vars().collect(),
@@ -1055,18 +1579,19 @@ fn handle_get_env() -> Result<GetEnvResponse, AnyError> {
 }
 
 fn handle_challenge_issue(
+	log: &log::Logger,
 	params: ChallengeIssueParams,
 	auth_state: &Arc<std::sync::Mutex<AuthState>>,
+	auth_attempts: &AuthAttempts,
 ) -> Result<ChallengeIssueResponse, AnyError> {
+	auth_attempts.ensure_not_locked_out()?;
 	let challenge = create_challenge();
 
 	let mut auth_state = auth_state.lock().unwrap();
 	if let AuthState::WaitingForChallenge(Some(s)) = &*auth_state {
-		println!("looking for token {}, got {:?}", s, params.token);
-		match &params.token {
-			Some(t) if s != t => return Err(CodeError::AuthChallengeBadToken.into()),
-			None => return Err(CodeError::AuthChallengeBadToken.into()),
-			_ => {}
+		if params.token.as_ref() != Some(s) {
+			auth_attempts.record_failure(log, "connection token");
+			return Err(CodeError::AuthChallengeBadToken.into());
 		}
 	}
 
@@ -1075,17 +1600,24 @@ fn handle_challenge_issue(
 }
 
 fn handle_challenge_verify(
+	log: &log::Logger,
 	response: String,
 	auth_state: &Arc<std::sync::Mutex<AuthState>>,
+	auth_attempts: &AuthAttempts,
 ) -> Result<EmptyObject, AnyError> {
+	auth_attempts.ensure_not_locked_out()?;
 	let mut auth_state = auth_state.lock().unwrap();
 
 	match &*auth_state {
 		AuthState::Authenticated => Ok(EmptyObject {}),
 		AuthState::WaitingForChallenge(_) => Err(CodeError::AuthChallengeNotIssued.into()),
 		AuthState::ChallengeIssued(c) => match verify_challenge(c, &response) {
-			false => Err(CodeError::AuthChallengeNotIssued.into()),
+			false => {
+				auth_attempts.record_failure(log, "challenge response");
+				Err(CodeError::AuthChallengeNotIssued.into())
+			}
 			true => {
+				auth_attempts.record_success();
 				*auth_state = AuthState::Authenticated;
 				Ok(EmptyObject {})
 			}
@@ -1186,6 +1718,7 @@ async fn handle_acquire_cli(
 	paths: &LauncherPaths,
 	http: &Arc<FallbackSimpleHttp>,
 	log: &log::Logger,
+	spawned: &SpawnedProcesses,
 	params: AcquireCliParams,
 ) -> Result<SpawnResult, AnyError> {
 	let update_service = UpdateService::new(log.clone(), http.clone());
@@ -1210,11 +1743,12 @@ async fn handle_acquire_cli(
 		.await
 		.map_err(|e| wrap(e, "error opening cli file"))?;
 
-	handle_spawn::<_, DuplexStream>(log, params.spawn, Some(file), None, None).await
+	handle_spawn::<_, DuplexStream>(log, spawned, params.spawn, Some(file), None, None).await
 }
 
 async fn handle_spawn<Stdin, StdoutAndErr>(
 	log: &log::Logger,
+	spawned: &SpawnedProcesses,
 	params: SpawnParams,
 	stdin: Option<Stdin>,
 	stdout: Option<StdoutAndErr>,
@@ -1239,34 +1773,197 @@ where
 		};
 	}
 
-	let mut p = new_tokio_command(&params.command);
-	p.args(&params.args);
-	p.envs(&params.env);
-	p.stdin(pipe_if!(stdin.is_some()));
-	p.stdout(pipe_if!(stdin.is_some()));
-	p.stderr(pipe_if!(stderr.is_some()));
+	let mut pty = match params.pty {
+		Some(size) => Some(
+			Pty::open(size.cols, size.rows)
+				.map_err(|e| wrap(e, "could not open pseudo-terminal"))?,
+		),
+		None => None,
+	};
+
+	let mut cmd = new_tokio_command(&params.command);
+	cmd.args(&params.args);
+	cmd.envs(&params.env);
+	match &mut pty {
+		Some(pty) => pty
+			.attach(&mut cmd)
+			.map_err(|e| wrap(e, "could not attach pseudo-terminal"))?,
+		None => {
+			cmd.stdin(pipe_if!(stdin.is_some()));
+			cmd.stdout(pipe_if!(stdin.is_some()));
+			cmd.stderr(pipe_if!(stderr.is_some()));
+		}
+	}
 	if let Some(cwd) = &params.cwd {
-		p.current_dir(cwd);
+		cmd.current_dir(cwd);
 	}
 
 	#[cfg(target_os = "windows")]
-	p.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
-
-	let mut p = p.spawn().map_err(CodeError::ProcessSpawnFailed)?;
+	cmd.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
+
+	let mut p = cmd.spawn().map_err(CodeError::ProcessSpawnFailed)?;
+	// closes our copies of the terminal's process side, so reads from the
+	// terminal end once the process exits
+	drop(cmd);
+	METRICS.process_spawns.inc_by(&[("kind", "rpc")], 1);
+
+	let pty = pty.map(Arc::new);
+	let _registration = match (&params.id, p.id()) {
+		(Some(id), Some(pid)) => Some(spawned.register(id.clone(), pid, pty.clone())),
+		_ => None,
+	};
 
 	let block_futs = FuturesUnordered::new();
 	let poll_futs = FuturesUnordered::new();
-	if let (Some(mut a), Some(mut b)) = (p.stdout.take(), stdout) {
-		block_futs.push(async move { tokio::io::copy(&mut a, &mut b).await }.boxed());
+	if let Some(pty) = &pty {
+		let (mut output, mut input) = pty
+			.io()
+			.map_err(|e| wrap(e, "could not open pseudo-terminal"))?;
+		if let Some(mut b) = stdout {
+			block_futs.push(async move { tokio::io::copy(&mut output, &mut b).await }.boxed());
+		}
+		if let Some(mut a) = stdin {
+			poll_futs.push(async move { tokio::io::copy(&mut a, &mut input).await }.boxed());
+		}
+	} else {
+		if let (Some(mut a), Some(mut b)) = (p.stdout.take(), stdout) {
+			block_futs.push(async move { tokio::io::copy(&mut a, &mut b).await }.boxed());
+		}
+		if let (Some(mut a), Some(mut b)) = (p.stderr.take(), stderr) {
+			block_futs.push(async move { tokio::io::copy(&mut a, &mut b).await }.boxed());
+		}
+		if let (Some(mut b), Some(mut a)) = (p.stdin.take(), stdin) {
+			poll_futs.push(async move { tokio::io::copy(&mut a, &mut b).await }.boxed());
+		}
 	}
-	if let (Some(mut a), Some(mut b)) = (p.stderr.take(), stderr) {
-		block_futs.push(async move { tokio::io::copy(&mut a, &mut b).await }.boxed());
+
+	wait_for_process_exit(log, &params.command, p, block_futs, poll_futs).await
+}
+
+fn handle_spawn_resize(
+	spawned: &SpawnedProcesses,
+	params: SpawnResizeParams,
+) -> Result<EmptyObject, AnyError> {
+	let pty = spawned
+		.get(&params.id)
+		.ok_or_else(|| CodeError::SpawnedProcessNotFound(params.id.clone()))?
+		.pty
+		.ok_or_else(|| CodeError::SpawnedProcessWithoutPty(params.id.clone()))?;
+
+	pty.resize(params.cols, params.rows)
+		.map_err(|e| wrap(e, "error resizing pseudo-terminal"))?;
+	Ok(EmptyObject {})
+}
+
+fn handle_spawn_signal(
+	spawned: &SpawnedProcesses,
+	params: SpawnSignalParams,
+) -> Result<EmptyObject, AnyError> {
+	let process = spawned
+		.get(&params.id)
+		.ok_or_else(|| CodeError::SpawnedProcessNotFound(params.id.clone()))?;
+
+	send_signal(process.pid, params.signal, process.pty.is_some()).map_err(|e| {
+		match e.kind() {
+			// it exited after it was looked up
+			std::io::ErrorKind::NotFound => CodeError::SpawnedProcessNotFound(params.id).into(),
+			_ => AnyError::from(wrap(e, "error sending signal")),
+		}
+	})?;
+	Ok(EmptyObject {})
+}
+
+/// Sends the signal to the process, or to the process group it leads.
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: ProcessSignal, group: bool) -> std::io::Result<()> {
+	let signal = match signal {
+		ProcessSignal::Hangup => libc::SIGHUP,
+		ProcessSignal::Interrupt => libc::SIGINT,
+		ProcessSignal::Quit => libc::SIGQUIT,
+		ProcessSignal::Kill => libc::SIGKILL,
+		ProcessSignal::Terminate => libc::SIGTERM,
+		ProcessSignal::User1 => libc::SIGUSR1,
+		ProcessSignal::User2 => libc::SIGUSR2,
+	};
+
+	let pid = pid as libc::pid_t;
+	// the process may have left its group, or exited and had its pid reused
+	// as another group's, so only signal a group that it still leads
+	let target = match group && unsafe { libc::getpgid(pid) } == pid {
+		true => -pid,
+		false => pid,
+	};
+	if unsafe { libc::kill(target, signal) } != 0 {
+		let e = std::io::Error::last_os_error();
+		return Err(match e.raw_os_error() {
+			Some(libc::ESRCH) => {
+				std::io::Error::new(std::io::ErrorKind::NotFound, "process not found")
+			}
+			_ => e,
+		});
+	}
+
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn send_signal(pid: u32, signal: ProcessSignal, _group: bool) -> std::io::Result<()> {
+	match signal {
+		ProcessSignal::Kill | ProcessSignal::Terminate if kill_pid(pid) => Ok(()),
+		ProcessSignal::Kill | ProcessSignal::Terminate => Err(std::io::Error::new(
+			std::io::ErrorKind::NotFound,
+			"process not found",
+		)),
+		_ => Err(std::io::Error::new(
+			std::io::ErrorKind::Unsupported,
+			format!("{:?} is not supported on this platform", signal),
+		)),
 	}
-	if let (Some(mut b), Some(mut a)) = (p.stdin.take(), stdin) {
-		poll_futs.push(async move { tokio::io::copy(&mut a, &mut b).await }.boxed());
+}
+
+/// Running process started by `spawn` with an ID.
+#[derive(Clone)]
+struct SpawnedProcess {
+	pid: u32,
+	pty: Option<Arc<Pty>>,
+}
+
+#[derive(Clone, Default)]
+struct SpawnedProcesses(Arc<std::sync::Mutex<HashMap<String, SpawnedProcess>>>);
+
+impl SpawnedProcesses {
+	/// Registers a process, until the returned registration is dropped.
+	fn register(&self, id: String, pid: u32, pty: Option<Arc<Pty>>) -> SpawnedRegistration {
+		self.0
+			.lock()
+			.unwrap()
+			.insert(id.clone(), SpawnedProcess { pid, pty });
+		SpawnedRegistration {
+			processes: self.clone(),
+			id,
+			pid,
+		}
 	}
 
-	wait_for_process_exit(log, &params.command, p, block_futs, poll_futs).await
+	fn get(&self, id: &str) -> Option<SpawnedProcess> {
+		self.0.lock().unwrap().get(id).cloned()
+	}
+}
+
+struct SpawnedRegistration {
+	processes: SpawnedProcesses,
+	id: String,
+	pid: u32,
+}
+
+impl Drop for SpawnedRegistration {
+	fn drop(&mut self) {
+		let mut processes = self.processes.0.lock().unwrap();
+		// the ID may have been reused by a newer process
+		if processes.get(&self.id).map(|p| p.pid) == Some(self.pid) {
+			processes.remove(&self.id);
+		}
+	}
 }
 
 async fn handle_spawn_cli(
@@ -1297,6 +1994,7 @@ async fn handle_spawn_cli(
 	}
 
 	let mut p = p.spawn().map_err(CodeError::ProcessSpawnFailed)?;
+	METRICS.process_spawns.inc_by(&[("kind", "rpc")], 1);
 
 	let mut stdin = p.stdin.take().unwrap();
 	let mut stdout = p.stdout.take().unwrap();
@@ -1366,6 +2064,7 @@ async fn wait_for_process_exit(
 		log,
 		"spawned cli {} exited with code {}", command, r.exit_code
 	);
+	METRICS.process_exits.inc_by(&[("kind", "rpc")], 1);
 
 	Ok(r)
 }
@@ -1430,3 +2129,371 @@ async fn do_challenge_response_flow(
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+	use super::*;
+	use crate::tunnels::protocol::FsFileKind;
+
+	#[test]
+	fn test_stat_path() {
+		let dir = tempfile::tempdir().unwrap();
+		let file = dir.path().join("file");
+		std::fs::write(&file, "hello").unwrap();
+
+		let stat = stat_path(&file);
+		assert!(stat.exists);
+		assert_eq!(stat.size, Some(5));
+		assert!(matches!(stat.kind, Some(FsFileKind::File)));
+		assert!(stat.link_target.is_none());
+		let now = system_time_millis(SystemTime::now()).unwrap();
+		for time in [stat.mtime, stat.ctime] {
+			let time = time.unwrap();
+			assert!(time <= now && time > now - 60_000);
+		}
+
+		let stat = stat_path(dir.path());
+		assert!(stat.exists);
+		assert!(matches!(stat.kind, Some(FsFileKind::Directory)));
+
+		let stat = stat_path(&dir.path().join("missing"));
+		assert!(!stat.exists);
+		assert!(stat.size.is_none() && stat.kind.is_none() && stat.mtime.is_none());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_stat_path_unix() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = tempfile::tempdir().unwrap();
+		let file = dir.path().join("file");
+		std::fs::write(&file, "hello").unwrap();
+		std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o4640)).unwrap();
+
+		let stat = stat_path(&file);
+		assert_eq!(stat.mode, Some(0o4640));
+		assert_eq!(stat.uid, Some(unsafe { libc::getuid() }));
+		assert_eq!(stat.gid, Some(unsafe { libc::getgid() }));
+
+		// symlinks are followed, but their target is reported
+		let link = dir.path().join("link");
+		std::os::unix::fs::symlink("file", &link).unwrap();
+		let stat = stat_path(&link);
+		assert_eq!(stat.size, Some(5));
+		assert!(matches!(stat.kind, Some(FsFileKind::File)));
+		assert_eq!(stat.link_target.as_deref(), Some("file"));
+
+		let dangling = dir.path().join("dangling");
+		std::os::unix::fs::symlink("missing", &dangling).unwrap();
+		assert!(!stat_path(&dangling).exists);
+	}
+
+	#[test]
+	fn test_system_time_millis() {
+		assert_eq!(system_time_millis(UNIX_EPOCH), Some(0));
+		assert_eq!(
+			system_time_millis(UNIX_EPOCH + Duration::from_micros(1_500_999)),
+			Some(1500)
+		);
+		assert_eq!(
+			system_time_millis(UNIX_EPOCH - Duration::from_secs(1)),
+			None
+		);
+	}
+
+	#[test]
+	fn test_readdir_stat() {
+		let dir = tempfile::tempdir().unwrap();
+		std::fs::write(dir.path().join("file"), "hello").unwrap();
+		std::fs::create_dir(dir.path().join("subdir")).unwrap();
+		let read = |stat| {
+			let mut contents = handle_fs_readdir(FsReadDirRequest {
+				path: dir.path().to_string_lossy().into_owned(),
+				stat,
+			})
+			.unwrap()
+			.contents;
+			contents.sort_by(|a, b| a.name.cmp(&b.name));
+			contents
+		};
+
+		let contents = read(false);
+		assert_eq!(contents.len(), 2);
+		assert!(contents.iter().all(|e| e.stat.is_none()));
+
+		let contents = read(true);
+		assert_eq!(contents[0].name, "file");
+		assert!(matches!(contents[0].kind, Some(FsFileKind::File)));
+		let stat = contents[0].stat.as_ref().unwrap();
+		assert!(stat.exists);
+		assert_eq!(stat.size, Some(5));
+		assert_eq!(contents[1].name, "subdir");
+		let stat = contents[1].stat.as_ref().unwrap();
+		assert!(matches!(stat.kind, Some(FsFileKind::Directory)));
+
+		assert!(handle_fs_readdir(FsReadDirRequest {
+			path: dir.path().join("missing").to_string_lossy().into_owned(),
+			stat: true,
+		})
+		.is_err());
+	}
+
+	async fn read_file(path: &Path, offset: i64, length: Option<u64>) -> Vec<u8> {
+		let (out, mut read) = tokio::io::duplex(1024);
+		let req = FsReadRequest {
+			path: path.to_string_lossy().into_owned(),
+			offset,
+			length,
+		};
+		let mut contents = Vec::new();
+		let (r, _) = tokio::join!(handle_fs_read(out, req), read.read_to_end(&mut contents));
+		r.unwrap();
+		contents
+	}
+
+	async fn write_file(
+		path: &Path,
+		contents: &str,
+		mode: FsWriteMode,
+		offset: u64,
+		create_exclusive: bool,
+	) -> Result<EmptyObject, AnyError> {
+		let (input, mut write) = tokio::io::duplex(1024);
+		write.write_all(contents.as_bytes()).await.unwrap();
+		drop(write);
+		handle_fs_write(
+			input,
+			FsWriteRequest {
+				path: path.to_string_lossy().into_owned(),
+				mode,
+				offset,
+				create_exclusive,
+				fsync: false,
+			},
+		)
+		.await
+	}
+
+	#[tokio::test]
+	async fn test_fs_read_ranges() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("file");
+		std::fs::write(&path, "0123456789").unwrap();
+
+		assert_eq!(read_file(&path, 0, None).await, b"0123456789");
+		assert_eq!(read_file(&path, 2, Some(3)).await, b"234");
+		assert_eq!(read_file(&path, 8, Some(5)).await, b"89");
+		assert_eq!(read_file(&path, 20, None).await, b"");
+		assert_eq!(read_file(&path, -3, None).await, b"789");
+		assert_eq!(read_file(&path, -3, Some(1)).await, b"7");
+		// offsets before the start of the file read from the start
+		assert_eq!(read_file(&path, -100, None).await, b"0123456789");
+		assert_eq!(read_file(&path, i64::MIN, Some(2)).await, b"01");
+	}
+
+	#[tokio::test]
+	async fn test_fs_write_modes() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("file");
+
+		write_file(&path, "hello world", FsWriteMode::Truncate, 0, true)
+			.await
+			.unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+		// exclusive creation fails once the file exists, leaving it as is
+		assert!(write_file(&path, "other", FsWriteMode::Truncate, 0, true)
+			.await
+			.is_err());
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+
+		write_file(&path, "!", FsWriteMode::Append, 0, false)
+			.await
+			.unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world!");
+
+		write_file(&path, "W", FsWriteMode::Overwrite, 6, false)
+			.await
+			.unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello World!");
+		write_file(&path, "Hi", FsWriteMode::Overwrite, 0, false)
+			.await
+			.unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "Hillo World!");
+		// past the end, the gap is filled with zeros
+		write_file(&path, "?", FsWriteMode::Overwrite, 14, false)
+			.await
+			.unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), b"Hillo World!\0\0?");
+
+		write_file(&path, "bye", FsWriteMode::Truncate, 0, false)
+			.await
+			.unwrap();
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "bye");
+
+		// overwrite and append create missing files too
+		let created = dir.path().join("created");
+		write_file(&created, "new", FsWriteMode::Overwrite, 0, false)
+			.await
+			.unwrap();
+		assert_eq!(std::fs::read_to_string(&created).unwrap(), "new");
+	}
+
+	async fn copy(from: &Path, to: &Path, overwrite: bool) -> Result<EmptyObject, AnyError> {
+		handle_fs_copy(FsCopyRequest {
+			from_path: from.to_string_lossy().into_owned(),
+			to_path: to.to_string_lossy().into_owned(),
+			overwrite,
+		})
+		.await
+	}
+
+	#[tokio::test]
+	async fn test_fs_copy() {
+		let dir = tempfile::tempdir().unwrap();
+		let a = dir.path().join("a");
+		std::fs::create_dir_all(a.join("sub")).unwrap();
+		std::fs::write(a.join("sub/file"), "a").unwrap();
+
+		let b = dir.path().join("b");
+		copy(&a, &b, false).await.unwrap();
+		assert_eq!(std::fs::read_to_string(b.join("sub/file")).unwrap(), "a");
+
+		// existing paths are only replaced with overwrite
+		std::fs::write(a.join("sub/file"), "changed").unwrap();
+		assert!(copy(&a.join("sub/file"), &b.join("sub/file"), false)
+			.await
+			.is_err());
+		assert_eq!(std::fs::read_to_string(b.join("sub/file")).unwrap(), "a");
+		copy(&a.join("sub/file"), &b.join("sub/file"), true)
+			.await
+			.unwrap();
+		assert_eq!(
+			std::fs::read_to_string(b.join("sub/file")).unwrap(),
+			"changed"
+		);
+
+		assert!(copy(&dir.path().join("missing"), &b, true).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_fs_copy_onto_itself() {
+		let dir = tempfile::tempdir().unwrap();
+		let file = dir.path().join("file");
+		std::fs::write(&file, "contents").unwrap();
+
+		assert!(copy(&file, &file, true).await.is_err());
+		assert!(copy(&file, &dir.path().join(".").join("file"), true)
+			.await
+			.is_err());
+		assert_eq!(std::fs::read_to_string(&file).unwrap(), "contents");
+
+		let sub = dir.path().join("sub");
+		std::fs::create_dir(&sub).unwrap();
+		assert!(copy(dir.path(), &sub.join("copy"), false).await.is_err());
+		assert!(copy(dir.path(), &sub, true).await.is_err());
+		assert!(!sub.join("copy").exists());
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn test_fs_copy_symlinks() {
+		let dir = tempfile::tempdir().unwrap();
+		let a = dir.path().join("a");
+		std::fs::create_dir(&a).unwrap();
+		std::fs::write(a.join("file"), "contents").unwrap();
+		handle_fs_symlink(
+			"file".to_string(),
+			a.join("link").to_string_lossy().into_owned(),
+		)
+		.unwrap();
+		assert_eq!(std::fs::read_to_string(a.join("link")).unwrap(), "contents");
+		assert!(handle_fs_symlink(
+			"other".to_string(),
+			a.join("link").to_string_lossy().into_owned()
+		)
+		.is_err());
+
+		// links are copied as links
+		let b = dir.path().join("b");
+		copy(&a, &b, false).await.unwrap();
+		assert_eq!(
+			std::fs::read_link(b.join("link")).unwrap(),
+			PathBuf::from("file")
+		);
+
+		// a link to the source is the same path, and isn't written through
+		let elsewhere = dir.path().join("elsewhere");
+		std::os::unix::fs::symlink(a.join("file"), &elsewhere).unwrap();
+		assert!(copy(&a.join("file"), &elsewhere, true).await.is_err());
+		assert_eq!(std::fs::read_to_string(a.join("file")).unwrap(), "contents");
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn test_send_signal() {
+		use std::os::unix::process::ExitStatusExt;
+
+		// a process in its own session leads its group, which is signaled
+		let mut pty = Pty::open(80, 24).unwrap();
+		let mut cmd = tokio::process::Command::new("sleep");
+		cmd.arg("30");
+		pty.attach(&mut cmd).unwrap();
+		let mut child = cmd.spawn().unwrap();
+		drop(cmd);
+		send_signal(child.id().unwrap(), ProcessSignal::Terminate, true).unwrap();
+		assert_eq!(child.wait().await.unwrap().signal(), Some(libc::SIGTERM));
+
+		// otherwise only the process is
+		let mut child = tokio::process::Command::new("sleep")
+			.arg("30")
+			.spawn()
+			.unwrap();
+		let pid = child.id().unwrap();
+		send_signal(pid, ProcessSignal::Kill, true).unwrap();
+		assert_eq!(child.wait().await.unwrap().signal(), Some(libc::SIGKILL));
+
+		// and once it exited, it's not found
+		let e = send_signal(pid, ProcessSignal::Kill, true).unwrap_err();
+		assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_sys_kill_invalid_pid() {
+		for pid in [0, u32::MAX, i32::MAX as u32 + 1] {
+			for signal in [Some(0), None] {
+				assert!(matches!(
+					handle_sys_kill(pid, signal),
+					Err(AnyError::CodeError(CodeError::InvalidProcessId(p))) if p == pid
+				));
+			}
+		}
+
+		let pid = std::process::id();
+		assert!(handle_sys_kill(pid, Some(0)).unwrap().success);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_fs_chmod() {
+		let dir = tempfile::tempdir().unwrap();
+		let file = dir.path().join("file");
+		std::fs::write(&file, "").unwrap();
+		let path = file.to_string_lossy().into_owned();
+
+		handle_fs_chmod(path.clone(), 0o600).unwrap();
+		assert_eq!(stat_path(&file).mode, Some(0o600));
+		// only the permission bits are used
+		handle_fs_chmod(path, 0o100755).unwrap();
+		assert_eq!(stat_path(&file).mode, Some(0o755));
+
+		assert!(handle_fs_chmod(
+			dir.path().join("missing").to_string_lossy().into_owned(),
+			0o600
+		)
+		.is_err());
+	}
+}
@@ -0,0 +1,516 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use regex::Regex;
+use serde::Serialize;
+
+use super::fs_watch::{glob_matches, Excludes};
+
+/// Files larger than this are not searched.
+const MAX_FILE_SIZE: u64 = 50 * 1024 * 1024;
+/// Longest preview of a matched line, in characters.
+const MAX_PREVIEW_CHARS: usize = 250;
+/// Characters of context kept before the match when a preview is shortened.
+const PREVIEW_CONTEXT_CHARS: usize = 50;
+
+/// Match found by `fs_search`, written as a line of JSON to its stream. The
+/// line and column are 1-based, and the column counts characters.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct FsSearchMatch {
+	pub path: String,
+	pub line: u64,
+	pub column: u64,
+	/// Text of the line, shortened around the match if it's long
+	pub preview: String,
+}
+
+pub struct SearchOptions {
+	pub root: PathBuf,
+	pub pattern: Regex,
+	/// Globs that files must match, if any are given
+	pub includes: Vec<String>,
+	pub excludes: Excludes,
+	pub use_ignore_files: bool,
+	pub max_results: usize,
+	/// Set to stop the search early, such as once its results aren't wanted
+	pub cancelled: Arc<AtomicBool>,
+}
+
+impl SearchOptions {
+	/// Converts include or exclude patterns without a `/` to match at any
+	/// depth, so `*.rs` matches all Rust files.
+	pub fn normalize_globs(patterns: Vec<String>) -> Vec<String> {
+		patterns
+			.into_iter()
+			.map(|p| match p.trim_matches('/').contains('/') {
+				true => p,
+				false => format!("**/{}", p),
+			})
+			.collect()
+	}
+}
+
+pub struct SearchSummary {
+	pub result_count: usize,
+	pub limit_hit: bool,
+}
+
+/// Searches the files under the root, calling `on_match` with each match
+/// until it returns false, the result limit is reached or it's cancelled.
+pub fn search(
+	options: &SearchOptions,
+	on_match: impl FnMut(FsSearchMatch) -> bool,
+) -> SearchSummary {
+	let mut searcher = Searcher {
+		options,
+		on_match,
+		ignores: Vec::new(),
+		result_count: 0,
+		limit_hit: false,
+		stopped: false,
+	};
+
+	if options.root.is_dir() {
+		searcher.search_dir(&options.root, "");
+	} else {
+		searcher.search_file(&options.root);
+	}
+
+	SearchSummary {
+		result_count: searcher.result_count,
+		limit_hit: searcher.limit_hit,
+	}
+}
+
+struct Searcher<'a, F> {
+	options: &'a SearchOptions,
+	on_match: F,
+	/// Ignore files of the directories being searched, outermost first
+	ignores: Vec<IgnoreFile>,
+	result_count: usize,
+	limit_hit: bool,
+	stopped: bool,
+}
+
+impl<'a, F: FnMut(FsSearchMatch) -> bool> Searcher<'a, F> {
+	/// Searches a directory, whose path relative to the root is `relative`.
+	fn search_dir(&mut self, dir: &Path, relative: &str) {
+		let ignore_file = match self.options.use_ignore_files {
+			true => fs::read_to_string(dir.join(".gitignore")).ok(),
+			false => None,
+		};
+		if let Some(contents) = &ignore_file {
+			self.ignores.push(IgnoreFile::parse(relative, contents));
+		}
+
+		let mut entries: Vec<_> = match fs::read_dir(dir) {
+			Ok(e) => e.flatten().collect(),
+			Err(_) => Vec::new(),
+		};
+		entries.sort_by_key(|e| e.file_name());
+
+		for entry in entries {
+			if self.stopped || self.options.cancelled.load(Ordering::Relaxed) {
+				self.stopped = true;
+				break;
+			}
+
+			// file_type() doesn't follow symlinks, so linked files and directories are skipped
+			let file_type = match entry.file_type() {
+				Ok(t) => t,
+				Err(_) => continue,
+			};
+			let name = entry.file_name();
+			let name = name.to_string_lossy();
+			let child = match relative.is_empty() {
+				true => name.to_string(),
+				false => format!("{}/{}", relative, name),
+			};
+
+			let path = entry.path();
+			if (file_type.is_dir() && name == ".git")
+				|| self.options.excludes.is_excluded(&self.options.root, &path)
+				|| self.is_ignored(&child, file_type.is_dir())
+			{
+				continue;
+			}
+
+			if file_type.is_dir() {
+				self.search_dir(&path, &child);
+			} else if file_type.is_file() && self.is_included(&child) {
+				self.search_file(&path);
+			}
+		}
+
+		if ignore_file.is_some() {
+			self.ignores.pop();
+		}
+	}
+
+	fn search_file(&mut self, path: &Path) {
+		let file = match fs::File::open(path) {
+			Ok(f) => f,
+			Err(_) => return,
+		};
+		if file
+			.metadata()
+			.map(|m| m.len() > MAX_FILE_SIZE)
+			.unwrap_or(true)
+		{
+			return;
+		}
+
+		let mut reader = BufReader::new(file);
+		// skip binary files, which usually have a null byte early on
+		match reader.fill_buf() {
+			Ok(head) if !head.contains(&0) => {}
+			_ => return,
+		}
+
+		let path_str = path.to_string_lossy();
+		let mut buf = Vec::new();
+		let mut line = 0;
+		loop {
+			buf.clear();
+			match reader.read_until(b'\n', &mut buf) {
+				Ok(0) | Err(_) => return,
+				Ok(_) => line += 1,
+			}
+
+			let text = String::from_utf8_lossy(&buf);
+			let text = text.trim_end_matches(['\r', '\n']);
+			for m in self.options.pattern.find_iter(text) {
+				let column = text[..m.start()].chars().count();
+				let found = FsSearchMatch {
+					path: path_str.to_string(),
+					line,
+					column: column as u64 + 1,
+					preview: preview(text, column),
+				};
+
+				if !(self.on_match)(found) {
+					self.stopped = true;
+					return;
+				}
+
+				self.result_count += 1;
+				if self.result_count >= self.options.max_results {
+					self.limit_hit = true;
+					self.stopped = true;
+					return;
+				}
+			}
+		}
+	}
+
+	fn is_included(&self, relative: &str) -> bool {
+		self.options.includes.is_empty()
+			|| self
+				.options
+				.includes
+				.iter()
+				.any(|g| glob_matches(g.as_bytes(), relative.as_bytes()))
+	}
+
+	fn is_ignored(&self, relative: &str, is_dir: bool) -> bool {
+		let mut ignored = false;
+		for file in &self.ignores {
+			let within = match file.dir.is_empty() {
+				true => relative,
+				false => match relative
+					.strip_prefix(&file.dir)
+					.and_then(|r| r.strip_prefix('/'))
+				{
+					Some(r) => r,
+					None => continue,
+				},
+			};
+
+			// later patterns take precedence, so negations can re-include paths
+			for pattern in &file.patterns {
+				if (is_dir || !pattern.dir_only)
+					&& glob_matches(pattern.glob.as_bytes(), within.as_bytes())
+				{
+					ignored = !pattern.negated;
+				}
+			}
+		}
+
+		ignored
+	}
+}
+
+/// Gets the preview of a line with a match at the given character.
+fn preview(text: &str, match_char: usize) -> String {
+	let start = match text.chars().count() > MAX_PREVIEW_CHARS {
+		true => match_char.saturating_sub(PREVIEW_CONTEXT_CHARS),
+		false => 0,
+	};
+	text.chars().skip(start).take(MAX_PREVIEW_CHARS).collect()
+}
+
+struct IgnorePattern {
+	glob: String,
+	negated: bool,
+	/// Whether the pattern, ending with a `/`, only matches directories
+	dir_only: bool,
+}
+
+/// Patterns of a `.gitignore` file in the directory at `dir`, relative to
+/// the search root.
+struct IgnoreFile {
+	dir: String,
+	patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreFile {
+	fn parse(dir: &str, contents: &str) -> Self {
+		let patterns = contents
+			.lines()
+			.filter_map(|line| {
+				let line = line.trim_end();
+				if line.is_empty() || line.starts_with('#') {
+					return None;
+				}
+
+				let (negated, line) = match line.strip_prefix('!') {
+					Some(l) => (true, l),
+					None => (false, line.strip_prefix('\\').unwrap_or(line)),
+				};
+				let (dir_only, line) = match line.strip_suffix('/') {
+					Some(l) => (true, l),
+					None => (false, line),
+				};
+
+				// patterns with a slash are relative to the .gitignore's
+				// directory, others match at any depth
+				let glob = match line.strip_prefix('/') {
+					Some(l) => l.to_string(),
+					None if line.contains('/') => line.to_string(),
+					None => format!("**/{}", line),
+				};
+
+				Some(IgnorePattern {
+					glob,
+					negated,
+					dir_only,
+				})
+			})
+			.collect();
+
+		IgnoreFile {
+			dir: dir.to_string(),
+			patterns,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn search_all(options: &SearchOptions) -> (Vec<FsSearchMatch>, SearchSummary) {
+		let mut matches = Vec::new();
+		let summary = search(options, |m| {
+			matches.push(m);
+			true
+		});
+		(matches, summary)
+	}
+
+	#[test]
+	fn test_search() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_owned();
+		fs::create_dir_all(root.join("src/gen")).unwrap();
+		fs::create_dir_all(root.join("target")).unwrap();
+		fs::write(root.join(".gitignore"), "/target/\n*.log\n").unwrap();
+		fs::write(root.join("src/.gitignore"), "gen/\n!keep.log\n").unwrap();
+		fs::write(
+			root.join("src/main.rs"),
+			"fn main() {\n\tprintln!(\"needle needle\");\n}\n",
+		)
+		.unwrap();
+		fs::write(root.join("src/keep.log"), "needle\n").unwrap();
+		fs::write(root.join("src/gen/out.rs"), "needle\n").unwrap();
+		fs::write(root.join("target/out.rs"), "needle\n").unwrap();
+		fs::write(root.join("other.log"), "needle\n").unwrap();
+		fs::write(root.join("binary.rs"), b"needle\0").unwrap();
+
+		let mut options = SearchOptions {
+			root: root.clone(),
+			pattern: Regex::new("needle").unwrap(),
+			includes: Vec::new(),
+			excludes: Excludes::default(),
+			use_ignore_files: true,
+			max_results: 100,
+			cancelled: Arc::default(),
+		};
+
+		let (matches, summary) = search_all(&options);
+		let main = root.join("src/main.rs").to_string_lossy().into_owned();
+		assert_eq!(
+			matches,
+			vec![
+				FsSearchMatch {
+					path: root.join("src/keep.log").to_string_lossy().into_owned(),
+					line: 1,
+					column: 1,
+					preview: "needle".to_string(),
+				},
+				FsSearchMatch {
+					path: main.clone(),
+					line: 2,
+					column: 12,
+					preview: "\tprintln!(\"needle needle\");".to_string(),
+				},
+				FsSearchMatch {
+					path: main.clone(),
+					line: 2,
+					column: 19,
+					preview: "\tprintln!(\"needle needle\");".to_string(),
+				},
+			]
+		);
+		assert!(!summary.limit_hit);
+
+		options.includes = SearchOptions::normalize_globs(vec!["*.rs".to_string()]);
+		options.max_results = 1;
+		let (matches, summary) = search_all(&options);
+		assert_eq!(matches.len(), 1);
+		assert_eq!(matches[0].path, main);
+		assert!(summary.limit_hit);
+
+		options.use_ignore_files = false;
+		options.max_results = 100;
+		let (matches, _) = search_all(&options);
+		assert_eq!(matches.len(), 4);
+	}
+
+	/// Gets the files under the root with a match, relative to it.
+	fn searched_files(root: &Path, cancelled: bool) -> Vec<String> {
+		let options = SearchOptions {
+			root: root.to_owned(),
+			pattern: Regex::new("needle").unwrap(),
+			includes: Vec::new(),
+			excludes: Excludes::default(),
+			use_ignore_files: true,
+			max_results: 100,
+			cancelled: Arc::new(AtomicBool::new(cancelled)),
+		};
+		let (matches, _) = search_all(&options);
+		matches
+			.into_iter()
+			.map(|m| {
+				Path::new(&m.path)
+					.strip_prefix(root)
+					.unwrap()
+					.to_string_lossy()
+					.replace('\\', "/")
+			})
+			.collect()
+	}
+
+	fn write_files(root: &Path, paths: &[&str]) {
+		for path in paths {
+			let path = root.join(path);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, "needle\n").unwrap();
+		}
+	}
+
+	#[test]
+	fn test_gitignore_negation() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		write_files(
+			root,
+			&[
+				"a.log",
+				"keep.log",
+				"sub/b.log",
+				"sub/keep.log",
+				"logs/keep.log",
+			],
+		);
+		// later patterns win, and a file in an ignored directory can't be re-included
+		fs::write(root.join(".gitignore"), "*.log\n!keep.log\nlogs/\n").unwrap();
+		fs::write(root.join("sub/.gitignore"), "keep.log\n!b.log\n").unwrap();
+
+		assert_eq!(searched_files(root, false), vec!["keep.log", "sub/b.log"]);
+	}
+
+	#[test]
+	fn test_gitignore_anchored() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		write_files(
+			root,
+			&[
+				"build/out.txt",
+				"src/build/out.txt",
+				"docs/a.md",
+				"src/docs/a.md",
+				"src/gen.rs",
+				"src/lib/gen.rs",
+			],
+		);
+		// a leading or inner slash anchors the pattern to the .gitignore's directory
+		fs::write(root.join(".gitignore"), "/build\ndocs/*.md\n").unwrap();
+		fs::write(root.join("src/.gitignore"), "/gen.rs\n").unwrap();
+
+		assert_eq!(
+			searched_files(root, false),
+			vec!["src/build/out.txt", "src/docs/a.md", "src/lib/gen.rs"]
+		);
+	}
+
+	#[test]
+	fn test_gitignore_globstar() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		write_files(
+			root,
+			&[
+				"tmp/a.txt",
+				"a/b/tmp/c.txt",
+				"a/x/y/z.txt",
+				"a/z.txt",
+				"logs/1/debug.txt",
+				"logs.txt",
+				"d/e/f.txt",
+			],
+		);
+		fs::write(root.join(".gitignore"), "**/tmp\na/**/z.txt\nlogs/**\n").unwrap();
+
+		assert_eq!(searched_files(root, false), vec!["d/e/f.txt", "logs.txt"]);
+	}
+
+	#[test]
+	fn test_search_cancelled() {
+		let dir = tempfile::tempdir().unwrap();
+		write_files(dir.path(), &["a.txt", "b/c.txt"]);
+
+		assert_eq!(searched_files(dir.path(), false).len(), 2);
+		assert!(searched_files(dir.path(), true).is_empty());
+	}
+
+	#[test]
+	fn test_preview() {
+		assert_eq!(preview("short line", 6), "short line");
+
+		let long = format!("{}needle{}", "a".repeat(300), "b".repeat(300));
+		let p = preview(&long, 300);
+		assert_eq!(p.chars().count(), MAX_PREVIEW_CHARS);
+		assert!(p.starts_with(&"a".repeat(PREVIEW_CONTEXT_CHARS)));
+		assert!(p[PREVIEW_CONTEXT_CHARS..].starts_with("needle"));
+	}
+}
@@ -0,0 +1,239 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::path::Path;
+
+use serde::Serialize;
+
+#[cfg(target_os = "linux")]
+pub type FsWatcher = super::fs_watch_linux::FsWatcher;
+
+#[cfg(not(target_os = "linux"))]
+pub type FsWatcher = super::fs_watch_poll::FsWatcher;
+
+/// Change to a watched path, written as a line of JSON to the `fs_watch` stream.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum FsWatchEvent {
+	Created {
+		path: String,
+	},
+	Modified {
+		path: String,
+	},
+	Deleted {
+		path: String,
+	},
+	/// Only reported on Linux, elsewhere renames are a deletion and a creation
+	#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+	Renamed {
+		from: String,
+		path: String,
+	},
+	/// Changes were dropped because too many happened at once, so anything
+	/// under the path may have changed and should be read again. Only
+	/// reported on Linux.
+	#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+	Overflow {
+		path: String,
+	},
+}
+
+impl FsWatchEvent {
+	pub fn created(path: &Path) -> Self {
+		Self::Created {
+			path: path.to_string_lossy().into_owned(),
+		}
+	}
+
+	pub fn modified(path: &Path) -> Self {
+		Self::Modified {
+			path: path.to_string_lossy().into_owned(),
+		}
+	}
+
+	pub fn deleted(path: &Path) -> Self {
+		Self::Deleted {
+			path: path.to_string_lossy().into_owned(),
+		}
+	}
+
+	#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+	pub fn renamed(from: &Path, path: &Path) -> Self {
+		Self::Renamed {
+			from: from.to_string_lossy().into_owned(),
+			path: path.to_string_lossy().into_owned(),
+		}
+	}
+
+	#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+	pub fn overflow(path: &Path) -> Self {
+		Self::Overflow {
+			path: path.to_string_lossy().into_owned(),
+		}
+	}
+}
+
+/// Glob patterns of paths, relative to the watched root, that are not
+/// reported. Supports `*`, `?` and `[...]` within a path segment and `**`
+/// across segments. Anything within an excluded directory is excluded as well.
+#[derive(Clone, Default)]
+pub struct Excludes(Vec<String>);
+
+impl Excludes {
+	pub fn new(patterns: Vec<String>) -> Self {
+		Self(
+			patterns
+				.into_iter()
+				.map(|p| p.trim_matches('/').to_string())
+				.filter(|p| !p.is_empty())
+				.collect(),
+		)
+	}
+
+	pub fn is_excluded(&self, root: &Path, path: &Path) -> bool {
+		if self.0.is_empty() {
+			return false;
+		}
+
+		let relative = match path.strip_prefix(root) {
+			Ok(r) => r.to_string_lossy().replace('\\', "/"),
+			Err(_) => return false,
+		};
+		let relative = relative.as_bytes();
+
+		// check the path itself and each of its parent directories
+		let prefixes = relative
+			.iter()
+			.enumerate()
+			.filter(|(_, c)| **c == b'/')
+			.map(|(i, _)| &relative[..i])
+			.chain(std::iter::once(relative));
+		prefixes
+			.filter(|p| !p.is_empty())
+			.any(|p| self.0.iter().any(|g| glob_matches(g.as_bytes(), p)))
+	}
+}
+
+pub fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+	match pattern.first() {
+		None => path.is_empty(),
+		Some(b'*') if pattern.get(1) == Some(&b'*') => {
+			// `**/` matches any number of whole segments, including none
+			let rest = &pattern[2..];
+			let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+			rest.is_empty()
+				|| glob_matches(rest, path)
+				|| (0..path.len()).any(|i| path[i] == b'/' && glob_matches(rest, &path[i + 1..]))
+		}
+		Some(b'*') => (0..=path.len())
+			.take_while(|&i| i == 0 || path[i - 1] != b'/')
+			.any(|i| glob_matches(&pattern[1..], &path[i..])),
+		Some(b'?') => {
+			!path.is_empty() && path[0] != b'/' && glob_matches(&pattern[1..], &path[1..])
+		}
+		Some(b'[') => match (class_matches(&pattern[1..], path.first()), path.is_empty()) {
+			(Some((true, len)), false) => glob_matches(&pattern[1 + len..], &path[1..]),
+			(Some(_), _) => false,
+			// no closing bracket, so it's a literal `[`
+			(None, _) => path.first() == Some(&b'[') && glob_matches(&pattern[1..], &path[1..]),
+		},
+		Some(c) => path.first() == Some(c) && glob_matches(&pattern[1..], &path[1..]),
+	}
+}
+
+/// Matches a character against a class like `[a-z_]` or `[!0-9]`, given the
+/// pattern after its `[`. Returns whether it matched and the length of the
+/// rest of the class, or None if the class isn't closed. A `]` directly after
+/// the `[` or negation is literal, and `/` never matches.
+fn class_matches(class: &[u8], c: Option<&u8>) -> Option<(bool, usize)> {
+	let negated = matches!(class.first(), Some(b'!' | b'^'));
+	let start = negated as usize;
+	let end = start + 1 + class.get(start + 1..)?.iter().position(|b| *b == b']')?;
+	let items = &class[start..end];
+
+	let c = match c {
+		Some(c) if *c != b'/' => *c,
+		_ => return Some((false, end + 1)),
+	};
+
+	let mut matched = false;
+	let mut i = 0;
+	while i < items.len() {
+		if items.get(i + 1) == Some(&b'-') && i + 2 < items.len() {
+			matched |= (items[i]..=items[i + 2]).contains(&c);
+			i += 3;
+		} else {
+			matched |= items[i] == c;
+			i += 1;
+		}
+	}
+
+	Some((matched != negated, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+
+	use super::*;
+
+	#[test]
+	fn test_glob_matches() {
+		assert!(glob_matches(b"*.log", b"server.log"));
+		assert!(!glob_matches(b"*.log", b"logs/server.log"));
+		assert!(glob_matches(b"**/*.log", b"logs/server.log"));
+		assert!(glob_matches(b"**/*.log", b"server.log"));
+		assert!(glob_matches(b"a/**", b"a/b/c"));
+		assert!(glob_matches(b"file?.txt", b"file1.txt"));
+		assert!(!glob_matches(b"file?.txt", b"file10.txt"));
+	}
+
+	#[test]
+	fn test_glob_matches_globstar() {
+		assert!(glob_matches(b"**", b"a/b/c"));
+		assert!(glob_matches(b"a/**/c", b"a/c"));
+		assert!(glob_matches(b"a/**/c", b"a/b/x/c"));
+		assert!(!glob_matches(b"a/**/c", b"a/b/cd"));
+		assert!(!glob_matches(b"a/**/c", b"b/a/c"));
+		assert!(glob_matches(b"**/b/**/*.rs", b"a/b/c/d.rs"));
+		assert!(glob_matches(b"**/b/**/*.rs", b"b/d.rs"));
+		assert!(!glob_matches(b"**/b/**/*.rs", b"a/bb/d.rs"));
+	}
+
+	#[test]
+	fn test_glob_matches_classes() {
+		assert!(glob_matches(b"file[0-9].txt", b"file7.txt"));
+		assert!(!glob_matches(b"file[0-9].txt", b"filex.txt"));
+		assert!(!glob_matches(b"file[0-9].txt", b"file.txt"));
+		assert!(glob_matches(b"[abc]*", b"build"));
+		assert!(!glob_matches(b"[abc]*", b"dist"));
+		assert!(glob_matches(b"*.[!o]", b"main.c"));
+		assert!(!glob_matches(b"*.[!o]", b"main.o"));
+		assert!(glob_matches(b"*.[^o]", b"main.h"));
+		assert!(glob_matches(b"[a-cx-z_]", b"y"));
+		assert!(glob_matches(b"[a-cx-z_]", b"_"));
+		assert!(!glob_matches(b"[a-cx-z_]", b"m"));
+		assert!(glob_matches(b"[-a]", b"-"));
+		assert!(glob_matches(b"[]]", b"]"));
+		assert!(!glob_matches(b"a[!x]b", b"a/b"));
+		// an unclosed class is a literal bracket
+		assert!(glob_matches(b"a[b", b"a[b"));
+		assert!(!glob_matches(b"a[b", b"ab"));
+	}
+
+	#[test]
+	fn test_is_excluded() {
+		let root = PathBuf::from("/workspace");
+		let excludes = Excludes::new(vec!["**/node_modules".to_string(), ".git".to_string()]);
+
+		assert!(excludes.is_excluded(&root, &root.join("node_modules")));
+		assert!(excludes.is_excluded(&root, &root.join("app/node_modules/pkg/index.js")));
+		assert!(excludes.is_excluded(&root, &root.join(".git/HEAD")));
+		assert!(!excludes.is_excluded(&root, &root.join("app/.git")));
+		assert!(!excludes.is_excluded(&root, &root.join("src/main.rs")));
+		assert!(!excludes.is_excluded(&root, &root));
+	}
+}
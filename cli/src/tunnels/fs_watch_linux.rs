@@ -0,0 +1,405 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::io::unix::AsyncFd;
+
+use super::fs_watch::{Excludes, FsWatchEvent};
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+	| libc::IN_MODIFY
+	| libc::IN_DELETE
+	| libc::IN_MOVED_FROM
+	| libc::IN_MOVED_TO
+	| libc::IN_DELETE_SELF
+	| libc::IN_MOVE_SELF;
+
+/// How long to wait for the "moved to" of a rename whose "moved from" was the
+/// last event read, before treating the path as moved out of the tree.
+const PENDING_MOVE_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Watches a file or directory using inotify. In recursive mode, a watch is
+/// added for each directory in the tree that isn't excluded.
+pub struct FsWatcher {
+	fd: AsyncFd<OwnedFd>,
+	root: PathBuf,
+	recursive: bool,
+	excludes: Excludes,
+	/// Directory being watched by each watch descriptor
+	dirs: HashMap<i32, PathBuf>,
+	/// Cookie and path of a "moved from" that's not yet been paired up
+	pending_move: Option<(u32, PathBuf)>,
+}
+
+impl FsWatcher {
+	pub fn new(root: PathBuf, recursive: bool, excludes: Excludes) -> io::Result<Self> {
+		let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+		if fd < 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		let mut watcher = FsWatcher {
+			fd: AsyncFd::new(unsafe { OwnedFd::from_raw_fd(fd) })?,
+			root: root.clone(),
+			recursive,
+			excludes,
+			dirs: HashMap::new(),
+			pending_move: None,
+		};
+
+		watcher.add_watch(&root)?;
+		if recursive && root.is_dir() {
+			watcher.add_subdirs(&root, &mut Vec::new());
+		}
+
+		Ok(watcher)
+	}
+
+	/// Waits for the next changes. Returns None once the watched path was
+	/// deleted or moved away, after which there's nothing left to watch.
+	pub async fn next(&mut self) -> io::Result<Option<Vec<FsWatchEvent>>> {
+		if self.dirs.is_empty() {
+			return Ok(None);
+		}
+
+		let mut buf = [0u8; 8192];
+		let n = match self.pending_move.is_some() {
+			// the rest of a rename may not have been read yet
+			true => match tokio::time::timeout(PENDING_MOVE_TIMEOUT, self.read(&mut buf)).await {
+				Ok(n) => n?,
+				Err(_) => {
+					let mut events = Vec::new();
+					if let Some((_, from)) = self.pending_move.take() {
+						self.moved_away(&from, &mut events);
+					}
+					return Ok(Some(events));
+				}
+			},
+			false => self.read(&mut buf).await?,
+		};
+
+		Ok(Some(self.parse_events(&buf[..n])))
+	}
+
+	async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			let mut guard = self.fd.readable().await?;
+			match guard.try_io(|fd| {
+				let n = unsafe {
+					libc::read(
+						fd.as_raw_fd(),
+						buf.as_mut_ptr() as *mut libc::c_void,
+						buf.len(),
+					)
+				};
+				if n < 0 {
+					Err(io::Error::last_os_error())
+				} else {
+					Ok(n as usize)
+				}
+			}) {
+				Ok(r) => return r,
+				Err(_would_block) => continue,
+			}
+		}
+	}
+
+	fn parse_events(&mut self, buf: &[u8]) -> Vec<FsWatchEvent> {
+		let header_len = std::mem::size_of::<libc::inotify_event>();
+		let mut events = Vec::new();
+		// a rename is reported as a "moved from" directly followed by a
+		// "moved to" with the same cookie, otherwise the path moved in or out.
+		// The pair may be split across reads, so an unpaired "moved from" at
+		// the end is kept for the next read.
+		let mut moved_from = self.pending_move.take();
+
+		let mut offset = 0;
+		while offset + header_len <= buf.len() {
+			let event = unsafe {
+				std::ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event)
+			};
+			let name_start = offset + header_len;
+			offset = name_start + event.len as usize;
+			let name = &buf[name_start..offset.min(buf.len())];
+			let name = &name[..name.iter().position(|c| *c == 0).unwrap_or(name.len())];
+
+			if event.mask & libc::IN_Q_OVERFLOW != 0 {
+				events.push(FsWatchEvent::overflow(&self.root));
+				continue;
+			}
+
+			if event.mask & libc::IN_IGNORED != 0 {
+				self.dirs.remove(&event.wd);
+				continue;
+			}
+
+			let dir = match self.dirs.get(&event.wd) {
+				Some(d) => d.clone(),
+				None => continue,
+			};
+
+			if event.mask & (libc::IN_DELETE_SELF | libc::IN_MOVE_SELF) != 0 {
+				// changes to subdirectories are reported by their parent
+				if dir == self.root {
+					events.push(FsWatchEvent::deleted(&dir));
+					self.forget_tree(&dir);
+				}
+				continue;
+			}
+
+			let path = match name.is_empty() {
+				true => dir,
+				false => dir.join(OsStr::from_bytes(name)),
+			};
+			if self.excludes.is_excluded(&self.root, &path) {
+				continue;
+			}
+
+			let is_dir = event.mask & libc::IN_ISDIR != 0;
+			if event.mask & libc::IN_MOVED_FROM != 0 {
+				if let Some((_, from)) = moved_from.replace((event.cookie, path)) {
+					self.moved_away(&from, &mut events);
+				}
+			} else if event.mask & libc::IN_MOVED_TO != 0 {
+				match moved_from.take() {
+					Some((cookie, from)) if cookie == event.cookie => {
+						self.rename_tree(&from, &path);
+						events.push(FsWatchEvent::renamed(&from, &path));
+					}
+					other => {
+						if let Some((_, from)) = other {
+							self.moved_away(&from, &mut events);
+						}
+						events.push(FsWatchEvent::created(&path));
+					}
+				}
+				if is_dir && self.recursive && !self.dirs.values().any(|d| d == &path) {
+					self.add_tree(&path, &mut events);
+				}
+			} else if event.mask & libc::IN_CREATE != 0 {
+				events.push(FsWatchEvent::created(&path));
+				if is_dir && self.recursive {
+					self.add_tree(&path, &mut events);
+				}
+			} else if event.mask & libc::IN_DELETE != 0 {
+				events.push(FsWatchEvent::deleted(&path));
+			} else if event.mask & libc::IN_MODIFY != 0 {
+				events.push(FsWatchEvent::modified(&path));
+			}
+		}
+
+		self.pending_move = moved_from;
+		events
+	}
+
+	/// Handles a path that was moved out of the watched tree.
+	fn moved_away(&mut self, path: &Path, events: &mut Vec<FsWatchEvent>) {
+		events.push(FsWatchEvent::deleted(path));
+		self.forget_tree(path);
+	}
+
+	fn add_watch(&mut self, path: &Path) -> io::Result<()> {
+		let c_path = CString::new(path.as_os_str().as_bytes())?;
+		let wd =
+			unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), WATCH_MASK) };
+		if wd < 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		self.dirs.insert(wd, path.to_owned());
+		Ok(())
+	}
+
+	/// Watches a directory that was added to the tree. Anything created in it
+	/// before it was watched is reported as created.
+	fn add_tree(&mut self, dir: &Path, events: &mut Vec<FsWatchEvent>) {
+		if self.add_watch(dir).is_ok() {
+			self.add_subdirs(dir, events);
+		}
+	}
+
+	fn add_subdirs(&mut self, dir: &Path, events: &mut Vec<FsWatchEvent>) {
+		let entries = match std::fs::read_dir(dir) {
+			Ok(e) => e,
+			Err(_) => return,
+		};
+
+		for entry in entries.flatten() {
+			let path = entry.path();
+			if self.excludes.is_excluded(&self.root, &path) {
+				continue;
+			}
+
+			events.push(FsWatchEvent::created(&path));
+			// file_type() doesn't follow symlinks, so links to directories aren't watched
+			if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+				self.add_tree(&path, events);
+			}
+		}
+	}
+
+	/// Stops watching a directory and everything within it.
+	fn forget_tree(&mut self, dir: &Path) {
+		let fd = self.fd.as_raw_fd();
+		self.dirs.retain(|wd, path| {
+			if path.starts_with(dir) {
+				unsafe { libc::inotify_rm_watch(fd, *wd) };
+				false
+			} else {
+				true
+			}
+		});
+	}
+
+	/// Updates the paths of watched directories within a renamed directory.
+	fn rename_tree(&mut self, from: &Path, to: &Path) {
+		for path in self.dirs.values_mut() {
+			if let Ok(rest) = path.strip_prefix(from) {
+				*path = to.join(rest);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+
+	/// Reads events until the expected one, returning those seen before it.
+	async fn wait_for(watcher: &mut FsWatcher, expected: FsWatchEvent) -> Vec<FsWatchEvent> {
+		let mut seen = Vec::new();
+		loop {
+			let events = tokio::time::timeout(Duration::from_secs(5), watcher.next())
+				.await
+				.expect("timed out waiting for event")
+				.unwrap()
+				.unwrap();
+			for event in events {
+				if event == expected {
+					return seen;
+				}
+				seen.push(event);
+			}
+		}
+	}
+
+	/// Encodes an event the way it's read from inotify.
+	fn raw_event(wd: i32, mask: u32, cookie: u32, name: &str) -> Vec<u8> {
+		let len = match name.is_empty() {
+			true => 0,
+			false => (name.len() + 1).next_multiple_of(4),
+		};
+		let header = libc::inotify_event {
+			wd,
+			mask,
+			cookie,
+			len: len as u32,
+		};
+		let mut buf = unsafe {
+			std::slice::from_raw_parts(
+				&header as *const libc::inotify_event as *const u8,
+				std::mem::size_of::<libc::inotify_event>(),
+			)
+		}
+		.to_vec();
+		buf.extend_from_slice(name.as_bytes());
+		buf.resize(buf.len() + len - name.len(), 0);
+		buf
+	}
+
+	fn root_wd(watcher: &FsWatcher) -> i32 {
+		*watcher
+			.dirs
+			.iter()
+			.find(|(_, d)| **d == watcher.root)
+			.unwrap()
+			.0
+	}
+
+	#[tokio::test]
+	async fn test_reports_overflow() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_owned();
+		let mut watcher = FsWatcher::new(root.clone(), true, Excludes::default()).unwrap();
+		let wd = root_wd(&watcher);
+
+		let mut buf = raw_event(-1, libc::IN_Q_OVERFLOW, 0, "");
+		buf.extend(raw_event(wd, libc::IN_CREATE, 0, "file"));
+		assert_eq!(
+			watcher.parse_events(&buf),
+			vec![
+				FsWatchEvent::overflow(&root),
+				FsWatchEvent::created(&root.join("file"))
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn test_rename_split_across_reads() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_owned();
+		let mut watcher = FsWatcher::new(root.clone(), true, Excludes::default()).unwrap();
+		let wd = root_wd(&watcher);
+
+		let from = raw_event(wd, libc::IN_MOVED_FROM, 7, "a");
+		assert!(watcher.parse_events(&from).is_empty());
+		assert_eq!(
+			watcher.parse_events(&raw_event(wd, libc::IN_MOVED_TO, 7, "b")),
+			vec![FsWatchEvent::renamed(&root.join("a"), &root.join("b"))]
+		);
+
+		// without a "moved to", the path was moved out of the tree
+		assert!(watcher.parse_events(&from).is_empty());
+		assert_eq!(
+			watcher.next().await.unwrap(),
+			Some(vec![FsWatchEvent::deleted(&root.join("a"))])
+		);
+	}
+
+	#[tokio::test]
+	async fn test_watches_recursively() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_owned();
+		std::fs::create_dir_all(root.join("a/b")).unwrap();
+		std::fs::create_dir_all(root.join("ignored")).unwrap();
+
+		let mut watcher = FsWatcher::new(
+			root.clone(),
+			true,
+			Excludes::new(vec!["ignored".to_string()]),
+		)
+		.unwrap();
+
+		std::fs::write(root.join("ignored/x"), "x").unwrap();
+		std::fs::write(root.join("a/b/file"), "x").unwrap();
+		let seen = wait_for(&mut watcher, FsWatchEvent::created(&root.join("a/b/file"))).await;
+		assert!(seen.is_empty());
+
+		std::fs::rename(root.join("a/b/file"), root.join("a/file")).unwrap();
+		let seen = wait_for(
+			&mut watcher,
+			FsWatchEvent::renamed(&root.join("a/b/file"), &root.join("a/file")),
+		)
+		.await;
+		assert!(!seen.contains(&FsWatchEvent::deleted(&root.join("a/b/file"))));
+
+		// may be written before the new directory is watched
+		std::fs::create_dir(root.join("c")).unwrap();
+		std::fs::rename(root.join("a/file"), root.join("c/file")).unwrap();
+		wait_for(&mut watcher, FsWatchEvent::created(&root.join("c/file"))).await;
+
+		std::fs::remove_file(root.join("c/file")).unwrap();
+		wait_for(&mut watcher, FsWatchEvent::deleted(&root.join("c/file"))).await;
+	}
+}
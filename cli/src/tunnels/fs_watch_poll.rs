@@ -0,0 +1,120 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::fs_watch::{Excludes, FsWatchEvent};
+
+/// How often the watched tree is scanned for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(PartialEq, Eq)]
+struct Entry {
+	is_dir: bool,
+	len: u64,
+	modified: Option<SystemTime>,
+}
+
+type Snapshot = HashMap<PathBuf, Entry>;
+
+/// Watches a file or directory by periodically scanning it. Renames are
+/// reported as a deletion and a creation.
+pub struct FsWatcher {
+	root: PathBuf,
+	recursive: bool,
+	excludes: Excludes,
+	snapshot: Snapshot,
+}
+
+impl FsWatcher {
+	pub fn new(root: PathBuf, recursive: bool, excludes: Excludes) -> io::Result<Self> {
+		std::fs::metadata(&root)?;
+		let snapshot = scan(&root, recursive, &excludes);
+		Ok(FsWatcher {
+			root,
+			recursive,
+			excludes,
+			snapshot,
+		})
+	}
+
+	/// Waits for the next changes. Returns None once the watched path was
+	/// deleted, after which there's nothing left to watch.
+	pub async fn next(&mut self) -> io::Result<Option<Vec<FsWatchEvent>>> {
+		if self.snapshot.is_empty() {
+			return Ok(None);
+		}
+
+		tokio::time::sleep(POLL_INTERVAL).await;
+
+		let (root, recursive, excludes) =
+			(self.root.clone(), self.recursive, self.excludes.clone());
+		let snapshot = tokio::task::spawn_blocking(move || scan(&root, recursive, &excludes))
+			.await
+			.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+		let mut events = Vec::new();
+		for (path, entry) in &snapshot {
+			match self.snapshot.get(path) {
+				None => events.push(FsWatchEvent::created(path)),
+				Some(e) if !entry.is_dir && e != entry => events.push(FsWatchEvent::modified(path)),
+				_ => {}
+			}
+		}
+		for path in self.snapshot.keys() {
+			if !snapshot.contains_key(path) {
+				events.push(FsWatchEvent::deleted(path));
+			}
+		}
+
+		self.snapshot = snapshot;
+		Ok(Some(events))
+	}
+}
+
+fn scan(root: &Path, recursive: bool, excludes: &Excludes) -> Snapshot {
+	let mut snapshot = Snapshot::new();
+	if let Ok(m) = std::fs::symlink_metadata(root) {
+		snapshot.insert(root.to_owned(), to_entry(&m));
+		if m.is_dir() {
+			scan_dir(root, root, recursive, excludes, &mut snapshot);
+		}
+	}
+
+	snapshot
+}
+
+fn scan_dir(root: &Path, dir: &Path, recursive: bool, excludes: &Excludes, into: &mut Snapshot) {
+	let entries = match std::fs::read_dir(dir) {
+		Ok(e) => e,
+		Err(_) => return,
+	};
+
+	for entry in entries.flatten() {
+		let path = entry.path();
+		if excludes.is_excluded(root, &path) {
+			continue;
+		}
+
+		if let Ok(m) = entry.metadata() {
+			let is_dir = m.is_dir();
+			into.insert(path.clone(), to_entry(&m));
+			if is_dir && recursive {
+				scan_dir(root, &path, recursive, excludes, into);
+			}
+		}
+	}
+}
+
+fn to_entry(m: &std::fs::Metadata) -> Entry {
+	Entry {
+		is_dir: m.is_dir(),
+		len: m.len(),
+		modified: m.modified().ok(),
+	}
+}
@@ -139,6 +139,9 @@ pub struct GetEnvResponse {
 #[derive(Deserialize)]
 pub struct SysKillRequest {
 	pub pid: u32,
+	/// Signal number to send instead, such as 15 for SIGTERM. Ignored on Windows.
+	#[serde(default)]
+	pub signal: Option<i32>,
 }
 
 #[derive(Serialize)]
@@ -146,12 +149,55 @@ pub struct SysKillResponse {
 	pub success: bool,
 }
 
+/// Method: `sys_info`. Gets information about the machine. Memory and disk
+/// sizes are in bytes, and the uptime is in seconds. The load average is
+/// always zero on Windows.
+#[derive(Serialize)]
+pub struct SysInfoResponse {
+	pub cpu_count: usize,
+	pub load_average: [f64; 3],
+	pub total_memory: u64,
+	pub free_memory: u64,
+	pub available_memory: u64,
+	pub os_release: Option<String>,
+	pub uptime: u64,
+	/// Disk that the CLI's data directory is on
+	pub data_dir_disk: Option<SysDiskUsage>,
+}
+
+#[derive(Serialize)]
+pub struct SysDiskUsage {
+	pub mount_point: String,
+	pub total_space: u64,
+	pub available_space: u64,
+}
+
+/// Method: `sys_processes`. Lists the running processes.
+#[derive(Serialize)]
+pub struct SysProcessesResponse {
+	pub processes: Vec<SysProcess>,
+}
+
+#[derive(Serialize)]
+pub struct SysProcess {
+	pub pid: u32,
+	pub ppid: Option<u32>,
+	pub name: String,
+	pub command: Vec<String>,
+	/// CPU usage, as a percentage of a single core
+	pub cpu: f32,
+	/// Resident set size in bytes
+	pub rss: u64,
+}
+
 /// Methods: `fs_read`/`fs_write`/`fs_rm`/`fs_mkdirp`/`fs_stat`
-///  - fs_read: reads into a stream returned from the method,
-///  - fs_write: writes from a stream passed to the method.
+///  - fs_read: reads into a stream returned from the method, see `FsReadRequest`
+///  - fs_write: writes from a stream passed to the method, see `FsWriteRequest`
 ///  - fs_rm: recursively removes the file
 ///  - fs_mkdirp: recursively creates the directory
-///  - fs_readdir: reads directory contents
+///  - fs_readdir: reads directory contents, see `FsReadDirRequest`
+///  - fs_watch: streams changes to the path, see `FsWatchRequest`
+///  - fs_search: streams matches of text in files, see `FsSearchRequest`
 ///  - fs_stat: stats the given path
 ///  - fs_connect: connect to the given unix or named pipe socket, streaming
 ///    data in and out from the method's stream.
@@ -160,6 +206,46 @@ pub struct FsSinglePathRequest {
 	pub path: String,
 }
 
+/// Method: `fs_read`. Reads `length` bytes of the file starting at `offset`,
+/// or all of it by default. A negative offset counts from the end of the
+/// file, so `-4096` reads its last 4KB.
+#[derive(Deserialize)]
+pub struct FsReadRequest {
+	pub path: String,
+	#[serde(default)]
+	pub offset: i64,
+	pub length: Option<u64>,
+}
+
+#[derive(Deserialize, Default, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum FsWriteMode {
+	/// Replace the contents of the file
+	#[default]
+	Truncate,
+	/// Write to the end of the file
+	Append,
+	/// Write over the contents of the file at `offset`, without truncating it
+	Overwrite,
+}
+
+/// Method: `fs_write`. Writes to the file, creating it if it doesn't exist.
+/// With `create_exclusive`, it fails if the file already exists instead.
+/// With `fsync`, the method returns once the data is flushed to disk.
+#[derive(Deserialize)]
+pub struct FsWriteRequest {
+	pub path: String,
+	#[serde(default)]
+	pub mode: FsWriteMode,
+	/// Position to write at in `overwrite` mode
+	#[serde(default)]
+	pub offset: u64,
+	#[serde(default)]
+	pub create_exclusive: bool,
+	#[serde(default)]
+	pub fsync: bool,
+}
+
 #[derive(Serialize)]
 pub enum FsFileKind {
 	#[serde(rename = "dir")]
@@ -184,12 +270,32 @@ impl From<std::fs::FileType> for FsFileKind {
 	}
 }
 
+/// Stat of a path, following symlinks. Times are in milliseconds since the
+/// Unix epoch. `ctime` is the inode change time on Unix, and the creation
+/// time on Windows. `mode`, `uid`, and `gid` are only set on Unix.
 #[derive(Serialize, Default)]
 pub struct FsStatResponse {
 	pub exists: bool,
 	pub size: Option<u64>,
 	#[serde(rename = "type")]
 	pub kind: Option<FsFileKind>,
+	pub mtime: Option<u64>,
+	pub ctime: Option<u64>,
+	/// Permission bits of the mode
+	pub mode: Option<u32>,
+	pub uid: Option<u32>,
+	pub gid: Option<u32>,
+	/// Target of the path, if it's a symlink
+	pub link_target: Option<String>,
+}
+
+/// Method: `fs_readdir`. Reads directory contents, with the stat of each
+/// entry if `stat` is set.
+#[derive(Deserialize)]
+pub struct FsReadDirRequest {
+	pub path: String,
+	#[serde(default)]
+	pub stat: bool,
 }
 
 #[derive(Serialize)]
@@ -202,6 +308,8 @@ pub struct FsReadDirEntry {
 	pub name: String,
 	#[serde(rename = "type")]
 	pub kind: Option<FsFileKind>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub stat: Option<FsStatResponse>,
 }
 
 /// Method: `fs_reaname`. Renames a file.
@@ -211,6 +319,80 @@ pub struct FsRenameRequest {
 	pub to_path: String,
 }
 
+/// Method: `fs_watch`. Streams changes to the given path, as a line of JSON
+/// per change, until the stream is closed. `excludes` are glob patterns
+/// relative to the path.
+#[derive(Deserialize)]
+pub struct FsWatchRequest {
+	pub path: String,
+	#[serde(default)]
+	pub recursive: bool,
+	#[serde(default)]
+	pub excludes: Vec<String>,
+}
+
+/// Method: `fs_search`. Searches files under the path for the query, which
+/// is literal unless `is_regex` is set, and streams each match as a line of
+/// JSON until `max_results` are found or the stream is closed. `includes` and
+/// `excludes` are glob patterns relative to the path, which match at any depth
+/// if they have no `/`. Files ignored by `.gitignore` are skipped unless
+/// `include_ignored` is set.
+#[derive(Deserialize)]
+pub struct FsSearchRequest {
+	pub path: String,
+	pub query: String,
+	#[serde(default)]
+	pub is_regex: bool,
+	#[serde(default)]
+	pub case_sensitive: bool,
+	#[serde(default)]
+	pub includes: Vec<String>,
+	#[serde(default)]
+	pub excludes: Vec<String>,
+	#[serde(default)]
+	pub include_ignored: bool,
+	pub max_results: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct FsSearchResponse {
+	pub result_count: usize,
+	/// Whether the search stopped early after reaching `max_results`
+	pub limit_hit: bool,
+}
+
+/// Method: `fs_copy`. Copies a file or, recursively, a directory. Symlinks
+/// are copied as links. Fails if `to_path` exists, unless `overwrite` is set.
+#[derive(Deserialize)]
+pub struct FsCopyRequest {
+	pub from_path: String,
+	pub to_path: String,
+	#[serde(default)]
+	pub overwrite: bool,
+}
+
+/// Method: `fs_chmod`. Sets the permission bits of a path. On Windows, only
+/// whether the path is read-only is changed, from the write bits.
+#[derive(Deserialize)]
+pub struct FsChmodRequest {
+	pub path: String,
+	pub mode: u32,
+}
+
+/// Method: `fs_symlink`. Creates a symlink at `path` pointing to `target`.
+#[derive(Deserialize)]
+pub struct FsSymlinkRequest {
+	pub target: String,
+	pub path: String,
+}
+
+/// Method: `fs_realpath`. Gets the canonical, absolute form of a path, with
+/// symlinks resolved.
+#[derive(Serialize)]
+pub struct FsRealpathResponse {
+	pub path: String,
+}
+
 /// Method: `net_connect`. Connects to a port.
 #[derive(Deserialize)]
 pub struct NetConnectRequest {
@@ -257,6 +439,57 @@ pub struct SpawnParams {
 	pub cwd: Option<String>,
 	#[serde(default)]
 	pub env: HashMap<String, String>,
+	/// Runs the process in a pseudo-terminal of the given size. Its output is
+	/// written to the stdout stream.
+	#[serde(default)]
+	pub pty: Option<SpawnPtySize>,
+	/// ID chosen by the client, used to resize or signal the process while it runs.
+	#[serde(default)]
+	pub id: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct SpawnPtySize {
+	pub cols: u16,
+	pub rows: u16,
+}
+
+/// Method: `spawn_resize`. Resizes the pseudo-terminal of a process started
+/// by `spawn` with an `id` and a `pty`.
+#[derive(Deserialize)]
+pub struct SpawnResizeParams {
+	pub id: String,
+	pub cols: u16,
+	pub rows: u16,
+}
+
+/// Method: `spawn_signal`. Sends a signal to a process started by `spawn`
+/// with an `id`. Processes in a pseudo-terminal lead their own process
+/// group, and the signal is sent to the whole group.
+#[derive(Deserialize)]
+pub struct SpawnSignalParams {
+	pub id: String,
+	pub signal: ProcessSignal,
+}
+
+/// Signal sent to a process. On Windows, only `SIGKILL` and `SIGTERM` are
+/// supported, both of which terminate the process.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum ProcessSignal {
+	#[serde(rename = "SIGHUP")]
+	Hangup,
+	#[serde(rename = "SIGINT")]
+	Interrupt,
+	#[serde(rename = "SIGQUIT")]
+	Quit,
+	#[serde(rename = "SIGKILL")]
+	Kill,
+	#[serde(rename = "SIGTERM")]
+	Terminate,
+	#[serde(rename = "SIGUSR1")]
+	User1,
+	#[serde(rename = "SIGUSR2")]
+	User2,
 }
 
 #[derive(Deserialize)]
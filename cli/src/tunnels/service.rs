@@ -14,6 +14,48 @@ use crate::util::io::{tailf, TailEvent};
 
 pub const SERVICE_LOG_FILE_NAME: &str = "tunnel-service.log";
 
+/// Kind of command that's installed as a service. Each kind is registered
+/// under its own name, so they can be installed side by side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServiceKind {
+	/// `tunnel service internal-run`
+	Tunnel,
+	/// `serve-web service internal-run`
+	ServeWeb,
+}
+
+impl ServiceKind {
+	/// Identifier used in service and file names.
+	pub fn id(&self) -> &'static str {
+		match self {
+			ServiceKind::Tunnel => "tunnel",
+			ServiceKind::ServeWeb => "serve-web",
+		}
+	}
+
+	/// Name shown to users in logs and service descriptions.
+	pub fn display_name(&self) -> &'static str {
+		match self {
+			ServiceKind::Tunnel => "Tunnel",
+			ServiceKind::ServeWeb => "Web Server",
+		}
+	}
+
+	/// Path for the service logs, when using file logs.
+	pub fn log_file(&self, paths: &LauncherPaths) -> PathBuf {
+		match self {
+			ServiceKind::Tunnel => paths.service_log_file(),
+			ServiceKind::ServeWeb => paths.root().join("serve-web-service.log"),
+		}
+	}
+
+	/// Path the running service writes its process ID to, where the platform
+	/// has no other way to find the service process.
+	pub fn pid_file(&self, paths: &LauncherPaths) -> PathBuf {
+		paths.root().join(format!("{}-service.pid", self.id()))
+	}
+}
+
 #[async_trait]
 pub trait ServiceContainer: Send {
 	async fn run_service(
@@ -57,27 +99,35 @@ pub type ServiceManagerImpl = super::service_linux::SystemdService;
 #[cfg(target_os = "macos")]
 pub type ServiceManagerImpl = super::service_macos::LaunchdService;
 
+pub fn create_service_manager(log: log::Logger, paths: &LauncherPaths) -> ServiceManagerImpl {
+	create_service_manager_for(log, paths, ServiceKind::Tunnel)
+}
+
 #[allow(unreachable_code)]
 #[allow(unused_variables)]
-pub fn create_service_manager(log: log::Logger, paths: &LauncherPaths) -> ServiceManagerImpl {
+pub fn create_service_manager_for(
+	log: log::Logger,
+	paths: &LauncherPaths,
+	kind: ServiceKind,
+) -> ServiceManagerImpl {
 	#[cfg(target_os = "macos")]
 	{
-		super::service_macos::LaunchdService::new(log, paths)
+		super::service_macos::LaunchdService::new(log, paths, kind)
 	}
 	#[cfg(target_os = "windows")]
 	{
-		super::service_windows::WindowsService::new(log, paths)
+		super::service_windows::WindowsService::new(log, paths, kind)
 	}
 	#[cfg(target_os = "linux")]
 	{
-		super::service_linux::SystemdService::new(log, paths.clone())
+		super::service_linux::SystemdService::new(log, paths.clone(), kind)
 	}
 }
 
 #[allow(dead_code)] // unused on Linux
 pub(crate) async fn tail_log_file(log_file: &Path) -> Result<(), AnyError> {
 	if !log_file.exists() {
-		println!("The tunnel service has not started yet.");
+		println!("The service has not started yet.");
 		return Ok(());
 	}
 
@@ -86,7 +136,7 @@ pub(crate) async fn tail_log_file(log_file: &Path) -> Result<(), AnyError> {
 	while let Some(line) = rx.recv().await {
 		match line {
 			TailEvent::Line(l) => print!("{}", l),
-			TailEvent::Reset => println!("== Tunnel service restarted =="),
+			TailEvent::Reset => println!("== Service restarted =="),
 			TailEvent::Err(e) => return Err(wrap(e, "error reading log file").into()),
 		}
 	}
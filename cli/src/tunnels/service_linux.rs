@@ -20,18 +20,20 @@ use crate::{
 	util::errors::{wrap, AnyError, DbusConnectFailedError},
 };
 
-use super::ServiceManager;
+use super::{service::ServiceKind, ServiceManager};
 
 pub struct SystemdService {
 	log: log::Logger,
+	kind: ServiceKind,
 	service_file: PathBuf,
 }
 
 impl SystemdService {
-	pub fn new(log: log::Logger, paths: LauncherPaths) -> Self {
+	pub fn new(log: log::Logger, paths: LauncherPaths, kind: ServiceKind) -> Self {
 		Self {
 			log,
-			service_file: paths.root().join(SystemdService::service_name_string()),
+			kind,
+			service_file: paths.root().join(service_name_string(kind)),
 		}
 	}
 }
@@ -61,11 +63,15 @@ impl SystemdService {
 		self.service_file.as_os_str().to_string_lossy().to_string()
 	}
 
-	fn service_name_string() -> String {
-		format!("{}-tunnel.service", APPLICATION_NAME)
+	fn service_name_string(&self) -> String {
+		service_name_string(self.kind)
 	}
 }
 
+fn service_name_string(kind: ServiceKind) -> String {
+	format!("{}-{}.service", APPLICATION_NAME, kind.id())
+}
+
 #[async_trait]
 impl ServiceManager for SystemdService {
 	async fn register(
@@ -76,7 +82,7 @@ impl ServiceManager for SystemdService {
 		let connection = SystemdService::connect().await?;
 		let proxy = SystemdService::proxy(&connection).await?;
 
-		write_systemd_service_file(&self.service_file, exe, args)
+		write_systemd_service_file(&self.service_file, self.kind, exe, args)
 			.map_err(|e| wrap(e, "error creating service file"))?;
 
 		proxy
@@ -98,7 +104,7 @@ impl ServiceManager for SystemdService {
 		// https://github.com/microsoft/vscode/issues/167489#issuecomment-1331222826
 		proxy
 			.enable_unit_files(
-				vec![SystemdService::service_name_string()],
+				vec![self.service_name_string()],
 				/* 'runtime only'= */ false,
 				/* replace existing = */ true,
 			)
@@ -108,11 +114,15 @@ impl ServiceManager for SystemdService {
 		info!(self.log, "Successfully enabled unit files...");
 
 		proxy
-			.start_unit(SystemdService::service_name_string(), "replace".to_string())
+			.start_unit(self.service_name_string(), "replace".to_string())
 			.await
 			.map_err(|e| wrap(e, "error starting service"))?;
 
-		info!(self.log, "Tunnel service successfully started");
+		info!(
+			self.log,
+			"{} service successfully started",
+			self.kind.display_name()
+		);
 
 		if std::env::var("SSH_CLIENT").is_ok() || std::env::var("SSH_TTY").is_ok() {
 			info!(self.log, "Tip: run `sudo loginctl enable-linger $USER` to ensure the service stays running after you disconnect.");
@@ -124,9 +134,7 @@ impl ServiceManager for SystemdService {
 	async fn is_installed(&self) -> Result<bool, AnyError> {
 		let connection = SystemdService::connect().await?;
 		let proxy = SystemdService::proxy(&connection).await?;
-		let state = proxy
-			.get_unit_file_state(SystemdService::service_name_string())
-			.await;
+		let state = proxy.get_unit_file_state(self.service_name_string()).await;
 
 		if let Ok(s) = state {
 			Ok(s == "enabled")
@@ -146,20 +154,14 @@ impl ServiceManager for SystemdService {
 	async fn show_logs(&self) -> Result<(), AnyError> {
 		// show the systemctl status header...
 		Command::new("systemctl")
-			.args([
-				"--user",
-				"status",
-				"-n",
-				"0",
-				&SystemdService::service_name_string(),
-			])
+			.args(["--user", "status", "-n", "0", &self.service_name_string()])
 			.status()
 			.map(|s| s.code().unwrap_or(1))
 			.map_err(|e| wrap(e, "error running systemctl"))?;
 
 		// then follow log files
 		Command::new("journalctl")
-			.args(["--user", "-f", "-u", &SystemdService::service_name_string()])
+			.args(["--user", "-f", "-u", &self.service_name_string()])
 			.status()
 			.map(|s| s.code().unwrap_or(1))
 			.map_err(|e| wrap(e, "error running journalctl"))?;
@@ -171,7 +173,7 @@ impl ServiceManager for SystemdService {
 		let proxy = SystemdService::proxy(&connection).await?;
 
 		proxy
-			.stop_unit(SystemdService::service_name_string(), "replace".to_string())
+			.stop_unit(self.service_name_string(), "replace".to_string())
 			.await
 			.map_err(|e| wrap(e, "error unregistering service"))?;
 
@@ -179,13 +181,13 @@ impl ServiceManager for SystemdService {
 
 		proxy
 			.disable_unit_files(
-				vec![SystemdService::service_name_string()],
+				vec![self.service_name_string()],
 				/* 'runtime only'= */ false,
 			)
 			.await
 			.map_err(|e| wrap(e, "error unregistering service"))?;
 
-		info!(self.log, "Tunnel service uninstalled");
+		info!(self.log, "{} service uninstalled", self.kind.display_name());
 
 		Ok(())
 	}
@@ -193,6 +195,7 @@ impl ServiceManager for SystemdService {
 
 fn write_systemd_service_file(
 	path: &PathBuf,
+	kind: ServiceKind,
 	exe: std::path::PathBuf,
 	args: &[&str],
 ) -> io::Result<()> {
@@ -200,7 +203,7 @@ fn write_systemd_service_file(
 	write!(
 		&mut f,
 		"[Unit]\n\
-      Description={} Tunnel\n\
+      Description={} {}\n\
       After=network.target\n\
       StartLimitIntervalSec=0\n\
       \n\
@@ -214,6 +217,7 @@ fn write_systemd_service_file(
       WantedBy=default.target\n\
     ",
 		PRODUCT_NAME_LONG,
+		kind.display_name(),
 		exe.into_os_string().to_string_lossy(),
 		args.join("\" \"")
 	)?;
@@ -21,18 +21,23 @@ use crate::{
 	},
 };
 
-use super::{service::tail_log_file, ServiceManager};
+use super::{
+	service::{tail_log_file, ServiceKind},
+	ServiceManager,
+};
 
 pub struct LaunchdService {
 	log: log::Logger,
+	kind: ServiceKind,
 	log_file: PathBuf,
 }
 
 impl LaunchdService {
-	pub fn new(log: log::Logger, paths: &LauncherPaths) -> Self {
+	pub fn new(log: log::Logger, paths: &LauncherPaths, kind: ServiceKind) -> Self {
 		Self {
 			log,
-			log_file: paths.service_log_file(),
+			kind,
+			log_file: kind.log_file(paths),
 		}
 	}
 }
@@ -44,8 +49,8 @@ impl ServiceManager for LaunchdService {
 		exe: std::path::PathBuf,
 		args: &[&str],
 	) -> Result<(), crate::util::errors::AnyError> {
-		let service_file = get_service_file_path()?;
-		write_service_file(&service_file, &self.log_file, exe, args)
+		let service_file = get_service_file_path(self.kind)?;
+		write_service_file(&service_file, self.kind, &self.log_file, exe, args)
 			.map_err(|e| wrap(e, "error creating service file"))?;
 
 		info!(self.log, "Successfully registered service...");
@@ -56,9 +61,14 @@ impl ServiceManager for LaunchdService {
 		)
 		.await?;
 
-		capture_command_and_check_status("launchctl", &["start", &get_service_label()]).await?;
+		capture_command_and_check_status("launchctl", &["start", &get_service_label(self.kind)])
+			.await?;
 
-		info!(self.log, "Tunnel service successfully started");
+		info!(
+			self.log,
+			"{} service successfully started",
+			self.kind.display_name()
+		);
 
 		Ok(())
 	}
@@ -77,13 +87,18 @@ impl ServiceManager for LaunchdService {
 
 	async fn is_installed(&self) -> Result<bool, AnyError> {
 		let cmd = capture_command_and_check_status("launchctl", &["list"]).await?;
-		Ok(String::from_utf8_lossy(&cmd.stdout).contains(&get_service_label()))
+		Ok(String::from_utf8_lossy(&cmd.stdout).contains(&get_service_label(self.kind)))
 	}
 
 	async fn unregister(&self) -> Result<(), crate::util::errors::AnyError> {
-		let service_file = get_service_file_path()?;
+		let service_file = get_service_file_path(self.kind)?;
 
-		match capture_command_and_check_status("launchctl", &["stop", &get_service_label()]).await {
+		match capture_command_and_check_status(
+			"launchctl",
+			&["stop", &get_service_label(self.kind)],
+		)
+		.await
+		{
 			Ok(_) => {}
 			// status 3 == "no such process"
 			Err(CodeError::CommandFailed { code: 3, .. }) => {}
@@ -101,9 +116,9 @@ impl ServiceManager for LaunchdService {
 		)
 		.await?;
 
-		info!(self.log, "Tunnel service uninstalled");
+		info!(self.log, "{} service uninstalled", self.kind.display_name());
 
-		if let Ok(f) = get_service_file_path() {
+		if let Ok(f) = get_service_file_path(self.kind) {
 			remove_file(f).ok();
 		}
 
@@ -111,14 +126,14 @@ impl ServiceManager for LaunchdService {
 	}
 }
 
-fn get_service_label() -> String {
-	format!("com.visualstudio.{}.tunnel", APPLICATION_NAME)
+fn get_service_label(kind: ServiceKind) -> String {
+	format!("com.visualstudio.{}.{}", APPLICATION_NAME, kind.id())
 }
 
-fn get_service_file_path() -> Result<PathBuf, MissingHomeDirectory> {
+fn get_service_file_path(kind: ServiceKind) -> Result<PathBuf, MissingHomeDirectory> {
 	match dirs::home_dir() {
 		Some(mut d) => {
-			d.push(format!("{}.plist", get_service_label()));
+			d.push(format!("{}.plist", get_service_label(kind)));
 			Ok(d)
 		}
 		None => Err(MissingHomeDirectory()),
@@ -127,6 +142,7 @@ fn get_service_file_path() -> Result<PathBuf, MissingHomeDirectory> {
 
 fn write_service_file(
 	path: &PathBuf,
+	kind: ServiceKind,
 	log_file: &Path,
 	exe: std::path::PathBuf,
 	args: &[&str],
@@ -158,7 +174,7 @@ fn write_service_file(
 			<string>{}</string>\n\
 		</dict>\n\
 		</plist>",
-		get_service_label(),
+		get_service_label(kind),
 		exe.into_os_string().to_string_lossy(),
 		args.join("</string><string>"),
 		log_file,
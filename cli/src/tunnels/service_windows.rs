@@ -12,29 +12,45 @@ use winreg::{enums::HKEY_CURRENT_USER, RegKey};
 
 use crate::util::command::new_std_command;
 use crate::{
-	constants::TUNNEL_ACTIVITY_NAME,
+	constants::{SERVE_WEB_ACTIVITY_NAME, TUNNEL_ACTIVITY_NAME},
 	log,
 	state::LauncherPaths,
 	tunnels::{protocol, singleton_client::do_single_rpc_call},
-	util::errors::{wrap, wrapdbg, AnyError},
+	util::{
+		errors::{wrap, wrapdbg, AnyError},
+		machine::kill_pid,
+	},
 };
 
-use super::service::{tail_log_file, ServiceContainer, ServiceManager as CliServiceManager};
+use super::service::{
+	tail_log_file, ServiceContainer, ServiceKind, ServiceManager as CliServiceManager,
+};
 
 const DID_LAUNCH_AS_HIDDEN_PROCESS: &str = "VSCODE_CLI_DID_LAUNCH_AS_HIDDEN_PROCESS";
 
 pub struct WindowsService {
 	log: log::Logger,
+	kind: ServiceKind,
 	tunnel_lock: PathBuf,
 	log_file: PathBuf,
+	pid_file: PathBuf,
 }
 
 impl WindowsService {
-	pub fn new(log: log::Logger, paths: &LauncherPaths) -> Self {
+	pub fn new(log: log::Logger, paths: &LauncherPaths, kind: ServiceKind) -> Self {
 		Self {
 			log,
+			kind,
 			tunnel_lock: paths.tunnel_lockfile(),
-			log_file: paths.service_log_file(),
+			log_file: kind.log_file(paths),
+			pid_file: kind.pid_file(paths),
+		}
+	}
+
+	fn value_name(&self) -> &'static str {
+		match self.kind {
+			ServiceKind::Tunnel => TUNNEL_ACTIVITY_NAME,
+			ServiceKind::ServeWeb => SERVE_WEB_ACTIVITY_NAME,
 		}
 	}
 
@@ -68,7 +84,7 @@ impl CliServiceManager for WindowsService {
 		add_arg("--log-to-file");
 		add_arg(self.log_file.to_string_lossy().as_ref());
 
-		key.set_value(TUNNEL_ACTIVITY_NAME, &reg_str)
+		key.set_value(self.value_name(), &reg_str)
 			.map_err(|e| AnyError::from(wrapdbg(e, "error setting registry key")))?;
 
 		info!(self.log, "Successfully registered service...");
@@ -80,7 +96,11 @@ impl CliServiceManager for WindowsService {
 		cmd.spawn()
 			.map_err(|e| wrapdbg(e, "error starting service"))?;
 
-		info!(self.log, "Tunnel service successfully started");
+		info!(
+			self.log,
+			"{} service successfully started",
+			self.kind.display_name()
+		);
 		Ok(())
 	}
 
@@ -115,18 +135,30 @@ impl CliServiceManager for WindowsService {
 
 	async fn is_installed(&self) -> Result<bool, AnyError> {
 		let key = WindowsService::open_key()?;
-		Ok(key.get_raw_value(TUNNEL_ACTIVITY_NAME).is_ok())
+		Ok(key.get_raw_value(self.value_name()).is_ok())
 	}
 
 	async fn unregister(&self) -> Result<(), AnyError> {
 		let key = WindowsService::open_key()?;
-		match key.delete_value(TUNNEL_ACTIVITY_NAME) {
+		match key.delete_value(self.value_name()) {
 			Ok(_) => {}
 			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
 			Err(e) => return Err(wrap(e, "error deleting registry key").into()),
 		}
 
-		info!(self.log, "Tunnel service uninstalled");
+		info!(self.log, "{} service uninstalled", self.kind.display_name());
+
+		if self.kind == ServiceKind::ServeWeb {
+			let pid = std::fs::read_to_string(&self.pid_file)
+				.ok()
+				.and_then(|p| p.trim().parse::<u32>().ok());
+			if pid.map(kill_pid).unwrap_or(false) {
+				info!(self.log, "Successfully shut down running web server.");
+			} else {
+				warning!(self.log, "The web server service has been unregistered, but we couldn't find a running server process. You may need to restart or log out and back in to fully stop it.");
+			}
+			return Ok(());
+		}
 
 		let r = do_single_rpc_call::<_, ()>(
 			&self.tunnel_lock,
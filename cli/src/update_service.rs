@@ -3,13 +3,18 @@
  *  Licensed under the MIT License. See License.txt in the project root for license information.
  *--------------------------------------------------------------------------------------------*/
 
-use std::{fmt, path::Path};
+use std::{
+	fmt,
+	path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
 	constants::VSCODE_CLI_UPDATE_ENDPOINT,
-	debug, log, options, spanf,
+	debug,
+	download_cache::DownloadCache,
+	log, options, spanf,
 	util::{
 		errors::{wrap, AnyError, CodeError, WrappedError},
 		http::{BoxedHttp, SimpleResponse},
@@ -186,6 +191,65 @@ where
 	}
 }
 
+/// Subset of a server's product.json that identifies its release.
+#[derive(Deserialize, Clone)]
+pub struct ReleaseProduct {
+	pub commit: String,
+	pub quality: options::Quality,
+}
+
+impl ReleaseProduct {
+	/// Reads the product information of an unpacked server in the directory.
+	pub fn read_from(server_dir: &Path) -> Result<ReleaseProduct, CodeError> {
+		let contents = std::fs::read(server_dir.join("product.json"))
+			.map_err(|e| CodeError::InvalidServerArchive(e.to_string()))?;
+		serde_json::from_slice(&contents)
+			.map_err(|e| CodeError::InvalidServerArchive(e.to_string()))
+	}
+}
+
+/// Imports a server archive from disk into the cache, which allows servers to
+/// be installed on machines without access to the update service. The commit
+/// and quality are read from the archive's product.json, and `cache_name`
+/// gives the name of the cache entry to create for it. The archive is unpacked
+/// into `server_subdir` of the entry, if given. Returns the product information
+/// and the path of the cache entry.
+pub async fn import_release_archive<T>(
+	archive: &Path,
+	cache: &DownloadCache,
+	server_subdir: Option<&str>,
+	cache_name: impl FnOnce(&ReleaseProduct) -> String,
+	reporter: T,
+) -> Result<(ReleaseProduct, PathBuf), AnyError>
+where
+	T: ReportCopyProgress,
+{
+	// Unpack next to the cache so the result can be moved into place without copying.
+	std::fs::create_dir_all(cache.path()).map_err(|e| wrap(e, "error creating cache dir"))?;
+	let unpacked = tempfile::tempdir_in(cache.path())
+		.map_err(|e| wrap(e, "error creating temp import dir"))?;
+	unzip_downloaded_release(archive, unpacked.path(), reporter)?;
+
+	let product = ReleaseProduct::read_from(unpacked.path())?;
+	let source_dir = unpacked.path().to_owned();
+	let dir = cache
+		.create(cache_name(&product), |target_dir| async move {
+			let dest = match server_subdir {
+				Some(s) => target_dir.join(s),
+				None => {
+					std::fs::remove_dir(&target_dir)
+						.map_err(|e| wrap(e, "error preparing server directory"))?;
+					target_dir
+				}
+			};
+			std::fs::rename(source_dir, dest)
+				.map_err(|e| wrap(e, "error moving imported server").into())
+		})
+		.await?;
+
+	Ok((product, dir))
+}
+
 #[derive(Eq, PartialEq, Copy, Clone)]
 pub enum TargetKind {
 	Server,
@@ -332,3 +396,95 @@ impl fmt::Display for Platform {
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+
+	use super::*;
+	use crate::util::io::SilentCopyProgress;
+
+	const COMMIT: &str = "0123456789abcdef0123456789abcdef01234567";
+
+	/// Writes a gzipped server archive with the files nested in a top-level
+	/// folder, like the archives from the update service.
+	fn write_archive(path: &Path, files: &[(&str, &str)]) {
+		let gz = flate2::write::GzEncoder::new(
+			std::fs::File::create(path).unwrap(),
+			flate2::Compression::fast(),
+		);
+		let mut tar = ::tar::Builder::new(gz);
+		for (name, contents) in files {
+			let mut header = ::tar::Header::new_gnu();
+			header.set_size(contents.len() as u64);
+			header.set_mode(0o644);
+			header.set_cksum();
+			tar.append_data(
+				&mut header,
+				format!("vscode-server/{}", name),
+				contents.as_bytes(),
+			)
+			.unwrap();
+		}
+		tar.into_inner().unwrap().finish().unwrap().flush().unwrap();
+	}
+
+	#[tokio::test]
+	async fn test_import_release_archive() {
+		let dir = tempfile::tempdir().unwrap();
+		let archive = dir.path().join("server.tar.gz");
+		write_archive(
+			&archive,
+			&[
+				(
+					"product.json",
+					&format!(r#"{{"commit":"{}","quality":"stable"}}"#, COMMIT),
+				),
+				("bin/code-server", "#!/bin/sh"),
+			],
+		);
+
+		let cache = DownloadCache::new(dir.path().join("cache"));
+		let (product, server_dir) = import_release_archive(
+			&archive,
+			&cache,
+			Some("server"),
+			|p| format!("stable-{}", p.commit),
+			SilentCopyProgress(),
+		)
+		.await
+		.unwrap();
+
+		assert_eq!(product.commit, COMMIT);
+		assert_eq!(product.quality, options::Quality::Stable);
+		assert_eq!(server_dir, cache.path().join(format!("stable-{}", COMMIT)));
+		assert!(server_dir.join("server/bin/code-server").exists());
+		assert_eq!(cache.get_lru(), vec![format!("stable-{}", COMMIT)]);
+
+		let product = ReleaseProduct::read_from(&server_dir.join("server")).unwrap();
+		assert_eq!(product.commit, COMMIT);
+	}
+
+	#[tokio::test]
+	async fn test_import_release_archive_without_product() {
+		let dir = tempfile::tempdir().unwrap();
+		let archive = dir.path().join("server.tar.gz");
+		write_archive(
+			&archive,
+			&[("bin/code-server", "#!/bin/sh"), ("README", "")],
+		);
+
+		let cache = DownloadCache::new(dir.path().join("cache"));
+		let result = import_release_archive(
+			&archive,
+			&cache,
+			None,
+			|p| p.commit.clone(),
+			SilentCopyProgress(),
+		)
+		.await;
+
+		assert!(result.is_err());
+		assert!(cache.get_lru().is_empty());
+	}
+}
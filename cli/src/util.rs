@@ -5,13 +5,16 @@
 
 mod is_integrated;
 
+pub mod auth_limit;
 pub mod command;
 pub mod errors;
 pub mod http;
 pub mod input;
 pub mod io;
 pub mod machine;
+pub mod metrics;
 pub mod prereqs;
+pub mod pty;
 pub mod ring_buffer;
 pub mod sync;
 pub use is_integrated::*;
@@ -0,0 +1,161 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of failed attempts an address gets before it's locked out.
+const FREE_ATTEMPTS: u32 = 5;
+/// Lockout after the first attempt past `FREE_ATTEMPTS`, doubling with each
+/// further failure.
+const BASE_LOCKOUT: Duration = Duration::from_secs(1);
+/// Longest an address is locked out for.
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// Failures are forgotten once an address hasn't failed for this long.
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+
+struct FailureState {
+	failures: u32,
+	last_failure: Instant,
+	locked_until: Option<Instant>,
+}
+
+/// Limits failed authentication attempts, such as bad connection tokens or
+/// challenge responses, per remote address. After a few failures, an address
+/// is locked out for exponentially increasing periods of time. Connections
+/// without an address, such as over local sockets, aren't limited: they
+/// usually all come from a reverse proxy, and a shared limit would let one
+/// client lock out everyone else.
+#[derive(Default)]
+pub struct AuthLimiter {
+	addresses: Mutex<HashMap<IpAddr, FailureState>>,
+	total_failures: AtomicU64,
+}
+
+impl AuthLimiter {
+	/// Gets the remaining lockout time for the address, if it's locked out.
+	pub fn locked_out_for(&self, addr: Option<IpAddr>) -> Option<Duration> {
+		self.locked_out_for_at(addr, Instant::now())
+	}
+
+	/// Records a failed attempt from the address, returning the time it's now
+	/// locked out for, if any.
+	pub fn record_failure(&self, addr: Option<IpAddr>) -> Option<Duration> {
+		self.record_failure_at(addr, Instant::now())
+	}
+
+	/// Records a successful attempt, resetting the address' failures.
+	pub fn record_success(&self, addr: Option<IpAddr>) {
+		let mut addresses = self.addresses.lock().unwrap();
+		if let Some(addr) = addr {
+			addresses.remove(&addr);
+		}
+	}
+
+	/// Gets the number of failed attempts since the process started.
+	pub fn total_failures(&self) -> u64 {
+		self.total_failures.load(Ordering::Relaxed)
+	}
+
+	fn locked_out_for_at(&self, addr: Option<IpAddr>, now: Instant) -> Option<Duration> {
+		let addr = addr?;
+		self.addresses
+			.lock()
+			.unwrap()
+			.get(&addr)
+			.and_then(|s| s.locked_until)
+			.filter(|until| *until > now)
+			.map(|until| until - now)
+	}
+
+	fn record_failure_at(&self, addr: Option<IpAddr>, now: Instant) -> Option<Duration> {
+		self.total_failures.fetch_add(1, Ordering::Relaxed);
+		let addr = addr?;
+
+		let mut addresses = self.addresses.lock().unwrap();
+		addresses.retain(|_, s| now.duration_since(s.last_failure) < FORGET_AFTER);
+
+		let state = addresses.entry(addr).or_insert(FailureState {
+			failures: 0,
+			last_failure: now,
+			locked_until: None,
+		});
+		state.failures += 1;
+		state.last_failure = now;
+
+		if state.failures < FREE_ATTEMPTS {
+			return None;
+		}
+
+		let exponent = (state.failures - FREE_ATTEMPTS).min(16);
+		let lockout = BASE_LOCKOUT.saturating_mul(1 << exponent).min(MAX_LOCKOUT);
+		state.locked_until = Some(now + lockout);
+		Some(lockout)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_lockout_backoff() {
+		let limiter = AuthLimiter::default();
+		let addr = Some("10.0.0.1".parse().unwrap());
+		let other = Some("10.0.0.2".parse().unwrap());
+		let now = Instant::now();
+
+		for _ in 1..FREE_ATTEMPTS {
+			assert_eq!(limiter.record_failure_at(addr, now), None);
+		}
+		assert_eq!(limiter.locked_out_for_at(addr, now), None);
+
+		assert_eq!(limiter.record_failure_at(addr, now), Some(BASE_LOCKOUT));
+		assert_eq!(limiter.record_failure_at(addr, now), Some(BASE_LOCKOUT * 2));
+		assert_eq!(limiter.record_failure_at(addr, now), Some(BASE_LOCKOUT * 4));
+		assert_eq!(limiter.locked_out_for_at(addr, now), Some(BASE_LOCKOUT * 4));
+		assert_eq!(
+			limiter.locked_out_for_at(addr, now + BASE_LOCKOUT * 4),
+			None
+		);
+		assert_eq!(limiter.locked_out_for_at(other, now), None);
+
+		for _ in 0..100 {
+			limiter.record_failure_at(addr, now);
+		}
+		assert_eq!(limiter.locked_out_for_at(addr, now), Some(MAX_LOCKOUT));
+		assert_eq!(limiter.total_failures(), FREE_ATTEMPTS as u64 + 102);
+
+		limiter.record_success(addr);
+		assert_eq!(limiter.locked_out_for_at(addr, now), None);
+	}
+
+	#[test]
+	fn test_does_not_limit_unknown_addresses() {
+		let limiter = AuthLimiter::default();
+		let now = Instant::now();
+
+		for _ in 0..FREE_ATTEMPTS * 2 {
+			assert_eq!(limiter.record_failure_at(None, now), None);
+		}
+		assert_eq!(limiter.locked_out_for_at(None, now), None);
+		assert_eq!(limiter.total_failures(), FREE_ATTEMPTS as u64 * 2);
+	}
+
+	#[test]
+	fn test_forgets_old_failures() {
+		let limiter = AuthLimiter::default();
+		let addr = Some("10.0.0.1".parse().unwrap());
+		let now = Instant::now();
+
+		for _ in 0..FREE_ATTEMPTS - 1 {
+			limiter.record_failure_at(addr, now);
+		}
+		assert_eq!(limiter.record_failure_at(addr, now + FORGET_AFTER), None);
+	}
+}
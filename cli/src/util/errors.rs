@@ -475,6 +475,12 @@ pub enum CodeError {
 	PrerequisitesFailed { name: &'static str, bullets: String },
 	#[error("failed to spawn process: {0:?}")]
 	ProcessSpawnFailed(std::io::Error),
+	#[error("no running process with ID {0}")]
+	SpawnedProcessNotFound(String),
+	#[error("process {0} was not started in a pseudo-terminal")]
+	SpawnedProcessWithoutPty(String),
+	#[error("invalid process ID {0}")]
+	InvalidProcessId(u32),
 	#[error("failed to handshake spawned process: {0:?}")]
 	ProcessSpawnHandshakeFailed(std::io::Error),
 	#[error("download appears corrupted, please retry ({0})")]
@@ -487,6 +493,8 @@ pub enum CodeError {
 	AuthChallengeNotIssued,
 	#[error("challenge token is invalid")]
 	AuthChallengeBadToken,
+	#[error("too many failed authentication attempts, try again in {0}s")]
+	AuthLockedOut(u64),
 	#[error("unauthorized client refused")]
 	AuthMismatch,
 	#[error("keyring communication timed out after 5s")]
@@ -497,6 +505,26 @@ pub enum CodeError {
 	InvalidHostAddress(std::net::AddrParseError),
 	#[error("could not start server on the given host/port: {0}")]
 	CouldNotListenOnInterface(hyper::Error),
+	#[error("could not listen on {0}: {1}")]
+	CouldNotListenOnAddress(String, std::io::Error),
+	#[error("could not use sockets passed by the service manager: {0}")]
+	InvalidSocketActivation(String),
+	#[error("could not open access log: {0}")]
+	CouldNotOpenAccessLog(std::io::Error),
+	#[error("invalid auth config: {0}")]
+	InvalidAuthConfig(String),
+	#[error("invalid server config: {0}")]
+	InvalidServerConfig(String),
+	#[error("invalid response headers config: {0}")]
+	InvalidHeadersConfig(String),
+	#[error("server {0} crashed repeatedly and will not be started again for a while")]
+	ServerCrashedRepeatedly(String),
+	#[error("no OIDC provider is configured")]
+	OidcNotConfigured,
+	#[error("OIDC login failed: {0}")]
+	OidcLoginFailed(String),
+	#[error("the serve-web service is not installed, run `serve-web service install` first")]
+	ServeWebServiceNotInstalled,
 	#[error(
 		"Run this command again with --accept-server-license-terms to indicate your agreement."
 	)]
@@ -512,10 +540,30 @@ pub enum CodeError {
 	// todo: can be specialized when update service is moved to CodeErrors
 	#[error("Could not check for update: {0}")]
 	UpdateCheckFailed(String),
+	#[error("'{0}' is not a valid commit hash")]
+	InvalidCommitHash(String),
+	#[error("Could not read product.json of the server: {0}")]
+	InvalidServerArchive(String),
+	#[error("The server for commit {0} is not cached, and cannot be downloaded in offline mode")]
+	ServerNotCachedOffline(String),
+	#[error(
+		"No cached server is available to use in offline mode, import one with --server-archive"
+	)]
+	NoCachedServerOffline,
 	#[error("Could not read connection token file: {0}")]
 	CouldNotReadConnectionTokenFile(std::io::Error),
 	#[error("Could not write connection token file: {0}")]
 	CouldNotCreateConnectionTokenFile(std::io::Error),
+	#[error("Could not read TLS certificate or key file: {0}")]
+	CouldNotReadTlsCertificate(std::io::Error),
+	#[error("Invalid TLS certificate or key: {0}")]
+	InvalidTlsCertificate(native_tls::Error),
+	#[error("Could not create a self-signed certificate: {0}")]
+	CouldNotCreateTlsCertificate(String),
+	#[error("Unsupported TLS private key: {0}")]
+	UnsupportedTlsKey(String),
+	#[error("--user-header can't be used when listening on {0} without TLS or a connection token, since anyone could send the header to act as any user")]
+	UnsafeUserHeader(String),
 	#[error("A tunnel with the name {0} exists and is in-use. Please pick a different name or stop the existing tunnel.")]
 	TunnelActiveAndInUse(String),
 	#[error("Timed out looking for port/socket")]
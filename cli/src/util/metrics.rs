@@ -0,0 +1,354 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use lazy_static::lazy_static;
+
+use crate::log;
+use crate::util::errors::CodeError;
+
+/// Path metrics are served on.
+pub const METRICS_PATH: &str = "/metrics";
+/// Prefix added to the name of all metrics.
+const METRIC_PREFIX: &str = "vscode_cli_";
+/// Upper bounds, in seconds, of the buckets of duration histograms.
+const DURATION_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0];
+
+lazy_static! {
+	/// Metrics recorded by the CLI process.
+	pub static ref METRICS: Metrics = Metrics::default();
+}
+
+/// Metrics recorded by serve-web and the tunnel control server, which can be
+/// rendered in the Prometheus text format with `Metrics::render`.
+pub struct Metrics {
+	pub http_requests: Counter,
+	pub http_request_duration: Histogram,
+	pub websockets_active: Gauge,
+	pub downloads_started: Counter,
+	pub downloads_finished: Counter,
+	pub downloads_failed: Counter,
+	pub process_spawns: Counter,
+	pub process_exits: Counter,
+	pub process_crashes: Counter,
+	pub sockets_served: Counter,
+	pub sockets_active: Gauge,
+	pub socket_tx_bytes: Counter,
+	pub socket_rx_bytes: Counter,
+	pub auth_failures: Counter,
+}
+
+impl Default for Metrics {
+	fn default() -> Self {
+		Self {
+			http_requests: Counter::new(
+				"http_requests_total",
+				"HTTP requests handled, by status code",
+			),
+			http_request_duration: Histogram::new(
+				"http_request_duration_seconds",
+				"Time taken to respond to HTTP requests",
+			),
+			websockets_active: Gauge::new(
+				"websockets_active",
+				"Currently open WebSocket connections",
+			),
+			downloads_started: Counter::new(
+				"server_downloads_started_total",
+				"Server downloads started",
+			),
+			downloads_finished: Counter::new(
+				"server_downloads_finished_total",
+				"Server downloads finished successfully",
+			),
+			downloads_failed: Counter::new(
+				"server_downloads_failed_total",
+				"Server downloads that failed",
+			),
+			process_spawns: Counter::new(
+				"process_spawns_total",
+				"Child processes spawned, by kind",
+			),
+			process_exits: Counter::new("process_exits_total", "Child processes exited, by kind"),
+			process_crashes: Counter::new(
+				"process_crashes_total",
+				"Child processes that exited unexpectedly, by kind",
+			),
+			sockets_served: Counter::new("sockets_served_total", "Control server sockets served"),
+			sockets_active: Gauge::new("sockets_active", "Currently open control server sockets"),
+			socket_tx_bytes: Counter::new(
+				"socket_tx_bytes_total",
+				"Bytes sent on control server sockets, counted as they're sent",
+			),
+			socket_rx_bytes: Counter::new(
+				"socket_rx_bytes_total",
+				"Bytes received on control server sockets, counted as they're received",
+			),
+			auth_failures: Counter::new(
+				"auth_failures_total",
+				"Failed authentication attempts, by kind",
+			),
+		}
+	}
+}
+
+impl Metrics {
+	/// Renders all metrics in the Prometheus text exposition format.
+	pub fn render(&self) -> String {
+		let mut out = String::new();
+		self.http_requests.render(&mut out);
+		self.http_request_duration.render(&mut out);
+		self.websockets_active.render(&mut out);
+		self.downloads_started.render(&mut out);
+		self.downloads_finished.render(&mut out);
+		self.downloads_failed.render(&mut out);
+		self.process_spawns.render(&mut out);
+		self.process_exits.render(&mut out);
+		self.process_crashes.render(&mut out);
+		self.sockets_served.render(&mut out);
+		self.sockets_active.render(&mut out);
+		self.socket_tx_bytes.render(&mut out);
+		self.socket_rx_bytes.render(&mut out);
+		self.auth_failures.render(&mut out);
+		out
+	}
+}
+
+/// Creates a response with the rendered metrics.
+pub fn metrics_response() -> Response<Body> {
+	Response::builder()
+		.status(200)
+		.header("Content-Type", "text/plain; version=0.0.4")
+		.body(Body::from(METRICS.render()))
+		.unwrap()
+}
+
+/// Starts a server in the background that exposes metrics at `METRICS_PATH`
+/// on the given address.
+pub fn spawn_metrics_server(log: log::Logger, addr: SocketAddr) -> Result<(), CodeError> {
+	let builder = Server::try_bind(&addr).map_err(CodeError::CouldNotListenOnInterface)?;
+	let server = builder.serve(make_service_fn(|_| async {
+		Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+			Ok::<_, Infallible>(if req.uri().path() == METRICS_PATH {
+				metrics_response()
+			} else {
+				Response::builder().status(404).body(Body::empty()).unwrap()
+			})
+		}))
+	}));
+
+	info!(log, "Serving metrics at http://{}{}", addr, METRICS_PATH);
+	tokio::spawn(async move {
+		if let Err(e) = server.await {
+			warning!(log, "metrics server exited: {}", e);
+		}
+	});
+
+	Ok(())
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+	writeln!(out, "# HELP {}{} {}", METRIC_PREFIX, name, help).unwrap();
+	writeln!(out, "# TYPE {}{} {}", METRIC_PREFIX, name, kind).unwrap();
+}
+
+/// Formats labels as they appear in the text format, e.g. `{kind="server"}`
+fn format_labels(labels: &[(&str, &str)]) -> String {
+	if labels.is_empty() {
+		return String::new();
+	}
+
+	let pairs: Vec<String> = labels
+		.iter()
+		.map(|(k, v)| {
+			let v = v
+				.replace('\\', "\\\\")
+				.replace('"', "\\\"")
+				.replace('\n', "\\n");
+			format!("{}=\"{}\"", k, v)
+		})
+		.collect();
+	format!("{{{}}}", pairs.join(","))
+}
+
+/// Monotonically increasing count, optionally split by labels.
+pub struct Counter {
+	name: &'static str,
+	help: &'static str,
+	values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Counter {
+	pub fn new(name: &'static str, help: &'static str) -> Self {
+		Self {
+			name,
+			help,
+			values: Mutex::default(),
+		}
+	}
+
+	pub fn inc(&self) {
+		self.inc_by(&[], 1);
+	}
+
+	pub fn inc_by(&self, labels: &[(&str, &str)], n: u64) {
+		*self
+			.values
+			.lock()
+			.unwrap()
+			.entry(format_labels(labels))
+			.or_default() += n;
+	}
+
+	fn render(&self, out: &mut String) {
+		write_header(out, self.name, self.help, "counter");
+		let values = self.values.lock().unwrap();
+		if values.is_empty() {
+			writeln!(out, "{}{} 0", METRIC_PREFIX, self.name).unwrap();
+		}
+		for (labels, v) in values.iter() {
+			writeln!(out, "{}{}{} {}", METRIC_PREFIX, self.name, labels, v).unwrap();
+		}
+	}
+}
+
+/// Value that can go up and down.
+pub struct Gauge {
+	name: &'static str,
+	help: &'static str,
+	value: AtomicI64,
+}
+
+impl Gauge {
+	pub fn new(name: &'static str, help: &'static str) -> Self {
+		Self {
+			name,
+			help,
+			value: AtomicI64::new(0),
+		}
+	}
+
+	pub fn inc(&self) {
+		self.value.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn dec(&self) {
+		self.value.fetch_sub(1, Ordering::Relaxed);
+	}
+
+	fn render(&self, out: &mut String) {
+		write_header(out, self.name, self.help, "gauge");
+		writeln!(
+			out,
+			"{}{} {}",
+			METRIC_PREFIX,
+			self.name,
+			self.value.load(Ordering::Relaxed)
+		)
+		.unwrap();
+	}
+}
+
+#[derive(Default)]
+struct HistogramState {
+	buckets: [u64; DURATION_BUCKETS.len()],
+	sum: f64,
+	count: u64,
+}
+
+/// Distribution of durations, recorded in seconds.
+pub struct Histogram {
+	name: &'static str,
+	help: &'static str,
+	state: Mutex<HistogramState>,
+}
+
+impl Histogram {
+	pub fn new(name: &'static str, help: &'static str) -> Self {
+		Self {
+			name,
+			help,
+			state: Mutex::default(),
+		}
+	}
+
+	pub fn observe(&self, duration: Duration) {
+		let secs = duration.as_secs_f64();
+		let mut state = self.state.lock().unwrap();
+		for (bucket, le) in state.buckets.iter_mut().zip(DURATION_BUCKETS) {
+			if secs <= le {
+				*bucket += 1;
+			}
+		}
+		state.sum += secs;
+		state.count += 1;
+	}
+
+	fn render(&self, out: &mut String) {
+		write_header(out, self.name, self.help, "histogram");
+		let state = self.state.lock().unwrap();
+		for (count, le) in state.buckets.iter().zip(DURATION_BUCKETS) {
+			writeln!(
+				out,
+				"{}{}_bucket{{le=\"{}\"}} {}",
+				METRIC_PREFIX, self.name, le, count
+			)
+			.unwrap();
+		}
+		writeln!(
+			out,
+			"{}{}_bucket{{le=\"+Inf\"}} {}",
+			METRIC_PREFIX, self.name, state.count
+		)
+		.unwrap();
+		writeln!(out, "{}{}_sum {}", METRIC_PREFIX, self.name, state.sum).unwrap();
+		writeln!(out, "{}{}_count {}", METRIC_PREFIX, self.name, state.count).unwrap();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_counter_render() {
+		let c = Counter::new("things_total", "Things");
+		let mut out = String::new();
+		c.render(&mut out);
+		assert!(out.ends_with("vscode_cli_things_total 0\n"));
+
+		c.inc_by(&[("kind", "a\"b")], 2);
+		c.inc_by(&[("kind", "a\"b")], 1);
+		let mut out = String::new();
+		c.render(&mut out);
+		assert_eq!(
+			out,
+			"# HELP vscode_cli_things_total Things\n# TYPE vscode_cli_things_total counter\nvscode_cli_things_total{kind=\"a\\\"b\"} 3\n"
+		);
+	}
+
+	#[test]
+	fn test_histogram_buckets() {
+		let h = Histogram::new("latency_seconds", "Latency");
+		h.observe(Duration::from_millis(20));
+		h.observe(Duration::from_secs(60));
+		let mut out = String::new();
+		h.render(&mut out);
+		assert!(out.contains("vscode_cli_latency_seconds_bucket{le=\"0.01\"} 0\n"));
+		assert!(out.contains("vscode_cli_latency_seconds_bucket{le=\"0.025\"} 1\n"));
+		assert!(out.contains("vscode_cli_latency_seconds_bucket{le=\"30\"} 1\n"));
+		assert!(out.contains("vscode_cli_latency_seconds_bucket{le=\"+Inf\"} 2\n"));
+		assert!(out.contains("vscode_cli_latency_seconds_count 2\n"));
+	}
+}
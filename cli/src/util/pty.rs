@@ -0,0 +1,273 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Microsoft Corporation. All rights reserved.
+ *  Licensed under the MIT License. See License.txt in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use std::io;
+#[cfg(unix)]
+use std::pin::Pin;
+#[cfg(unix)]
+use std::task::{ready, Context, Poll};
+
+#[cfg(unix)]
+use tokio::io::unix::AsyncFd;
+#[cfg(unix)]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::process::Command;
+
+/// A pseudo-terminal that a spawned process runs in.
+#[cfg(unix)]
+pub struct Pty {
+	master: std::os::fd::OwnedFd,
+	/// Side of the terminal given to the process, until it's attached
+	slave: Option<std::os::fd::OwnedFd>,
+}
+
+#[cfg(unix)]
+impl Pty {
+	/// Opens a pseudo-terminal with the given size, in characters.
+	pub fn open(cols: u16, rows: u16) -> io::Result<Pty> {
+		use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+		let mut master = -1;
+		let mut slave = -1;
+		let size = winsize(cols, rows);
+		if unsafe {
+			libc::openpty(
+				&mut master,
+				&mut slave,
+				std::ptr::null_mut(),
+				std::ptr::null_mut(),
+				&size as *const _ as *mut _,
+			)
+		} != 0
+		{
+			return Err(io::Error::last_os_error());
+		}
+
+		let (master, slave) =
+			unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+		// neither should be inherited, the process gets copies of the slave as its stdio
+		for fd in [&master, &slave] {
+			if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+				return Err(io::Error::last_os_error());
+			}
+		}
+		// the master is polled by the runtime rather than read on blocking threads
+		let flags = unsafe { libc::fcntl(master.as_raw_fd(), libc::F_GETFL) };
+		if flags < 0
+			|| unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) }
+				!= 0
+		{
+			return Err(io::Error::last_os_error());
+		}
+
+		Ok(Pty {
+			master,
+			slave: Some(slave),
+		})
+	}
+
+	/// Makes the terminal the stdio and controlling terminal of the command,
+	/// which is started in a new session and process group. The command must
+	/// be dropped once the process is spawned, so that the terminal is closed
+	/// when the process exits.
+	pub fn attach(&mut self, cmd: &mut Command) -> io::Result<()> {
+		use std::process::Stdio;
+
+		let slave = self.slave.take().ok_or_else(|| {
+			io::Error::new(io::ErrorKind::AlreadyExists, "terminal already attached")
+		})?;
+		cmd.stdin(Stdio::from(slave.try_clone()?));
+		cmd.stdout(Stdio::from(slave.try_clone()?));
+		cmd.stderr(Stdio::from(slave));
+
+		unsafe {
+			cmd.pre_exec(|| {
+				if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+					return Err(io::Error::last_os_error());
+				}
+				Ok(())
+			});
+		}
+
+		Ok(())
+	}
+
+	/// Resizes the terminal, which also signals the process with SIGWINCH.
+	pub fn resize(&self, cols: u16, rows: u16) -> io::Result<()> {
+		use std::os::fd::AsRawFd;
+
+		let size = winsize(cols, rows);
+		if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } != 0 {
+			return Err(io::Error::last_os_error());
+		}
+
+		Ok(())
+	}
+
+	/// Gets streams to read the process's output from and write its input to.
+	/// Reading fails once the process exits and the terminal is closed.
+	pub fn io(&self) -> io::Result<(PtyStream, PtyStream)> {
+		Ok((
+			PtyStream(AsyncFd::new(self.master.try_clone()?)?),
+			PtyStream(AsyncFd::new(self.master.try_clone()?)?),
+		))
+	}
+}
+
+/// Master side of a pseudo-terminal, which reads the output of its process
+/// and writes its input.
+#[cfg(unix)]
+pub struct PtyStream(AsyncFd<std::os::fd::OwnedFd>);
+
+#[cfg(unix)]
+impl AsyncRead for PtyStream {
+	fn poll_read(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		use std::os::fd::AsRawFd;
+
+		loop {
+			let mut guard = ready!(self.0.poll_read_ready(cx))?;
+			let unfilled = buf.initialize_unfilled();
+			match guard.try_io(|fd| {
+				let n = unsafe {
+					libc::read(
+						fd.as_raw_fd(),
+						unfilled.as_mut_ptr() as *mut libc::c_void,
+						unfilled.len(),
+					)
+				};
+				if n < 0 {
+					Err(io::Error::last_os_error())
+				} else {
+					Ok(n as usize)
+				}
+			}) {
+				Ok(Ok(n)) => {
+					buf.advance(n);
+					return Poll::Ready(Ok(()));
+				}
+				Ok(Err(e)) => return Poll::Ready(Err(e)),
+				Err(_would_block) => continue,
+			}
+		}
+	}
+}
+
+#[cfg(unix)]
+impl AsyncWrite for PtyStream {
+	fn poll_write(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		use std::os::fd::AsRawFd;
+
+		loop {
+			let mut guard = ready!(self.0.poll_write_ready(cx))?;
+			match guard.try_io(|fd| {
+				let n = unsafe {
+					libc::write(
+						fd.as_raw_fd(),
+						buf.as_ptr() as *const libc::c_void,
+						buf.len(),
+					)
+				};
+				if n < 0 {
+					Err(io::Error::last_os_error())
+				} else {
+					Ok(n as usize)
+				}
+			}) {
+				Ok(r) => return Poll::Ready(r),
+				Err(_would_block) => continue,
+			}
+		}
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+#[cfg(unix)]
+fn winsize(cols: u16, rows: u16) -> libc::winsize {
+	libc::winsize {
+		ws_row: rows,
+		ws_col: cols,
+		ws_xpixel: 0,
+		ws_ypixel: 0,
+	}
+}
+
+/// Pseudo-terminals are not yet supported on this platform.
+#[cfg(not(unix))]
+pub struct Pty;
+
+#[cfg(not(unix))]
+pub type PtyStream = tokio::fs::File;
+
+#[cfg(not(unix))]
+impl Pty {
+	pub fn open(_cols: u16, _rows: u16) -> io::Result<Pty> {
+		Err(unsupported())
+	}
+
+	pub fn attach(&mut self, _cmd: &mut Command) -> io::Result<()> {
+		Err(unsupported())
+	}
+
+	pub fn resize(&self, _cols: u16, _rows: u16) -> io::Result<()> {
+		Err(unsupported())
+	}
+
+	pub fn io(&self) -> io::Result<(PtyStream, PtyStream)> {
+		Err(unsupported())
+	}
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+	io::Error::new(
+		io::ErrorKind::Unsupported,
+		"pseudo-terminals are not supported on this platform",
+	)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_runs_in_terminal() {
+		let mut pty = Pty::open(80, 24).unwrap();
+		let mut cmd = Command::new("sh");
+		cmd.args(["-c", "read line; stty size; echo \"got $line\""]);
+		pty.attach(&mut cmd).unwrap();
+		let mut child = cmd.spawn().unwrap();
+		drop(cmd);
+
+		pty.resize(100, 30).unwrap();
+		let (mut output, mut input) = pty.io().unwrap();
+		input.write_all(b"hello\n").await.unwrap();
+		input.flush().await.unwrap();
+
+		let mut out = Vec::new();
+		// reading fails with EIO once the terminal is closed
+		let _ = output.read_to_end(&mut out).await;
+		let out = String::from_utf8_lossy(&out);
+		assert!(out.contains("30 100"), "unexpected output: {}", out);
+		assert!(out.contains("got hello"), "unexpected output: {}", out);
+		assert!(child.wait().await.unwrap().success());
+	}
+}
@@ -33,6 +33,11 @@ where
 	pub fn is_open(&self) -> bool {
 		self.0.borrow().is_some()
 	}
+
+	/// Gets the value the barrier was opened with, if it's open.
+	pub fn peek(&self) -> Option<T> {
+		self.0.borrow().clone()
+	}
 }
 
 #[async_trait]
@@ -218,4 +223,15 @@ mod tests {
 		assert!(rx1.await.unwrap() == 42);
 		assert!(rx2.await.unwrap() == 42);
 	}
+
+	#[test]
+	fn test_barrier_peek() {
+		let (barrier, opener) = new_barrier::<u32>();
+		assert!(!barrier.is_open());
+		assert_eq!(barrier.peek(), None);
+
+		opener.open(42);
+		assert!(barrier.is_open());
+		assert_eq!(barrier.peek(), Some(42));
+	}
 }